@@ -1,3 +1,84 @@
-fn main() {
-    println!("Hello, world!");
+use std::path::Path;
+
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+use shared::{AppConfig, AppInfo, AppState, StateBackup, StateLayout};
+
+mod shared;
+
+const DEFAULT_CONFIG_PATH: &str = "axus-config.toml";
+// 値を取るオプション
+const VALUE_FLAGS: &[&str] = &["--backup", "--restore"];
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config_path = args
+        .iter()
+        .enumerate()
+        .find(|(i, n)| !n.starts_with("--") && (*i == 0 || !VALUE_FLAGS.contains(&args[i - 1].as_str())))
+        .map(|(_, n)| n.clone())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+
+    if args.iter().any(|n| n == "--version") {
+        println!("{}", AppInfo::current());
+        return Ok(());
+    }
+
+    if args.iter().any(|n| n == "--init-config") {
+        if Path::new(&config_path).exists() {
+            anyhow::bail!("config file already exists: {}", config_path);
+        }
+        std::fs::write(&config_path, AppConfig::default().to_commented_toml()?)?;
+        info!(config_path = config_path.as_str(), "config written");
+        return Ok(());
+    }
+
+    // デーモンを停止した状態で実行する
+    if let Some(archive_path) = flag_value(&args, "--backup") {
+        let config = AppConfig::load(&config_path)?;
+        StateBackup::create(&config, Path::new(&config_path), Path::new(&archive_path)).await?;
+        return Ok(());
+    }
+
+    // 設定ファイルの state_dir_path が指す、空のディレクトリへ復元する
+    if let Some(archive_path) = flag_value(&args, "--restore") {
+        let config = AppConfig::load(&config_path)?;
+        StateBackup::restore(Path::new(&archive_path), Path::new(&config.state_dir_path))?;
+        return Ok(());
+    }
+
+    let app_info = AppInfo::current();
+    info!(%app_info, "starting");
+
+    let config = AppConfig::load(&config_path)?;
+    info!(config_path = config_path.as_str(), ?config, "config loaded");
+
+    StateLayout::new(&config).migrate(&config.state_dir_path)?;
+
+    if let Some(update_check_url) = config.update_check_url.clone() {
+        tokio::spawn(async move {
+            match app_info.check_for_update(&update_check_url).await {
+                Ok(Some(version)) => info!(current = app_info.version, latest = version.as_str(), "newer release available"),
+                Ok(None) => {}
+                Err(e) => warn!(error_message = format!("{:#}", e), "update check failed"),
+            }
+        });
+    }
+
+    let state = AppState::new(config).await?;
+
+    tokio::signal::ctrl_c().await?;
+    info!("shutting down");
+
+    state.terminate().await?;
+
+    Ok(())
+}
+
+fn flag_value(args: &[String], name: &str) -> Option<String> {
+    args.iter().position(|n| n == name).and_then(|i| args.get(i + 1)).cloned()
 }
@@ -0,0 +1,15 @@
+mod app_info;
+mod backup;
+mod config;
+mod config_upgrade;
+mod config_writer;
+mod layout;
+mod state;
+
+pub use app_info::*;
+pub use backup::*;
+pub use config::*;
+pub use config_upgrade::*;
+pub use config_writer::*;
+pub use layout::*;
+pub use state::*;
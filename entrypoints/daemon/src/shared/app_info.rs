@@ -0,0 +1,90 @@
+use std::{fmt, time::Duration};
+
+use serde::Deserialize;
+
+use omnius_axus_engine::service::engine::NODE_FINDER_PROTOCOL_VERSION;
+
+// git hash とビルド日時は、リリース時のビルド環境から環境変数で渡される
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppInfo {
+    pub version: &'static str,
+    pub git_hash: Option<&'static str>,
+    pub build_date: Option<&'static str>,
+    pub node_finder_protocol_version: u32,
+}
+
+impl AppInfo {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("AXUS_GIT_HASH"),
+            build_date: option_env!("AXUS_BUILD_DATE"),
+            node_finder_protocol_version: NODE_FINDER_PROTOCOL_VERSION,
+        }
+    }
+
+    // update_check_url から最新のリリースを取得し、新しいものがあればそのバージョンを返す
+    // 応答は {"version": "x.y.z"} の形式とする。更新自体は行わない
+    pub async fn check_for_update(&self, update_check_url: &str) -> anyhow::Result<Option<String>> {
+        #[derive(Deserialize)]
+        struct LatestRelease {
+            version: String,
+        }
+
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(30)).build()?;
+        let latest: LatestRelease = client.get(update_check_url).send().await?.error_for_status()?.json().await?;
+
+        Ok(is_newer_version(self.version, &latest.version)?.then_some(latest.version))
+    }
+}
+
+impl fmt::Display for AppInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "axus-daemon {} (git: {}, built: {}, node_finder protocol: {})",
+            self.version,
+            self.git_hash.unwrap_or("unknown"),
+            self.build_date.unwrap_or("unknown"),
+            self.node_finder_protocol_version
+        )
+    }
+}
+
+// "v1.2.3" のような先頭の v は無視し、数値として比較する
+fn is_newer_version(current: &str, latest: &str) -> anyhow::Result<bool> {
+    fn parse(v: &str) -> anyhow::Result<Vec<u64>> {
+        v.trim().trim_start_matches('v').split('.').map(|n| Ok(n.parse::<u64>()?)).collect()
+    }
+
+    // 桁数の違いは 0 で埋めて比べ、0.1 と 0.1.0 を同じものとみなす
+    let (mut current, mut latest) = (parse(current)?, parse(latest)?);
+    let len = current.len().max(latest.len());
+    current.resize(len, 0);
+    latest.resize(len, 0);
+
+    Ok(latest > current)
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+
+    use super::{is_newer_version, AppInfo};
+
+    #[test]
+    pub fn simple_test() -> TestResult {
+        assert!(is_newer_version("0.1.0", "0.1.1")?);
+        assert!(is_newer_version("0.1.9", "v0.1.10")?);
+        assert!(!is_newer_version("0.1", "0.1.0")?);
+        assert!(!is_newer_version("0.1.0", "0.1")?);
+        assert!(is_newer_version("0.1", "0.1.1")?);
+        assert!(!is_newer_version("0.1.0", "0.1.0")?);
+        assert!(!is_newer_version("1.0.0", "0.9.9")?);
+        assert!(is_newer_version("0.1.0", "latest").is_err());
+
+        assert!(AppInfo::current().to_string().starts_with("axus-daemon "));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use tracing::info;
+
+use omnius_axus_engine::service::{
+    storage::{BlobStorage, BlobStorageOption},
+    util::SqliteBackup,
+};
+
+use super::{gen_backup_path, rewrite_config_file, AppConfig, PathsConfig};
+
+const MAGIC: &[u8] = b"axus-backup\0";
+const VERSION: u32 = 1;
+const CHECKSUM_SIZE: u64 = 32;
+const MAX_ENTRY_PATH_LENGTH: usize = 4096;
+const STAGING_DIR_NAME: &str = "backup.staging";
+const CONFIG_FILE_NAME: &str = "axus-config.toml";
+// VACUUM INTO で写した DB には WAL の内容も含まれるため、付随するファイルは含めない
+const SQLITE_SIDECAR_SUFFIXES: [&str; 3] = ["-wal", "-shm", "-journal"];
+
+// 状態ディレクトリの内部構成を知らなくても、1 つのファイルで退避・復元できるようにする
+// 形式: MAGIC, VERSION, (パス長, パス, データ長, データ)*, 終端 (パス長 0), 以上の SHA-256
+// デーモンを停止した状態で実行すること
+pub struct StateBackup;
+
+impl StateBackup {
+    pub async fn create(config: &AppConfig, config_path: &Path, archive_path: &Path) -> anyhow::Result<usize> {
+        let state_dir_path = Path::new(&config.state_dir_path);
+        let staging_dir_path = state_dir_path.join(STAGING_DIR_NAME);
+        if staging_dir_path.exists() {
+            fs::remove_dir_all(&staging_dir_path)?;
+        }
+        fs::create_dir_all(&staging_dir_path)?;
+
+        let res = match Self::stage(config, &staging_dir_path).await {
+            Ok(()) => Self::write_archive(config, config_path, &staging_dir_path, archive_path),
+            Err(e) => Err(e),
+        };
+        fs::remove_dir_all(&staging_dir_path)?;
+        let count = res?;
+
+        info!(archive_path = %archive_path.display(), count, "backup created");
+        Ok(count)
+    }
+
+    // DB は整合の取れた状態を取り出して、作業用のディレクトリに置く
+    async fn stage(config: &AppConfig, staging_dir_path: &Path) -> anyhow::Result<()> {
+        // チェックポイントは書き込み側として開いたものからしか作れないため、secondary の設定は使わない
+        {
+            let option = BlobStorageOption {
+                secondary_path: None,
+                ..config.blob_storage_option()
+            };
+            let blob_storage = BlobStorage::new(config.storage_dir_path(), option)?;
+            blob_storage.backup_to(staging_dir_path.join("storage"))?;
+        }
+
+        stage_sqlite_files(&config.repo_dir_path(), &staging_dir_path.join("repo")).await?;
+        stage_sqlite_files(&config.stats_dir_path(), &staging_dir_path.join("stats")).await?;
+
+        Ok(())
+    }
+
+    fn write_archive(config: &AppConfig, config_path: &Path, staging_dir_path: &Path, archive_path: &Path) -> anyhow::Result<usize> {
+        let state_dir_path = Path::new(&config.state_dir_path);
+        let mut entries: Vec<(String, PathBuf)> = Vec::new();
+        collect_files(&staging_dir_path.join("storage"), "storage", &mut entries)?;
+        collect_files(&staging_dir_path.join("repo"), "repo", &mut entries)?;
+        collect_files(&state_dir_path.join("node_finder"), "node_finder", &mut entries)?;
+        collect_files(&staging_dir_path.join("stats"), "stats", &mut entries)?;
+        if config.bootstrap_file_path().exists() {
+            entries.push(("bootstrap_node_profiles.txt".to_string(), config.bootstrap_file_path()));
+        }
+        if config_path.exists() {
+            entries.push((CONFIG_FILE_NAME.to_string(), config_path.to_path_buf()));
+        }
+
+        // 書き終えるまでは一時ファイルに書き、途中で失敗しても壊れたアーカイブを残さない
+        let tmp_path = archive_path.with_extension("tmp");
+        let mut writer = HashWriter::new(BufWriter::new(fs::File::create(&tmp_path)?));
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_be_bytes())?;
+        for (name, path) in entries.iter() {
+            let mut file = fs::File::open(path)?;
+            let len = file.metadata()?.len();
+            writer.write_all(&(name.len() as u32).to_be_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&len.to_be_bytes())?;
+            let copied = io::copy(&mut (&mut file).take(len), &mut writer)?;
+            if copied != len {
+                anyhow::bail!("file changed while reading: {}", path.display());
+            }
+        }
+        writer.write_all(&0_u32.to_be_bytes())?;
+
+        let (mut inner, checksum) = writer.finish();
+        inner.write_all(&checksum)?;
+        inner.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, archive_path)?;
+
+        Ok(entries.len())
+    }
+
+    // アーカイブ全体を検証してから、空の状態ディレクトリへ展開する
+    // 設定ファイルは展開先を指すよう書き換えて state_dir_path 直下に置く
+    pub fn restore(archive_path: &Path, state_dir_path: &Path) -> anyhow::Result<usize> {
+        if state_dir_path.exists() && fs::read_dir(state_dir_path)?.next().is_some() {
+            anyhow::bail!("destination is not empty: {}", state_dir_path.display());
+        }
+
+        let body_len = Self::verify(archive_path)?;
+
+        fs::create_dir_all(state_dir_path)?;
+        let mut reader = BufReader::new(fs::File::open(archive_path)?).take(body_len);
+        Self::read_header(&mut reader)?;
+
+        let mut count = 0;
+        loop {
+            let name_len = read_u32(&mut reader)? as usize;
+            if name_len == 0 {
+                break;
+            }
+            if name_len > MAX_ENTRY_PATH_LENGTH {
+                anyhow::bail!("entry path too long: {}", name_len);
+            }
+            let mut name = vec![0; name_len];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name)?;
+            let path = state_dir_path.join(to_relative_path(&name)?);
+            let len = read_u64(&mut reader)?;
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = fs::File::create(&path)?;
+            let copied = io::copy(&mut (&mut reader).take(len), &mut file)?;
+            if copied != len {
+                anyhow::bail!("unexpected end of archive");
+            }
+            count += 1;
+        }
+
+        let config_path = state_dir_path.join(CONFIG_FILE_NAME);
+        if config_path.exists() {
+            let mut config = AppConfig::load(&config_path)?;
+            config.state_dir_path = state_dir_path.to_string_lossy().to_string();
+            config.paths = PathsConfig::default();
+            rewrite_config_file(&config_path, &config.to_commented_toml()?, &gen_backup_path(&config_path, "bak"))?;
+        }
+
+        info!(state_dir_path = %state_dir_path.display(), count, "backup restored");
+        Ok(count)
+    }
+
+    // 末尾のチェックサムを照合し、本体の長さを返す
+    fn verify(archive_path: &Path) -> anyhow::Result<u64> {
+        let archive_len = fs::metadata(archive_path)?.len();
+        if archive_len < MAGIC.len() as u64 + 4 + 4 + CHECKSUM_SIZE {
+            anyhow::bail!("archive too short");
+        }
+        let body_len = archive_len - CHECKSUM_SIZE;
+
+        let mut reader = BufReader::new(fs::File::open(archive_path)?);
+        let mut writer = HashWriter::new(io::sink());
+        io::copy(&mut (&mut reader).take(body_len), &mut writer)?;
+        let (_, checksum) = writer.finish();
+
+        let mut expected = [0; CHECKSUM_SIZE as usize];
+        reader.read_exact(&mut expected)?;
+        if checksum != expected {
+            anyhow::bail!("checksum mismatch: {}", archive_path.display());
+        }
+
+        Ok(body_len)
+    }
+
+    fn read_header<R: Read>(reader: &mut R) -> anyhow::Result<()> {
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            anyhow::bail!("not a backup archive");
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            anyhow::bail!("unsupported backup version: {}", version);
+        }
+        Ok(())
+    }
+}
+
+// *.db は SqliteBackup で写し、それ以外のファイルはそのまま写す
+async fn stage_sqlite_files(dir_path: &Path, staging_dir_path: &Path) -> anyhow::Result<()> {
+    if !dir_path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(staging_dir_path)?;
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let staging_path = staging_dir_path.join(&name);
+        if file_type.is_dir() {
+            Box::pin(stage_sqlite_files(&entry.path(), &staging_path)).await?;
+        } else if file_type.is_file() {
+            if SQLITE_SIDECAR_SUFFIXES.iter().any(|n| name.ends_with(n)) {
+                continue;
+            }
+            if name.ends_with(".db") {
+                SqliteBackup::backup_file(entry.path(), &staging_path).await?;
+            } else {
+                fs::copy(entry.path(), &staging_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn collect_files(dir_path: &Path, prefix: &str, entries: &mut Vec<(String, PathBuf)>) -> anyhow::Result<()> {
+    if !dir_path.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if file_type.is_dir() {
+            collect_files(&entry.path(), &name, entries)?;
+        } else if file_type.is_file() {
+            entries.push((name, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+// 展開先の外を指すパスは受け付けない
+fn to_relative_path(name: &str) -> anyhow::Result<PathBuf> {
+    let path = PathBuf::from(name);
+    if name.is_empty() || !path.components().all(|n| matches!(n, Component::Normal(_))) {
+        anyhow::bail!("invalid entry path: {}", name);
+    }
+    Ok(path)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> anyhow::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> anyhow::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+struct HashWriter<W: Write> {
+    inner: W,
+    context: ring::digest::Context,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            context: ring::digest::Context::new(&ring::digest::SHA256),
+        }
+    }
+
+    fn finish(self) -> (W, [u8; CHECKSUM_SIZE as usize]) {
+        let mut checksum = [0; CHECKSUM_SIZE as usize];
+        checksum.copy_from_slice(self.context.finish().as_ref());
+        (self.inner, checksum)
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.context.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write as _};
+
+    use testresult::TestResult;
+
+    use sqlx::{sqlite::SqlitePool, Row as _};
+
+    use omnius_axus_engine::service::storage::BlobStorage;
+
+    use crate::shared::AppConfig;
+
+    use super::StateBackup;
+
+    #[tokio::test]
+    pub async fn backup_and_restore_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("axus-config.toml");
+        let config = AppConfig {
+            state_dir_path: dir.path().join("state").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        fs::write(&config_path, config.to_commented_toml()?)?;
+
+        {
+            let blob_storage = BlobStorage::new(config.storage_dir_path(), config.blob_storage_option())?;
+            blob_storage.put(b"key", b"value")?;
+        }
+        fs::create_dir_all(config.repo_dir_path().join("sub"))?;
+        let db_path = config.repo_dir_path().join("sub").join("sqlite.db");
+        let db = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", db_path.to_string_lossy())).await?;
+        sqlx::query("PRAGMA journal_mode = WAL").execute(&db).await?;
+        sqlx::query("CREATE TABLE items (value TEXT NOT NULL)").execute(&db).await?;
+        sqlx::query("INSERT INTO items (value) VALUES ('db')").execute(&db).await?;
+
+        // 開いたままの DB も、WAL に残っている内容を含めて退避する
+        let archive_path = dir.path().join("backup.axb");
+        StateBackup::create(&config, &config_path, &archive_path).await?;
+        assert!(!dir.path().join("state").join("backup.staging").exists());
+        db.close().await;
+
+        let restored_path = dir.path().join("restored");
+        StateBackup::restore(&archive_path, &restored_path)?;
+
+        let restored = AppConfig::load(restored_path.join("axus-config.toml"))?;
+        assert_eq!(restored.state_dir_path, restored_path.to_string_lossy());
+        // 書き換える前の設定は残しておく
+        assert!(restored_path.join("axus-config.toml.bak").exists());
+        let db_path = restored.repo_dir_path().join("sub").join("sqlite.db");
+        assert!(!restored.repo_dir_path().join("sub").join("sqlite.db-wal").exists());
+        let db = SqlitePool::connect(&format!("sqlite:{}", db_path.to_string_lossy())).await?;
+        let row = sqlx::query("SELECT value FROM items").fetch_one(&db).await?;
+        assert_eq!(row.get::<String, _>(0), "db");
+        db.close().await;
+        let blob_storage = BlobStorage::new(restored.storage_dir_path(), restored.blob_storage_option())?;
+        assert_eq!(blob_storage.get(b"key")?.as_deref(), Some(&b"value"[..]));
+
+        // 空でない展開先には復元しない
+        assert!(StateBackup::restore(&archive_path, &restored_path).is_err());
+
+        // 壊れたアーカイブは展開する前に弾く
+        let mut file = fs::OpenOptions::new().append(true).open(&archive_path)?;
+        file.write_all(b"x")?;
+        let broken_path = dir.path().join("broken");
+        assert!(StateBackup::restore(&archive_path, &broken_path).is_err());
+        assert!(!broken_path.exists());
+
+        Ok(())
+    }
+}
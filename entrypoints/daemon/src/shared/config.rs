@@ -0,0 +1,854 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use omnius_axus_engine::service::{
+    connection::{BandwidthOption, BandwidthScheduleRule, ScheduleWindow, TcpListenerOption, TcpProxyCredential, TcpProxyOption, TcpProxyType},
+    engine::{ConnectionPacerOption, NodeFinderOption},
+    session::{SessionAccepterOption, SessionConnectorOption},
+    stats::StatsRecorderOption,
+    storage::{BlobStorageOption, DiskSpaceWatchdogOption},
+};
+use omnius_core_omnikit::model::OmniAddr;
+
+use super::{upgrade_config_file, ConfigDoc, CURRENT_CONFIG_VERSION};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub version: i64,
+    pub state_dir_path: String,
+    pub paths: PathsConfig,
+    pub listeners: Vec<ListenerConfig>,
+    pub proxy: ProxyConfig,
+    pub bandwidth: BandwidthConfig,
+    pub features: FeaturesConfig,
+    pub engine: EngineConfig,
+    pub update_check_url: Option<String>,
+    pub read_only: bool,
+}
+
+impl ConfigDoc for AppConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("version", "Config file version. Older files are upgraded automatically on load."),
+        ("state_dir_path", "Directory where the daemon keeps its state."),
+        ("paths", "Locations of data directories. Unset paths are placed under state_dir_path."),
+        ("listeners", "TCP addresses to accept connections on."),
+        ("proxy", "Proxy used for outgoing connections."),
+        ("bandwidth", "Bandwidth limits in bytes/sec. Unset limits are unlimited."),
+        ("features", "Subsystems to run. Publisher requires node_finder."),
+        ("engine", "Engine tuning."),
+        (
+            "update_check_url",
+            "URL returning {\"version\": \"x.y.z\"} for the latest release. Checked once at startup; nothing is installed.",
+        ),
+        (
+            "read_only",
+            "Serve and relay existing files only. Publishing and importing are disabled. Implied by engine.storage.shared_reader.",
+        ),
+    ];
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_CONFIG_VERSION,
+            state_dir_path: "./state".to_string(),
+            paths: PathsConfig::default(),
+            listeners: vec![ListenerConfig {
+                addr: "tcp(ip4(0.0.0.0),4000)".to_string(),
+                use_upnp: false,
+            }],
+            proxy: ProxyConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            features: FeaturesConfig::default(),
+            engine: EngineConfig::default(),
+            update_check_url: None,
+            read_only: false,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        upgrade_config_file(path.as_ref())?;
+
+        let c = ::config::Config::builder()
+            .add_source(::config::File::from(path.as_ref()).required(false))
+            .add_source(::config::Environment::with_prefix("AXUS").prefix_separator("_").separator("__"))
+            .build()?;
+        Ok(c.try_deserialize()?)
+    }
+
+    // 他のデーモンのストレージを読むだけの構成では、書き込む経路を全て止める
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || self.engine.storage.shared_reader
+    }
+
+    // 未指定のパスは state_dir_path 配下に置く
+    pub fn storage_dir_path(&self) -> PathBuf {
+        self.resolve_path(self.paths.storage_dir_path.as_deref(), "storage")
+    }
+
+    pub fn repo_dir_path(&self) -> PathBuf {
+        self.resolve_path(self.paths.repo_dir_path.as_deref(), "repo")
+    }
+
+    pub fn node_profile_repo_dir_path(&self) -> PathBuf {
+        self.repo_dir_path().join("node_profile")
+    }
+
+    pub fn file_publisher_repo_dir_path(&self) -> PathBuf {
+        self.repo_dir_path().join("file_publisher")
+    }
+
+    pub fn log_dir_path(&self) -> PathBuf {
+        self.resolve_path(self.paths.log_dir_path.as_deref(), "log")
+    }
+
+    fn resolve_path(&self, path: Option<&str>, default_name: &str) -> PathBuf {
+        match path {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&self.state_dir_path).join(default_name),
+        }
+    }
+
+    pub fn tcp_listener_options(&self) -> Vec<TcpListenerOption> {
+        self.listeners
+            .iter()
+            .map(|n| TcpListenerOption {
+                addr: OmniAddr::new(n.addr.as_str()),
+                use_upnp: n.use_upnp,
+            })
+            .collect()
+    }
+
+    pub fn tcp_proxy_option(&self) -> anyhow::Result<TcpProxyOption> {
+        let proxy = &self.proxy;
+        let typ = match proxy.typ {
+            ProxyType::None => TcpProxyType::None,
+            ProxyType::Socks5 => TcpProxyType::Socks5,
+        };
+        if matches!(typ, TcpProxyType::Socks5) && proxy.addr.is_none() {
+            anyhow::bail!("proxy.addr is required for socks5");
+        }
+        let credential = match (&proxy.username, &proxy.password) {
+            (Some(username), Some(password)) => Some(TcpProxyCredential {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            (None, None) => None,
+            _ => anyhow::bail!("proxy.username and proxy.password must be set together"),
+        };
+
+        Ok(TcpProxyOption {
+            typ,
+            addr: proxy.addr.clone(),
+            credential,
+        })
+    }
+
+    pub fn bandwidth_option(&self) -> anyhow::Result<BandwidthOption> {
+        let bandwidth = &self.bandwidth;
+        let schedules = bandwidth
+            .schedules
+            .iter()
+            .map(|n| {
+                Ok(BandwidthScheduleRule {
+                    window: ScheduleWindow::parse(&n.window)?,
+                    max_upload_bytes_per_sec: n.max_upload_bytes_per_sec,
+                    max_download_bytes_per_sec: n.max_download_bytes_per_sec,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(BandwidthOption {
+            max_upload_bytes_per_sec: bandwidth.max_upload_bytes_per_sec,
+            max_download_bytes_per_sec: bandwidth.max_download_bytes_per_sec,
+            max_session_upload_bytes_per_sec: bandwidth.max_session_upload_bytes_per_sec,
+            max_session_download_bytes_per_sec: bandwidth.max_session_download_bytes_per_sec,
+            exempt_lan: bandwidth.exempt_lan,
+            schedules,
+        })
+    }
+
+    pub fn disk_space_watchdog_option(&self) -> DiskSpaceWatchdogOption {
+        DiskSpaceWatchdogOption {
+            min_free_bytes: self.engine.storage.min_free_bytes,
+            max_rss_bytes: self.engine.storage.max_rss_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn bootstrap_file_path(&self) -> PathBuf {
+        Path::new(&self.state_dir_path).join("bootstrap_node_profiles.txt")
+    }
+
+    pub fn node_finder_option(&self) -> NodeFinderOption {
+        NodeFinderOption {
+            state_dir_path: Path::new(&self.state_dir_path).join("node_finder").to_string_lossy().to_string(),
+            max_connected_session_count: self.engine.node_finder.max_connected_session_count,
+            max_accepted_session_count: self.engine.node_finder.max_accepted_session_count,
+            connection_pacer: ConnectionPacerOption {
+                min_interval: Duration::from_secs(self.engine.node_finder.min_connect_interval_secs),
+                max_interval: Duration::from_secs(self.engine.node_finder.max_connect_interval_secs),
+                idle_interval: Duration::from_secs(self.engine.node_finder.idle_connect_interval_secs),
+                short_session_lifetime: Duration::from_secs(self.engine.node_finder.short_session_lifetime_secs),
+            },
+            session_idle_timeout: Duration::from_secs(self.engine.node_finder.session_idle_timeout_secs),
+            asset_key_location_ttl: Duration::from_secs(self.engine.node_finder.asset_key_location_ttl_secs),
+            replication_factor: self.engine.node_finder.replication_factor,
+            lookup_width: self.engine.node_finder.lookup_width,
+        }
+    }
+
+    pub fn session_connector_option(&self) -> anyhow::Result<SessionConnectorOption> {
+        let pinned_public_keys = self
+            .engine
+            .session
+            .pinned_public_keys
+            .iter()
+            .map(|n| {
+                let public_key = BASE64
+                    .decode(n.public_key.as_str())
+                    .with_context(|| format!("invalid public_key for {}", n.addr))?;
+                Ok((OmniAddr::new(n.addr.as_str()), public_key))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        Ok(SessionConnectorOption {
+            pinned_public_keys,
+            network_key: self.network_key(),
+            ..Default::default()
+        })
+    }
+
+    pub fn session_accepter_option(&self) -> SessionAccepterOption {
+        let session = &self.engine.session;
+        SessionAccepterOption {
+            network_key: self.network_key(),
+            max_sessions_per_ip: session.max_sessions_per_ip,
+            max_sessions_per_subnet: session.max_sessions_per_subnet,
+            exempt_lan: session.exempt_lan,
+            ..Default::default()
+        }
+    }
+
+    fn network_key(&self) -> Option<Vec<u8>> {
+        self.engine.session.network_key.as_ref().map(|n| n.as_bytes().to_vec())
+    }
+
+    pub fn stats_dir_path(&self) -> PathBuf {
+        Path::new(&self.state_dir_path).join("stats")
+    }
+
+    pub fn stats_recorder_option(&self) -> StatsRecorderOption {
+        let stats = &self.engine.stats;
+        StatsRecorderOption {
+            sample_interval: Duration::from_secs(stats.sample_interval_secs.max(1)),
+            raw_retention: Duration::from_secs(stats.raw_retention_hours * 3600),
+            hourly_retention: Duration::from_secs(stats.hourly_retention_days * 3600 * 24),
+        }
+    }
+
+    pub fn blob_storage_option(&self) -> BlobStorageOption {
+        let storage = &self.engine.storage;
+        BlobStorageOption {
+            write_buffer_size: storage.write_buffer_size,
+            max_background_jobs: storage.max_background_jobs,
+            blob_gc_age_cutoff: storage.blob_gc_age_cutoff,
+            blob_gc_force_threshold: storage.blob_gc_force_threshold,
+            block_cache_size: storage.block_cache_size,
+            thread_count: storage.thread_count,
+            value_cache_size: storage.value_cache_size,
+            secondary_path: storage.shared_reader.then(|| Path::new(&self.state_dir_path).join("storage_reader")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    pub storage_dir_path: Option<String>,
+    pub repo_dir_path: Option<String>,
+    pub log_dir_path: Option<String>,
+}
+
+impl ConfigDoc for PathsConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("storage_dir_path", "Block storage directory."),
+        ("repo_dir_path", "Metadata repository directory."),
+        ("log_dir_path", "Log directory."),
+    ];
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ListenerConfig {
+    pub addr: String,
+    #[serde(default)]
+    pub use_upnp: bool,
+}
+
+impl ConfigDoc for ListenerConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("addr", "Address to listen on, e.g. tcp(ip4(0.0.0.0),4000)."),
+        ("use_upnp", "Open the port on the router with UPnP."),
+    ];
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub typ: ProxyType,
+    pub addr: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl ConfigDoc for ProxyConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("typ", "Proxy type: none or socks5."),
+        ("addr", "Proxy address, e.g. 127.0.0.1:9050."),
+        ("username", "Proxy username. Must be set together with password."),
+        ("password", "Proxy password."),
+    ];
+}
+
+// 設定をログに出力してもパスワードが漏れないようにする
+impl fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("typ", &self.typ)
+            .field("addr", &self.addr)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyType {
+    #[default]
+    None,
+    Socks5,
+}
+
+// 単位は bytes/sec。未指定は無制限
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub max_download_bytes_per_sec: Option<u64>,
+    pub max_session_upload_bytes_per_sec: Option<u64>,
+    pub max_session_download_bytes_per_sec: Option<u64>,
+    pub exempt_lan: bool,
+    pub schedules: Vec<BandwidthScheduleConfig>,
+}
+
+impl ConfigDoc for BandwidthConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("max_upload_bytes_per_sec", "Upload limit shared by all connections."),
+        ("max_download_bytes_per_sec", "Download limit shared by all connections."),
+        ("max_session_upload_bytes_per_sec", "Upload limit per connection."),
+        ("max_session_download_bytes_per_sec", "Download limit per connection."),
+        ("exempt_lan", "Do not limit connections to peers on the local network."),
+        (
+            "schedules",
+            "Time windows that replace the shared limits while active. The first matching window wins.",
+        ),
+    ];
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            max_upload_bytes_per_sec: None,
+            max_download_bytes_per_sec: None,
+            max_session_upload_bytes_per_sec: None,
+            max_session_download_bytes_per_sec: None,
+            exempt_lan: true,
+            schedules: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BandwidthScheduleConfig {
+    pub window: String,
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub max_download_bytes_per_sec: Option<u64>,
+}
+
+impl ConfigDoc for BandwidthScheduleConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "window",
+            "Local time window, e.g. \"Mon-Fri 09:00-18:00\" or \"22:00-06:00\". Days may be omitted or \"*\" for every day.",
+        ),
+        (
+            "max_upload_bytes_per_sec",
+            "Shared upload limit while the window is active. Unset is unlimited.",
+        ),
+        (
+            "max_download_bytes_per_sec",
+            "Shared download limit while the window is active. Unset is unlimited.",
+        ),
+    ];
+}
+
+// NodeFinder のみ (ルーティング用) といった縮退構成で起動するためのフラグ
+// 購読はまだ実装されていないため、フラグも置かない
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FeaturesConfig {
+    pub node_finder: bool,
+    pub publisher: bool,
+}
+
+impl ConfigDoc for FeaturesConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] =
+        &[("node_finder", "Discover and exchange node profiles."), ("publisher", "Publish files.")];
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            node_finder: true,
+            publisher: true,
+        }
+    }
+}
+
+impl FeaturesConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.node_finder {
+            // 配信はノード探索に依存する
+            if self.publisher {
+                anyhow::bail!("features.node_finder is required for publisher");
+            }
+            anyhow::bail!("at least one feature must be enabled");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub node_finder: NodeFinderConfig,
+    pub session: SessionConfig,
+    pub file: FileConfig,
+    pub storage: StorageConfig,
+    pub stats: StatsConfig,
+}
+
+impl ConfigDoc for EngineConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("node_finder", "Node discovery."),
+        ("session", "Handshake with other nodes."),
+        ("file", "File transfer."),
+        ("storage", "Block storage (RocksDB)."),
+        ("stats", "Activity history kept for graphs."),
+    ];
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NodeFinderConfig {
+    pub max_connected_session_count: usize,
+    pub max_accepted_session_count: usize,
+    pub min_connect_interval_secs: u64,
+    pub max_connect_interval_secs: u64,
+    pub idle_connect_interval_secs: u64,
+    pub short_session_lifetime_secs: u64,
+    pub session_idle_timeout_secs: u64,
+    pub asset_key_location_ttl_secs: u64,
+    pub replication_factor: usize,
+    pub lookup_width: usize,
+    // axus:node/... 形式の URI
+    pub bootstrap_node_profiles: Vec<String>,
+}
+
+impl ConfigDoc for NodeFinderConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("max_connected_session_count", "Maximum number of outgoing sessions."),
+        ("max_accepted_session_count", "Maximum number of incoming sessions."),
+        (
+            "min_connect_interval_secs",
+            "Shortest wait between connection attempts, used when few sessions are open.",
+        ),
+        (
+            "max_connect_interval_secs",
+            "Longest wait between connection attempts, reached near the target or after repeated failures.",
+        ),
+        (
+            "idle_connect_interval_secs",
+            "Wait between checks once the outgoing session target is reached.",
+        ),
+        (
+            "short_session_lifetime_secs",
+            "Sessions that end sooner than this count as churn. Frequent churn slows down reconnection attempts.",
+        ),
+        (
+            "session_idle_timeout_secs",
+            "Close a session after this many seconds without receiving anything from the peer.",
+        ),
+        (
+            "asset_key_location_ttl_secs",
+            "Seconds to remember which nodes hold an asset, so lookups work right after a restart.",
+        ),
+        (
+            "replication_factor",
+            "Number of closest peers that receive published asset locations and pointers. Higher is more reliable but uses more bandwidth.",
+        ),
+        (
+            "lookup_width",
+            "Number of closest peers asked for each wanted asset. Higher finds assets sooner but uses more bandwidth.",
+        ),
+        ("bootstrap_node_profiles", "Node profile URIs (axus:node/...) to connect to first."),
+    ];
+}
+
+impl Default for NodeFinderConfig {
+    fn default() -> Self {
+        Self {
+            max_connected_session_count: 3,
+            max_accepted_session_count: 3,
+            min_connect_interval_secs: 1,
+            max_connect_interval_secs: 30,
+            idle_connect_interval_secs: 10,
+            short_session_lifetime_secs: 60,
+            session_idle_timeout_secs: 180,
+            asset_key_location_ttl_secs: 60 * 60,
+            replication_factor: 1,
+            lookup_width: 1,
+            bootstrap_node_profiles: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    pub pinned_public_keys: Vec<PinnedPublicKeyConfig>,
+    pub network_key: Option<String>,
+    pub max_sessions_per_ip: usize,
+    pub max_sessions_per_subnet: usize,
+    pub exempt_lan: bool,
+}
+
+impl ConfigDoc for SessionConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        (
+            "pinned_public_keys",
+            "Public keys expected from specific addresses. Connections to a listed address fail unless the peer signs with that key.",
+        ),
+        (
+            "network_key",
+            "Shared secret for a private network. Nodes with a different or missing key fail the handshake.",
+        ),
+        ("max_sessions_per_ip", "Maximum number of incoming sessions from one IP address."),
+        (
+            "max_sessions_per_subnet",
+            "Maximum number of incoming sessions from one /24 (IPv4) or /48 (IPv6) network.",
+        ),
+        ("exempt_lan", "Do not apply the per-address limits to loopback and private addresses."),
+    ];
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        let option = SessionAccepterOption::default();
+        Self {
+            pinned_public_keys: vec![],
+            network_key: None,
+            max_sessions_per_ip: option.max_sessions_per_ip,
+            max_sessions_per_subnet: option.max_sessions_per_subnet,
+            exempt_lan: option.exempt_lan,
+        }
+    }
+}
+
+// 設定をログに出力してもネットワーク鍵が漏れないようにする
+impl fmt::Debug for SessionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionConfig")
+            .field("pinned_public_keys", &self.pinned_public_keys)
+            .field("network_key", &self.network_key.as_ref().map(|_| "***"))
+            .field("max_sessions_per_ip", &self.max_sessions_per_ip)
+            .field("max_sessions_per_subnet", &self.max_sessions_per_subnet)
+            .field("exempt_lan", &self.exempt_lan)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PinnedPublicKeyConfig {
+    pub addr: String,
+    // 署名者の公開鍵を base64url (パディング無し) で表したもの
+    pub public_key: String,
+}
+
+impl ConfigDoc for PinnedPublicKeyConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("addr", "Peer address, e.g. tcp(ip4(192.0.2.1),4000)."),
+        ("public_key", "Peer public key, base64url without padding."),
+    ];
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FileConfig {
+    pub block_size: u64,
+}
+
+impl ConfigDoc for FileConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[("block_size", "Size of a file block in bytes.")];
+}
+
+impl Default for FileConfig {
+    fn default() -> Self {
+        Self { block_size: 1024 * 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StatsConfig {
+    pub enabled: bool,
+    pub sample_interval_secs: u64,
+    pub raw_retention_hours: u64,
+    pub hourly_retention_days: u64,
+}
+
+impl ConfigDoc for StatsConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("enabled", "Record traffic, session counts and storage usage periodically."),
+        ("sample_interval_secs", "Seconds between samples."),
+        ("raw_retention_hours", "Hours to keep individual samples."),
+        ("hourly_retention_days", "Days to keep the hourly rollups."),
+    ];
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        let option = StatsRecorderOption::default();
+        Self {
+            enabled: true,
+            sample_interval_secs: option.sample_interval.as_secs(),
+            raw_retention_hours: option.raw_retention.as_secs() / 3600,
+            hourly_retention_days: option.hourly_retention.as_secs() / (3600 * 24),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub write_buffer_size: usize,
+    pub max_background_jobs: i32,
+    pub blob_gc_age_cutoff: f64,
+    pub blob_gc_force_threshold: f64,
+    pub block_cache_size: usize,
+    pub thread_count: usize,
+    pub value_cache_size: usize,
+    pub min_free_bytes: u64,
+    pub max_rss_bytes: Option<u64>,
+    pub shared_reader: bool,
+}
+
+impl ConfigDoc for StorageConfig {
+    const FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("write_buffer_size", "RocksDB write buffer size in bytes."),
+        ("max_background_jobs", "RocksDB background job count."),
+        ("blob_gc_age_cutoff", "RocksDB blob GC age cutoff (0.0 - 1.0)."),
+        ("blob_gc_force_threshold", "RocksDB blob GC force threshold (0.0 - 1.0)."),
+        ("block_cache_size", "RocksDB block cache size in bytes."),
+        ("thread_count", "Threads dedicated to storage reads and scans."),
+        ("value_cache_size", "In-memory cache for frequently read blocks, in bytes. 0 disables it."),
+        (
+            "min_free_bytes",
+            "Pause writes when free space on the storage volume falls below this many bytes.",
+        ),
+        (
+            "max_rss_bytes",
+            "Pause writes while the daemon's resident memory exceeds this many bytes. Unset disables the check. Linux only.",
+        ),
+        (
+            "shared_reader",
+            "Open the storage directory read-only and follow another daemon that writes to it. Publishing is not possible in this mode.",
+        ),
+    ];
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        let option = BlobStorageOption::default();
+        Self {
+            write_buffer_size: option.write_buffer_size,
+            max_background_jobs: option.max_background_jobs,
+            blob_gc_age_cutoff: option.blob_gc_age_cutoff,
+            blob_gc_force_threshold: option.blob_gc_force_threshold,
+            block_cache_size: option.block_cache_size,
+            thread_count: option.thread_count,
+            value_cache_size: option.value_cache_size,
+            min_free_bytes: DiskSpaceWatchdogOption::default().min_free_bytes,
+            max_rss_bytes: DiskSpaceWatchdogOption::default().max_rss_bytes,
+            shared_reader: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use testresult::TestResult;
+
+    use omnius_axus_engine::service::connection::TcpProxyType;
+    use omnius_core_omnikit::model::OmniAddr;
+
+    use super::{AppConfig, PinnedPublicKeyConfig};
+
+    #[test]
+    pub fn simple_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("axus-config.toml");
+        std::fs::write(
+            &path,
+            r#"
+version = 1
+state_dir_path = "/var/lib/axus"
+
+[paths]
+storage_dir_path = "/mnt/data/axus"
+
+[[listeners]]
+addr = "tcp(ip4(0.0.0.0),4000)"
+use_upnp = true
+
+[[listeners]]
+addr = "tcp(ip4(127.0.0.1),4001)"
+
+[[listeners]]
+addr = "tcp(ip6(::),4000)"
+
+[proxy]
+typ = "socks5"
+addr = "127.0.0.1:9050"
+username = "user"
+password = "secret"
+
+[bandwidth]
+max_upload_bytes_per_sec = 1048576
+max_session_download_bytes_per_sec = 65536
+
+[[bandwidth.schedules]]
+window = "Mon-Fri 09:00-18:00"
+max_upload_bytes_per_sec = 1024
+
+[features]
+publisher = false
+
+[engine.node_finder]
+max_connected_session_count = 8
+bootstrap_node_profiles = ["axus:node/xxx"]
+
+[engine.session]
+network_key = "private"
+max_sessions_per_ip = 8
+
+[[engine.session.pinned_public_keys]]
+addr = "tcp(ip4(192.0.2.1),4000)"
+public_key = "AQID"
+
+[engine.storage]
+block_cache_size = 1024
+min_free_bytes = 2048
+"#,
+        )?;
+
+        let config = AppConfig::load(&path)?;
+        assert_eq!(config.version, 1);
+        assert_eq!(config.state_dir_path, "/var/lib/axus");
+        assert_eq!(config.storage_dir_path(), PathBuf::from("/mnt/data/axus"));
+        assert_eq!(config.repo_dir_path(), PathBuf::from("/var/lib/axus/repo"));
+        assert_eq!(config.listeners.len(), 3);
+        assert!(config.listeners[0].use_upnp);
+        assert!(!config.listeners[1].use_upnp);
+        assert_eq!(config.engine.node_finder.max_connected_session_count, 8);
+        assert_eq!(config.engine.node_finder.max_accepted_session_count, 3);
+        assert_eq!(config.engine.node_finder.bootstrap_node_profiles, vec!["axus:node/xxx".to_string()]);
+        assert!(config.features.node_finder && !config.features.publisher);
+        assert!(config.features.validate().is_ok());
+        assert_eq!(config.engine.file.block_size, 1024 * 1024);
+
+        let proxy_option = config.tcp_proxy_option()?;
+        assert!(matches!(proxy_option.typ, TcpProxyType::Socks5));
+        assert_eq!(proxy_option.addr.as_deref(), Some("127.0.0.1:9050"));
+        assert_eq!(proxy_option.credential.map(|n| n.username).as_deref(), Some("user"));
+        assert!(!format!("{:?}", config.proxy).contains("secret"));
+
+        let bandwidth_option = config.bandwidth_option()?;
+        assert_eq!(bandwidth_option.max_upload_bytes_per_sec, Some(1024 * 1024));
+        assert_eq!(bandwidth_option.max_download_bytes_per_sec, None);
+        assert_eq!(bandwidth_option.max_session_download_bytes_per_sec, Some(64 * 1024));
+        assert!(bandwidth_option.exempt_lan);
+        assert_eq!(bandwidth_option.schedules.len(), 1);
+        assert_eq!(bandwidth_option.schedules[0].max_upload_bytes_per_sec, Some(1024));
+
+        let option = config.node_finder_option();
+        assert_eq!(option.state_dir_path, "/var/lib/axus/node_finder");
+        assert_eq!(option.max_connected_session_count, 8);
+        assert_eq!(option.replication_factor, 1);
+        assert_eq!(option.lookup_width, 1);
+        let session_connector_option = config.session_connector_option()?;
+        assert_eq!(
+            session_connector_option
+                .pinned_public_keys
+                .get(&OmniAddr::new("tcp(ip4(192.0.2.1),4000)")),
+            Some(&vec![1, 2, 3])
+        );
+        assert_eq!(session_connector_option.network_key.as_deref(), Some(&b"private"[..]));
+        let session_accepter_option = config.session_accepter_option();
+        assert_eq!(session_accepter_option.network_key.as_deref(), Some(&b"private"[..]));
+        assert_eq!(session_accepter_option.max_sessions_per_ip, 8);
+        assert_eq!(session_accepter_option.max_sessions_per_subnet, 4);
+        assert!(session_accepter_option.exempt_lan);
+        assert!(!format!("{:?}", config.engine.session).contains("private"));
+        assert_eq!(config.blob_storage_option().block_cache_size, 1024);
+        assert_eq!(config.disk_space_watchdog_option().min_free_bytes, 2048);
+
+        let config = AppConfig::load(dir.path().join("missing.toml"))?;
+        assert_eq!(config.state_dir_path, "./state");
+        assert_eq!(config.listeners.len(), 1);
+        assert!(!config.is_read_only());
+        assert!(matches!(config.tcp_proxy_option()?.typ, TcpProxyType::None));
+        assert!(config.session_connector_option()?.pinned_public_keys.is_empty());
+        assert!(config.session_accepter_option().network_key.is_none());
+
+        let mut invalid = config.clone();
+        invalid.engine.session.pinned_public_keys.push(PinnedPublicKeyConfig {
+            addr: "tcp(ip4(192.0.2.1),4000)".to_string(),
+            public_key: "not base64!".to_string(),
+        });
+        assert!(invalid.session_connector_option().is_err());
+
+        let mut features = config.features.clone();
+        features.node_finder = false;
+        assert!(features.validate().is_err());
+        features.publisher = false;
+        assert!(features.validate().is_err());
+
+        Ok(())
+    }
+}
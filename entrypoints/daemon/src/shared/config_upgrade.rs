@@ -0,0 +1,129 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+pub const CURRENT_CONFIG_VERSION: i64 = 1;
+
+// UPGRADES[i] は version (i + 1) から version (i + 2) への変換
+// 設定の形式を変える時は、CURRENT_CONFIG_VERSION を上げてここに変換を追加する
+const UPGRADES: &[fn(&mut toml::Table) -> anyhow::Result<()>] = &[];
+
+// 古い形式の設定ファイルを現在の形式に書き換える。元のファイルはバックアップとして残す
+pub fn upgrade_config_file<P: AsRef<Path>>(path: P) -> anyhow::Result<bool> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let mut table: toml::Table = fs::read_to_string(path)?.parse()?;
+    let Some(old_version) = upgrade_config(&mut table)? else {
+        return Ok(false);
+    };
+
+    let backup_path = gen_backup_path(path, &format!("v{}.bak", old_version));
+    rewrite_config_file(path, &toml::to_string(&table)?, &backup_path)?;
+
+    info!(
+        path = path.to_string_lossy().as_ref(),
+        backup_path = backup_path.to_string_lossy().as_ref(),
+        old_version,
+        new_version = CURRENT_CONFIG_VERSION,
+        "config upgraded"
+    );
+
+    Ok(true)
+}
+
+// 設定ファイルをその場で書き換える前に、元のファイルを backup_path に残す
+// 一時ファイルに書いてから置き換えるので、途中で失敗しても元のファイルは壊れない
+pub fn rewrite_config_file(path: &Path, text: &str, backup_path: &Path) -> anyhow::Result<()> {
+    fs::copy(path, backup_path)?;
+
+    let tmp_path = gen_backup_path(path, "tmp");
+    fs::write(&tmp_path, text)?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+// 変換した場合は元の version を返す
+fn upgrade_config(table: &mut toml::Table) -> anyhow::Result<Option<i64>> {
+    // version を持たないものは最初の形式とみなす
+    let version = match table.get("version") {
+        None => 1,
+        Some(toml::Value::Integer(v)) => *v,
+        Some(_) => anyhow::bail!("invalid config version"),
+    };
+    if version < 1 || version > CURRENT_CONFIG_VERSION {
+        anyhow::bail!("unsupported config version: {}", version);
+    }
+    if version == CURRENT_CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    for upgrade in UPGRADES[(version - 1) as usize..].iter() {
+        upgrade(table)?;
+    }
+    table.insert("version".to_string(), toml::Value::Integer(CURRENT_CONFIG_VERSION));
+
+    Ok(Some(version))
+}
+
+pub fn gen_backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut s: OsString = path.as_os_str().to_owned();
+    s.push(".");
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+
+    use super::{rewrite_config_file, upgrade_config_file};
+
+    #[test]
+    pub fn upgrade_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("axus-config.toml");
+
+        // version を持たないものは現在の形式なので書き換えない
+        let text = r#"
+state_dir_path = "/var/lib/axus"
+"#;
+        std::fs::write(&path, text)?;
+        assert!(!upgrade_config_file(&path)?);
+        assert_eq!(std::fs::read_to_string(&path)?, text);
+
+        std::fs::write(&path, "version = 1\n")?;
+        assert!(!upgrade_config_file(&path)?);
+        assert!(!upgrade_config_file(dir.path().join("missing.toml"))?);
+
+        for text in ["version = 0\n", "version = 99\n", "version = \"1\"\n"] {
+            std::fs::write(&path, text)?;
+            assert!(upgrade_config_file(&path).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn rewrite_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("axus-config.toml");
+        let backup_path = dir.path().join("axus-config.toml.bak");
+
+        std::fs::write(&path, "version = 1\n# comment\n")?;
+        rewrite_config_file(&path, "version = 1\n", &backup_path)?;
+
+        assert_eq!(std::fs::read_to_string(&path)?, "version = 1\n");
+        assert_eq!(std::fs::read_to_string(&backup_path)?, "version = 1\n# comment\n");
+        assert!(!dir.path().join("axus-config.toml.tmp").exists());
+
+        Ok(())
+    }
+}
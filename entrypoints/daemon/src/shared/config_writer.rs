@@ -0,0 +1,122 @@
+use std::fmt::Write as _;
+
+use super::{
+    AppConfig, BandwidthConfig, BandwidthScheduleConfig, EngineConfig, FeaturesConfig, FileConfig, ListenerConfig, NodeFinderConfig, PathsConfig,
+    PinnedPublicKeyConfig, ProxyConfig, SessionConfig, StatsConfig, StorageConfig,
+};
+
+// 設定ファイルに出力する順のキーと説明
+pub trait ConfigDoc {
+    const FIELDS: &'static [(&'static str, &'static str)];
+}
+
+fn fields_of(path: &str) -> &'static [(&'static str, &'static str)] {
+    match path {
+        "" => AppConfig::FIELDS,
+        "paths" => PathsConfig::FIELDS,
+        "listeners" => ListenerConfig::FIELDS,
+        "proxy" => ProxyConfig::FIELDS,
+        "bandwidth" => BandwidthConfig::FIELDS,
+        "bandwidth.schedules" => BandwidthScheduleConfig::FIELDS,
+        "features" => FeaturesConfig::FIELDS,
+        "engine" => EngineConfig::FIELDS,
+        "engine.node_finder" => NodeFinderConfig::FIELDS,
+        "engine.session" => SessionConfig::FIELDS,
+        "engine.session.pinned_public_keys" => PinnedPublicKeyConfig::FIELDS,
+        "engine.file" => FileConfig::FIELDS,
+        "engine.storage" => StorageConfig::FIELDS,
+        "engine.stats" => StatsConfig::FIELDS,
+        _ => &[],
+    }
+}
+
+impl AppConfig {
+    // 全てのフィールドを説明のコメント付きで出力する。未設定のものはコメントアウトする
+    pub fn to_commented_toml(&self) -> anyhow::Result<String> {
+        let table = toml::Table::try_from(self)?;
+        let mut out = String::new();
+        write_table(&mut out, "", &table)?;
+        Ok(out)
+    }
+}
+
+fn write_table(out: &mut String, path: &str, table: &toml::Table) -> anyhow::Result<()> {
+    let fields = fields_of(path);
+    let mut keys: Vec<(&str, &str)> = fields.to_vec();
+    for key in table.keys() {
+        if !fields.iter().any(|(k, _)| k == key) {
+            keys.push((key.as_str(), ""));
+        }
+    }
+
+    // テーブルは値の後にまとめて出力する
+    let mut children: Vec<(&str, &str, &toml::Value)> = Vec::new();
+    for (key, doc) in keys {
+        match table.get(key) {
+            Some(v @ toml::Value::Table(_)) => children.push((key, doc, v)),
+            Some(v @ toml::Value::Array(vs)) if !vs.is_empty() && vs.iter().all(|n| n.is_table()) => children.push((key, doc, v)),
+            Some(v) => {
+                write_doc(out, doc)?;
+                writeln!(out, "{} = {}", key, v)?;
+            }
+            None => {
+                write_doc(out, doc)?;
+                writeln!(out, "# {} =", key)?;
+            }
+        }
+    }
+
+    for (key, doc, value) in children {
+        let child_path = if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+        writeln!(out)?;
+        write_doc(out, doc)?;
+        match value {
+            toml::Value::Table(t) => {
+                writeln!(out, "[{}]", child_path)?;
+                write_table(out, &child_path, t)?;
+            }
+            toml::Value::Array(vs) => {
+                for t in vs.iter().filter_map(|n| n.as_table()) {
+                    writeln!(out, "[[{}]]", child_path)?;
+                    write_table(out, &child_path, t)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+fn write_doc(out: &mut String, doc: &str) -> anyhow::Result<()> {
+    if !doc.is_empty() {
+        writeln!(out, "# {}", doc)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+
+    use super::super::AppConfig;
+
+    #[test]
+    pub fn default_config_test() -> TestResult {
+        let config = AppConfig::default();
+        let text = config.to_commented_toml()?;
+        assert!(text.contains("# Proxy type: none or socks5.\ntyp = \"none\"\n"));
+        assert!(text.contains("# max_upload_bytes_per_sec =\n"));
+        assert!(text.contains("[[listeners]]\n"));
+        assert!(text.contains("[engine.storage]\n"));
+
+        // 出力したものを読み込むと元の設定に戻る
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("axus-config.toml");
+        std::fs::write(&path, &text)?;
+        let loaded = AppConfig::load(&path)?;
+        assert_eq!(toml::Table::try_from(&loaded)?, toml::Table::try_from(&config)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    fs,
+    io::Read as _,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::AppConfig;
+
+const LAYOUT_FILE_NAME: &str = "layout.json";
+const COMPARE_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateLayout {
+    pub storage_dir_path: PathBuf,
+    pub repo_dir_path: PathBuf,
+    pub log_dir_path: PathBuf,
+}
+
+impl StateLayout {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            storage_dir_path: config.storage_dir_path(),
+            repo_dir_path: config.repo_dir_path(),
+            log_dir_path: config.log_dir_path(),
+        }
+    }
+
+    // 前回起動時の配置と比較し、変更されたディレクトリを新しい場所へ移動する
+    pub fn migrate<P: AsRef<Path>>(&self, state_dir_path: P) -> anyhow::Result<()> {
+        let state_dir_path = state_dir_path.as_ref();
+        fs::create_dir_all(state_dir_path)?;
+        let layout_path = state_dir_path.join(LAYOUT_FILE_NAME);
+
+        if layout_path.exists() {
+            let old: StateLayout = serde_json::from_slice(&fs::read(&layout_path)?)?;
+            for (from, to) in [
+                (&old.storage_dir_path, &self.storage_dir_path),
+                (&old.repo_dir_path, &self.repo_dir_path),
+                (&old.log_dir_path, &self.log_dir_path),
+            ] {
+                if from != to && from.exists() {
+                    info!(from = %from.display(), to = %to.display(), "migrate directory");
+                    move_dir(from, to)?;
+                }
+            }
+        }
+
+        // 移動が全て終わってから新しい配置を記録する (途中で失敗した場合は次回再試行する)
+        fs::write(&layout_path, serde_json::to_vec_pretty(self)?)?;
+
+        Ok(())
+    }
+}
+
+fn move_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if to.exists() && fs::read_dir(to)?.next().is_some() {
+        anyhow::bail!("destination is not empty: {}", to.display());
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // 同一ボリューム内であれば rename で済ませる
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    copy_dir(from, to)?;
+    verify_dir(from, to)?;
+    fs::remove_dir_all(from)?;
+
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+fn verify_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            verify_dir(&entry.path(), &target)?;
+        } else if file_type.is_file() && !file_equals(&entry.path(), &target)? {
+            anyhow::bail!("verification failed: {}", target.display());
+        }
+    }
+    Ok(())
+}
+
+fn file_equals(a: &Path, b: &Path) -> anyhow::Result<bool> {
+    if !b.exists() || fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    let mut a = fs::File::open(a)?;
+    let mut b = fs::File::open(b)?;
+    let mut a_buf = vec![0; COMPARE_BUFFER_SIZE];
+    let mut b_buf = vec![0; COMPARE_BUFFER_SIZE];
+    loop {
+        let n = a.read(&mut a_buf)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut b_buf[..n])?;
+        if a_buf[..n] != b_buf[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use testresult::TestResult;
+
+    use super::{copy_dir, verify_dir, StateLayout};
+
+    #[test]
+    pub fn migrate_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let state_dir_path = dir.path().join("state");
+
+        let old = StateLayout {
+            storage_dir_path: state_dir_path.join("storage"),
+            repo_dir_path: state_dir_path.join("repo"),
+            log_dir_path: state_dir_path.join("log"),
+        };
+        old.migrate(&state_dir_path)?;
+        fs::create_dir_all(old.storage_dir_path.join("sub"))?;
+        fs::write(old.storage_dir_path.join("sub").join("data"), b"data")?;
+
+        let new = StateLayout {
+            storage_dir_path: dir.path().join("other").join("storage"),
+            ..old.clone()
+        };
+        new.migrate(&state_dir_path)?;
+
+        assert!(!old.storage_dir_path.exists());
+        assert_eq!(fs::read(new.storage_dir_path.join("sub").join("data"))?, b"data");
+
+        // 記録済みの配置と同じであれば何もしない
+        new.migrate(&state_dir_path)?;
+        assert!(new.storage_dir_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn copy_and_verify_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+
+        fs::create_dir_all(from.join("sub"))?;
+        fs::write(from.join("a"), b"a")?;
+        fs::write(from.join("sub").join("b"), vec![0x01; 200 * 1024])?;
+
+        copy_dir(&from, &to)?;
+        verify_dir(&from, &to)?;
+
+        fs::write(to.join("sub").join("b"), vec![0x02; 200 * 1024])?;
+        assert!(verify_dir(&from, &to).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::Mutex as TokioMutex;
+use tracing::info;
+
+use omnius_axus_engine::{
+    model::AssetKey,
+    service::{
+        connection::{BandwidthLimiter, ConnectionTcpConnectorImpl, ConnectionTcpMultiAccepterImpl, TaskBandwidthScheduler},
+        engine::{FilePublisher, FilePublisherRepo, NodeFinder, NodeProfileFetcherBootstrap, NodeProfileRepo, ShutdownSequence, ShutdownStage},
+        session::{SessionAccepter, SessionConnector},
+        stats::{StatsRepo, TaskStatsRecorder},
+        storage::{BlobStorage, TaskDiskSpaceWatchdog},
+        util::FnHandle,
+    },
+};
+use omnius_core_base::{clock::ClockUtc, random_bytes::RandomBytesProviderImpl, sleeper::SleeperImpl, terminable::Terminable as _};
+use omnius_core_omnikit::model::{OmniSignType, OmniSigner};
+
+use super::AppConfig;
+
+// 有効化されたサブシステムのみを構築する
+#[allow(unused)]
+pub struct AppState {
+    pub config: AppConfig,
+    pub tcp_accepter: Arc<ConnectionTcpMultiAccepterImpl>,
+    pub tcp_connector: Arc<ConnectionTcpConnectorImpl>,
+    pub node_profile_fetcher: Option<Arc<NodeProfileFetcherBootstrap>>,
+    pub node_finder: Option<Arc<NodeFinder>>,
+    pub disk_space_watchdog: Arc<TaskDiskSpaceWatchdog>,
+    pub stats_recorder: Option<Arc<TaskStatsRecorder>>,
+    pub blob_storage: Option<Arc<TokioMutex<BlobStorage>>>,
+    pub file_publisher: Option<Arc<FilePublisher>>,
+    push_asset_keys_handle: Option<FnHandle<Vec<AssetKey>, ()>>,
+    shutdown_sequence: ShutdownSequence,
+}
+
+impl AppState {
+    pub async fn new(config: AppConfig) -> anyhow::Result<Self> {
+        config.features.validate()?;
+
+        let storage_dir_path = config.storage_dir_path();
+        std::fs::create_dir_all(&storage_dir_path)?;
+        let disk_space_watchdog = Arc::new(TaskDiskSpaceWatchdog::new(
+            &storage_dir_path,
+            Arc::new(SleeperImpl),
+            config.disk_space_watchdog_option(),
+        ));
+        disk_space_watchdog.run().await;
+
+        // 帯域の制限は待ち受け・接続の両方で共有する
+        let bandwidth_limiter = Arc::new(BandwidthLimiter::new(config.bandwidth_option()?));
+        let bandwidth_scheduler = if bandwidth_limiter.has_schedules() {
+            let task = Arc::new(TaskBandwidthScheduler::new(
+                bandwidth_limiter.clone(),
+                Arc::new(ClockUtc),
+                Arc::new(SleeperImpl),
+            ));
+            task.run().await;
+            Some(task)
+        } else {
+            None
+        };
+
+        let tcp_accepter = Arc::new(ConnectionTcpMultiAccepterImpl::new(&config.tcp_listener_options(), bandwidth_limiter.clone()).await?);
+        for listener in config.listeners.iter() {
+            info!(addr = listener.addr.as_str(), use_upnp = listener.use_upnp, "listening");
+        }
+
+        let stats_recorder = if config.engine.stats.enabled {
+            let stats_dir_path = config.stats_dir_path();
+            std::fs::create_dir_all(&stats_dir_path)?;
+            let stats_repo = Arc::new(StatsRepo::new(&stats_dir_path.to_string_lossy()).await?);
+            let task = Arc::new(TaskStatsRecorder::new(
+                stats_repo,
+                bandwidth_limiter.clone(),
+                Arc::new(ClockUtc),
+                Arc::new(SleeperImpl),
+                config.stats_recorder_option(),
+            ));
+            task.run().await;
+            Some(task)
+        } else {
+            None
+        };
+
+        let tcp_connector = Arc::new(ConnectionTcpConnectorImpl::new(config.tcp_proxy_option()?, bandwidth_limiter).await?);
+
+        let node_profile_fetcher = if config.features.node_finder {
+            let bootstrap_node_profiles: Vec<&str> = config.engine.node_finder.bootstrap_node_profiles.iter().map(|n| n.as_str()).collect();
+            Some(Arc::new(
+                NodeProfileFetcherBootstrap::new(&bootstrap_node_profiles, config.bootstrap_file_path()).await?,
+            ))
+        } else {
+            None
+        };
+
+        let node_finder = match node_profile_fetcher.clone() {
+            Some(node_profile_fetcher) => {
+                // 署名鍵はまだ保存していないため、起動毎に作り直す
+                let signer = Arc::new(OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "axus")?);
+                let random_bytes_provider = Arc::new(Mutex::new(RandomBytesProviderImpl::new()));
+                let session_accepter = Arc::new(
+                    SessionAccepter::new(
+                        tcp_accepter.clone(),
+                        signer.clone(),
+                        random_bytes_provider.clone(),
+                        Arc::new(SleeperImpl),
+                        config.session_accepter_option(),
+                    )
+                    .await,
+                );
+                let session_connector = Arc::new(SessionConnector::new(
+                    tcp_connector.clone(),
+                    signer,
+                    random_bytes_provider,
+                    config.session_connector_option()?,
+                ));
+
+                let repo_dir_path = config.node_profile_repo_dir_path();
+                std::fs::create_dir_all(&repo_dir_path)?;
+                let node_profile_repo = Arc::new(NodeProfileRepo::new(&repo_dir_path.to_string_lossy(), Arc::new(ClockUtc)).await?);
+
+                let node_finder_option = config.node_finder_option();
+                std::fs::create_dir_all(&node_finder_option.state_dir_path)?;
+
+                Some(Arc::new(
+                    NodeFinder::new(
+                        tcp_connector.clone(),
+                        tcp_accepter.clone(),
+                        session_connector,
+                        session_accepter,
+                        node_profile_repo,
+                        node_profile_fetcher,
+                        Arc::new(ClockUtc),
+                        Arc::new(SleeperImpl),
+                        disk_space_watchdog.gate(),
+                        node_finder_option,
+                    )
+                    .await,
+                ))
+            }
+            None => None,
+        };
+
+        // ブロックを保存するのは今のところ配信のみ
+        let blob_storage = if config.features.publisher {
+            Some(Arc::new(TokioMutex::new(BlobStorage::new(
+                &storage_dir_path,
+                config.blob_storage_option(),
+            )?)))
+        } else {
+            None
+        };
+
+        let file_publisher = match blob_storage.as_ref() {
+            Some(blob_storage) => {
+                let repo_dir_path = config.file_publisher_repo_dir_path();
+                std::fs::create_dir_all(&repo_dir_path)?;
+                let file_publisher_repo = Arc::new(FilePublisherRepo::new(&repo_dir_path.to_string_lossy(), Arc::new(ClockUtc)).await?);
+                Some(Arc::new(
+                    FilePublisher::new(
+                        file_publisher_repo,
+                        blob_storage.clone(),
+                        Arc::new(ClockUtc),
+                        Arc::new(SleeperImpl),
+                        config.is_read_only(),
+                        disk_space_watchdog.gate(),
+                    )
+                    .await?,
+                ))
+            }
+            None => None,
+        };
+
+        // 公開中のファイルのキーを NodeFinder から配布する
+        let push_asset_keys_handle = match (node_finder.as_ref(), file_publisher.clone()) {
+            (Some(node_finder), Some(file_publisher)) => Some(
+                node_finder
+                    .on_get_push_asset_keys()
+                    .register(move |_| file_publisher.get_push_asset_keys()),
+            ),
+            _ => None,
+        };
+
+        info!(
+            node_finder = config.features.node_finder,
+            publisher = config.features.publisher,
+            read_only = config.is_read_only(),
+            "subsystems enabled"
+        );
+
+        let shutdown_sequence = ShutdownSequence::new();
+        shutdown_sequence
+            .register(ShutdownStage::Accepters, "tcp_accepter", tcp_accepter.clone())
+            .await;
+        if let Some(bandwidth_scheduler) = bandwidth_scheduler {
+            shutdown_sequence
+                .register(ShutdownStage::Accepters, "bandwidth_scheduler", bandwidth_scheduler)
+                .await;
+        }
+        if let Some(stats_recorder) = stats_recorder.clone() {
+            shutdown_sequence
+                .register(ShutdownStage::Accepters, "stats_recorder", stats_recorder)
+                .await;
+        }
+        if let Some(node_finder) = node_finder.clone() {
+            shutdown_sequence.register(ShutdownStage::Finder, "node_finder", node_finder).await;
+        }
+        if let Some(file_publisher) = file_publisher.clone() {
+            shutdown_sequence
+                .register(ShutdownStage::Exchanger, "file_publisher", file_publisher)
+                .await;
+        }
+        shutdown_sequence
+            .register(ShutdownStage::Storage, "disk_space_watchdog", disk_space_watchdog.clone())
+            .await;
+
+        Ok(Self {
+            config,
+            tcp_accepter,
+            tcp_connector,
+            node_profile_fetcher,
+            node_finder,
+            disk_space_watchdog,
+            stats_recorder,
+            blob_storage,
+            file_publisher,
+            push_asset_keys_handle,
+            shutdown_sequence,
+        })
+    }
+
+    pub async fn terminate(&self) -> anyhow::Result<()> {
+        self.shutdown_sequence.terminate().await
+    }
+}
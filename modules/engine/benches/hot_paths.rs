@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use parking_lot::RwLock;
+use rand::{RngCore as _, SeedableRng as _};
+use rand_chacha::ChaCha20Rng;
+use tokio_util::bytes::Bytes;
+
+use omnius_core_omnikit::model::{OmniAddr, OmniHash, OmniHashAlgorithmType};
+use omnius_core_rocketpack::RocketMessage as _;
+
+use omnius_axus_engine::{
+    model::{AssetKey, NodeProfile},
+    service::{
+        engine::{DataMessage, SessionRegistry},
+        storage::{BlobStorage, BlobStorageOption},
+        util::Kadex,
+    },
+};
+
+const BLOCK_SIZES: [usize; 3] = [4 * 1024, 256 * 1024, 1024 * 1024];
+
+fn random_bytes(rng: &mut ChaCha20Rng, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    rng.fill_bytes(&mut buf);
+    buf
+}
+
+// ブロックの符号化はハッシュ計算のみなので、その計算と検証を測る
+fn block_codec(c: &mut Criterion) {
+    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    let mut group = c.benchmark_group("block");
+
+    for size in BLOCK_SIZES {
+        let block = random_bytes(&mut rng, size);
+        let hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &block);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("hash", size), &block, |b, block| {
+            b.iter(|| OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, black_box(block)))
+        });
+        group.bench_with_input(BenchmarkId::new("verify", size), &block, |b, block| {
+            b.iter(|| OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, black_box(block)) == hash)
+        });
+    }
+
+    group.finish();
+}
+
+fn blob_storage(c: &mut Criterion) {
+    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    let dir = tempfile::tempdir().unwrap();
+    let storage = BlobStorage::new(dir.path(), BlobStorageOption::default()).unwrap();
+    let mut group = c.benchmark_group("blob_storage");
+
+    for size in BLOCK_SIZES {
+        let value = random_bytes(&mut rng, size);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let mut index = 0u64;
+        group.bench_with_input(BenchmarkId::new("put", size), &value, |b, value| {
+            b.iter(|| {
+                index += 1;
+                storage.put(format!("put/{}/{}", size, index).as_bytes(), value).unwrap();
+            })
+        });
+
+        let key = format!("get/{}", size);
+        storage.put(key.as_bytes(), &value).unwrap();
+        group.bench_with_input(BenchmarkId::new("get", size), &key, |b, key| {
+            b.iter(|| storage.get(black_box(key.as_bytes())).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn gen_data_message(rng: &mut ChaCha20Rng, count: usize) -> DataMessage {
+    let node_profiles: Vec<NodeProfile> = (0..count)
+        .map(|i| NodeProfile {
+            id: random_bytes(rng, 32),
+            addrs: vec![OmniAddr::new(format!("tcp(ip4(192.0.2.{}),60000)", i % 256).as_str())],
+            reachable: false,
+        })
+        .collect();
+    let asset_keys: Vec<AssetKey> = (0..count)
+        .map(|_| AssetKey {
+            typ: "block".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &random_bytes(rng, 32)),
+        })
+        .collect();
+
+    DataMessage {
+        push_node_profiles: node_profiles.clone(),
+        want_asset_keys: asset_keys.clone(),
+        give_asset_key_locations: asset_keys.iter().map(|n| (n.clone(), node_profiles[..1].to_vec())).collect(),
+        push_asset_key_locations: HashMap::new(),
+        push_asset_pointers: Vec::new(),
+    }
+}
+
+fn data_message(c: &mut Criterion) {
+    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    let mut group = c.benchmark_group("data_message");
+
+    for count in [16, 256] {
+        let message = gen_data_message(&mut rng, count);
+        let bytes = message.export().unwrap();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("pack", count), &message, |b, message| {
+            b.iter(|| message.export().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("unpack", count), &bytes, |b, bytes| {
+            b.iter_batched(
+                || Bytes::from(bytes.to_vec()),
+                |mut bytes| DataMessage::import(&mut bytes).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn kadex(c: &mut Criterion) {
+    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    let mut group = c.benchmark_group("kadex");
+
+    let base = random_bytes(&mut rng, 32);
+    let target = random_bytes(&mut rng, 32);
+    for count in [256, 4096] {
+        let elements: Vec<Vec<u8>> = (0..count).map(|_| random_bytes(&mut rng, 32)).collect();
+        let elements: Vec<&[u8]> = elements.iter().map(|n| n.as_slice()).collect();
+
+        group.bench_with_input(BenchmarkId::new("find", count), &elements, |b, elements| {
+            b.iter(|| Kadex::find(&base, &target, black_box(elements), 20))
+        });
+    }
+
+    group.finish();
+}
+
+// 複数のスレッドからセッションの削除と追加を繰り返し、単一ロックの HashMap と比べる
+fn session_registry(c: &mut Criterion) {
+    const THREAD_COUNT: usize = 16;
+    const SESSION_COUNT: usize = 500;
+
+    let mut group = c.benchmark_group("session_registry");
+
+    let registry: SessionRegistry<usize> = SessionRegistry::new();
+    let single: RwLock<HashMap<Vec<u8>, usize>> = RwLock::new(HashMap::new());
+    for i in 0..SESSION_COUNT {
+        registry.try_insert(i.to_be_bytes().to_vec(), i);
+        single.write().insert(i.to_be_bytes().to_vec(), i);
+    }
+
+    group.bench_function("sharded", |b| {
+        b.iter_custom(|iters| {
+            run_threads(THREAD_COUNT, |t| {
+                for i in 0..iters as usize {
+                    let id = ((t + i) % SESSION_COUNT).to_be_bytes().to_vec();
+                    if let Some(v) = registry.remove(&id) {
+                        registry.try_insert(id, v);
+                    }
+                }
+            })
+        })
+    });
+    group.bench_function("single", |b| {
+        b.iter_custom(|iters| {
+            run_threads(THREAD_COUNT, |t| {
+                for i in 0..iters as usize {
+                    let id = ((t + i) % SESSION_COUNT).to_be_bytes().to_vec();
+                    let v = single.write().remove(&id);
+                    if let Some(v) = v {
+                        single.write().insert(id, v);
+                    }
+                }
+            })
+        })
+    });
+
+    group.finish();
+}
+
+// 全てのスレッドが終わるまでの時間を返す
+fn run_threads<F>(thread_count: usize, f: F) -> Duration
+where
+    F: Fn(usize) + Sync,
+{
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        for t in 0..thread_count {
+            let f = &f;
+            s.spawn(move || f(t));
+        }
+    });
+    start.elapsed()
+}
+
+criterion_group!(benches, block_codec, blob_storage, data_message, kadex, session_registry);
+criterion_main!(benches);
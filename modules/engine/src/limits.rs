@@ -0,0 +1,50 @@
+// 受信メッセージのサイズ・要素数・ネスト深さの上限はここに集約する
+
+// フレームはこの長さを越えると、領域を確保する前に受信側で拒否される
+// 最も大きいメッセージ (DataMessage) に合わせ、それ以上の領域を確保させない
+pub const MAX_FRAME_LENGTH: usize = 32 * 1024 * 1024;
+pub const MAX_DEPTH: u32 = 32;
+
+pub const MAX_ID_LENGTH: usize = 128;
+pub const MAX_STRING_LENGTH: usize = 1024;
+pub const MAX_ADDR_COUNT: usize = 128;
+pub const MAX_NODE_PROFILE_COUNT: usize = 128;
+pub const MAX_ASSET_KEY_COUNT: usize = 128;
+pub const MAX_ASSET_POINTER_COUNT: usize = 128;
+pub const MAX_MERKLE_LAYER_COUNT: usize = 32;
+
+// 受信時にフレーム長を検査する上限値 (メッセージ型毎)
+// MAX_FRAME_LENGTH を越える値はフレームの受信時点で拒否されるため、意味を持たない
+pub trait MessageLimit {
+    const MAX_LENGTH: usize;
+}
+
+pub fn check_len(len: u32, max: usize) -> anyhow::Result<usize> {
+    let len: usize = len.try_into()?;
+    if len > max {
+        anyhow::bail!("len too large: {} > {}", len, max);
+    }
+    Ok(len)
+}
+
+pub fn check_depth(depth: u32) -> anyhow::Result<()> {
+    if depth > MAX_DEPTH {
+        anyhow::bail!("depth too large: {} > {}", depth, MAX_DEPTH);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_depth, check_len, MAX_DEPTH};
+
+    #[test]
+    pub fn simple_test() {
+        assert_eq!(check_len(128, 128).unwrap(), 128);
+        assert!(check_len(129, 128).is_err());
+        assert!(check_len(u32::MAX, 128).is_err());
+
+        assert!(check_depth(MAX_DEPTH).is_ok());
+        assert!(check_depth(MAX_DEPTH + 1).is_err());
+    }
+}
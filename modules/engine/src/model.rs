@@ -1,7 +1,15 @@
 mod asset_key;
+mod asset_pointer;
+mod asset_uri;
+mod file_manifest;
 mod file_ref;
 mod node_profile;
+mod signed_file_ref;
 
 pub use asset_key::*;
+pub use asset_pointer::*;
+pub use asset_uri::*;
+pub use file_manifest::*;
 pub use file_ref::*;
 pub use node_profile::*;
+pub use signed_file_ref::*;
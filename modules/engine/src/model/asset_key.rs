@@ -1,6 +1,8 @@
 use omnius_core_omnikit::model::OmniHash;
 use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
+use crate::limits;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AssetKey {
     pub typ: String,
@@ -19,7 +21,9 @@ impl RocketMessage for AssetKey {
     where
         Self: Sized,
     {
-        let typ = reader.get_string(1024)?.parse()?;
+        limits::check_depth(depth)?;
+
+        let typ = reader.get_string(limits::MAX_STRING_LENGTH)?.parse()?;
         let hash = OmniHash::unpack(reader, depth + 1)?;
 
         Ok(Self { typ, hash })
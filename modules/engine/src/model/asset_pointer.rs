@@ -0,0 +1,158 @@
+use omnius_core_omnikit::model::{OmniCert, OmniHash, OmniHashAlgorithmType, OmniSigner};
+use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
+
+use crate::limits;
+
+use super::AssetKey;
+
+pub const ASSET_POINTER_KEY_TYPE: &str = "pointer";
+
+// 公開者の鍵とラベルの組から、最新版の AssetKey を指す更新可能なレコード
+// sequence の大きいものが新しい
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetPointer {
+    pub label: String,
+    pub target: AssetKey,
+    pub sequence: u64,
+    pub cert: OmniCert,
+}
+
+impl AssetPointer {
+    pub fn sign(label: String, target: AssetKey, sequence: u64, signer: &OmniSigner) -> anyhow::Result<Self> {
+        let body = AssetPointerBody { label, target, sequence };
+        let cert = signer.sign(&body.export()?)?;
+        Ok(Self {
+            label: body.label,
+            target: body.target,
+            sequence: body.sequence,
+            cert,
+        })
+    }
+
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let body = AssetPointerBody {
+            label: self.label.clone(),
+            target: self.target.clone(),
+            sequence: self.sequence,
+        };
+        if self.cert.verify(&body.export()?).is_err() {
+            anyhow::bail!("invalid signature");
+        }
+        Ok(())
+    }
+
+    // DHT 上で探すためのキー。署名者とラベルが同じであれば、指す先が変わっても同じ値になる
+    pub fn key(&self) -> AssetKey {
+        Self::compute_key(&self.cert.public_key, &self.label)
+    }
+
+    pub fn compute_key(public_key: &[u8], label: &str) -> AssetKey {
+        let mut buf = Vec::with_capacity(public_key.len() + label.len());
+        buf.extend_from_slice(public_key);
+        buf.extend_from_slice(label.as_bytes());
+        AssetKey {
+            typ: ASSET_POINTER_KEY_TYPE.to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &buf),
+        }
+    }
+}
+
+impl RocketMessage for AssetPointer {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        writer.put_str(&value.label);
+        AssetKey::pack(writer, &value.target, depth + 1)?;
+        writer.put_u64(value.sequence);
+        OmniCert::pack(writer, &value.cert, depth + 1)?;
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        limits::check_depth(depth)?;
+
+        let label = reader.get_string(limits::MAX_STRING_LENGTH)?;
+        let target = AssetKey::unpack(reader, depth + 1)?;
+        let sequence = reader.get_u64()?;
+        let cert = OmniCert::unpack(reader, depth + 1)?;
+
+        Ok(Self {
+            label,
+            target,
+            sequence,
+            cert,
+        })
+    }
+}
+
+// 署名の対象となる部分
+struct AssetPointerBody {
+    label: String,
+    target: AssetKey,
+    sequence: u64,
+}
+
+impl RocketMessage for AssetPointerBody {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        writer.put_str(&value.label);
+        AssetKey::pack(writer, &value.target, depth + 1)?;
+        writer.put_u64(value.sequence);
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        limits::check_depth(depth)?;
+
+        let label = reader.get_string(limits::MAX_STRING_LENGTH)?;
+        let target = AssetKey::unpack(reader, depth + 1)?;
+        let sequence = reader.get_u64()?;
+
+        Ok(Self { label, target, sequence })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+    use tokio_util::bytes::Bytes;
+
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
+    use omnius_core_rocketpack::RocketMessage as _;
+
+    use crate::model::AssetKey;
+
+    use super::AssetPointer;
+
+    #[test]
+    pub fn simple_test() -> TestResult {
+        let signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author")?;
+        let target = |v: &[u8]| AssetKey {
+            typ: "file".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, v),
+        };
+
+        let v1 = AssetPointer::sign("blog".to_string(), target(b"v1"), 1, &signer)?;
+        let v2 = AssetPointer::sign("blog".to_string(), target(b"v2"), 2, &signer)?;
+
+        let mut b = Bytes::from(v2.export()?.to_vec());
+        let v2 = AssetPointer::import(&mut b)?;
+        assert!(v2.verify().is_ok());
+
+        // 指す先が変わってもキーは変わらない
+        assert_eq!(v1.key(), v2.key());
+        assert_eq!(v1.key(), AssetPointer::compute_key(&v1.cert.public_key, "blog"));
+        assert_ne!(v1.key(), AssetPointer::compute_key(&v1.cert.public_key, "other"));
+
+        let mut tampered = v1.clone();
+        tampered.sequence = 3;
+        assert!(tampered.verify().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use omnius_core_omnikit::model::{OmniCert, OmniSigner};
+use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
+
+use crate::limits;
+
+use super::AssetKey;
+
+const FLAG_NAME: u32 = 1;
+const FLAG_CERT: u32 = 1 << 1;
+
+// 共有用の URI に載せる内容。ファイル名と作者の署名は省略できる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetUri {
+    pub asset_key: AssetKey,
+    pub name: Option<String>,
+    pub cert: Option<OmniCert>,
+}
+
+impl AssetUri {
+    pub fn sign(asset_key: AssetKey, name: Option<String>, signer: &OmniSigner) -> anyhow::Result<Self> {
+        let mut res = Self { asset_key, name, cert: None };
+        res.cert = Some(signer.sign(&res.export()?)?);
+        Ok(res)
+    }
+
+    // 署名は cert を除いた内容に対して行う
+    // author を指定した場合は、署名されていて、署名者の公開鍵が一致することも確認する
+    pub fn verify(&self, author: Option<&[u8]>) -> anyhow::Result<()> {
+        let Some(cert) = &self.cert else {
+            if author.is_some() {
+                anyhow::bail!("not signed");
+            }
+            return Ok(());
+        };
+
+        let unsigned = Self { cert: None, ..self.clone() };
+        if cert.verify(&unsigned.export()?).is_err() {
+            anyhow::bail!("invalid signature");
+        }
+        if let Some(author) = author {
+            if cert.public_key != author {
+                anyhow::bail!("unexpected author");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RocketMessage for AssetUri {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        AssetKey::pack(writer, &value.asset_key, depth + 1)?;
+
+        let mut flags = 0;
+        if value.name.is_some() {
+            flags |= FLAG_NAME;
+        }
+        if value.cert.is_some() {
+            flags |= FLAG_CERT;
+        }
+        writer.put_u32(flags);
+
+        if let Some(name) = &value.name {
+            writer.put_str(name);
+        }
+        if let Some(cert) = &value.cert {
+            OmniCert::pack(writer, cert, depth + 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        limits::check_depth(depth)?;
+
+        let asset_key = AssetKey::unpack(reader, depth + 1)?;
+
+        let flags = reader.get_u32()?;
+        if flags & !(FLAG_NAME | FLAG_CERT) != 0 {
+            anyhow::bail!("unknown flags: {}", flags);
+        }
+
+        let name = if flags & FLAG_NAME != 0 {
+            Some(reader.get_string(limits::MAX_STRING_LENGTH)?)
+        } else {
+            None
+        };
+        let cert = if flags & FLAG_CERT != 0 {
+            Some(OmniCert::unpack(reader, depth + 1)?)
+        } else {
+            None
+        };
+
+        Ok(Self { asset_key, name, cert })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
+
+    use crate::model::AssetKey;
+
+    use super::AssetUri;
+
+    #[test]
+    pub fn sign_test() -> TestResult {
+        let author = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author")?;
+        let other = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "other")?;
+        let author_public_key = author.sign(b"test")?.public_key;
+        let other_public_key = other.sign(b"test")?.public_key;
+
+        let asset_key = AssetKey {
+            typ: "file".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+
+        let unsigned = AssetUri {
+            asset_key: asset_key.clone(),
+            name: None,
+            cert: None,
+        };
+        assert!(unsigned.verify(None).is_ok());
+        assert!(unsigned.verify(Some(&author_public_key)).is_err());
+
+        let signed = AssetUri::sign(asset_key, Some("test.txt".to_string()), &author)?;
+        assert!(signed.verify(None).is_ok());
+        assert!(signed.verify(Some(&author_public_key)).is_ok());
+        assert!(signed.verify(Some(&other_public_key)).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.name = Some("tampered.txt".to_string());
+        assert!(tampered.verify(None).is_err());
+
+        Ok(())
+    }
+}
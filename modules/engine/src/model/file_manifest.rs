@@ -0,0 +1,177 @@
+use omnius_core_omnikit::model::{OmniCert, OmniHash, OmniSigner};
+use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
+
+use crate::limits;
+
+const FLAG_PROPERTY: u32 = 1;
+const FLAG_CERT: u32 = 1 << 1;
+
+// マークルツリーの各段のブロック数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileManifestLayer {
+    pub depth: u32,
+    pub block_count: u32,
+}
+
+// 公開したファイルの構成をまとめたもの。受け取った側は DHT を引かずに購読を始められる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileManifest {
+    pub root_hash: OmniHash,
+    pub file_name: String,
+    pub block_size: u32,
+    pub layers: Vec<FileManifestLayer>,
+    pub property: Option<String>,
+    pub cert: Option<OmniCert>,
+}
+
+impl FileManifest {
+    pub fn sign(mut self, signer: &OmniSigner) -> anyhow::Result<Self> {
+        self.cert = None;
+        self.cert = Some(signer.sign(&self.export()?)?);
+        Ok(self)
+    }
+
+    // 署名は cert を除いた内容に対して行う
+    // author を指定した場合は、署名されていて、署名者の公開鍵が一致することも確認する
+    pub fn verify(&self, author: Option<&[u8]>) -> anyhow::Result<()> {
+        let Some(cert) = &self.cert else {
+            if author.is_some() {
+                anyhow::bail!("not signed");
+            }
+            return Ok(());
+        };
+
+        let unsigned = Self { cert: None, ..self.clone() };
+        if cert.verify(&unsigned.export()?).is_err() {
+            anyhow::bail!("invalid signature");
+        }
+        if let Some(author) = author {
+            if cert.public_key != author {
+                anyhow::bail!("unexpected author");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RocketMessage for FileManifest {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        OmniHash::pack(writer, &value.root_hash, depth + 1)?;
+        writer.put_str(&value.file_name);
+        writer.put_u32(value.block_size);
+
+        writer.put_u32(value.layers.len().try_into()?);
+        for layer in value.layers.iter() {
+            writer.put_u32(layer.depth);
+            writer.put_u32(layer.block_count);
+        }
+
+        let mut flags = 0;
+        if value.property.is_some() {
+            flags |= FLAG_PROPERTY;
+        }
+        if value.cert.is_some() {
+            flags |= FLAG_CERT;
+        }
+        writer.put_u32(flags);
+
+        if let Some(property) = &value.property {
+            writer.put_str(property);
+        }
+        if let Some(cert) = &value.cert {
+            OmniCert::pack(writer, cert, depth + 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        limits::check_depth(depth)?;
+
+        let root_hash = OmniHash::unpack(reader, depth + 1)?;
+        let file_name = reader.get_string(limits::MAX_STRING_LENGTH)?;
+        let block_size = reader.get_u32()?;
+
+        let len = limits::check_len(reader.get_u32()?, limits::MAX_MERKLE_LAYER_COUNT)?;
+        let mut layers = Vec::with_capacity(len);
+        for _ in 0..len {
+            let depth = reader.get_u32()?;
+            let block_count = reader.get_u32()?;
+            layers.push(FileManifestLayer { depth, block_count });
+        }
+
+        let flags = reader.get_u32()?;
+        if flags & !(FLAG_PROPERTY | FLAG_CERT) != 0 {
+            anyhow::bail!("unknown flags: {}", flags);
+        }
+
+        let property = if flags & FLAG_PROPERTY != 0 {
+            Some(reader.get_string(limits::MAX_STRING_LENGTH)?)
+        } else {
+            None
+        };
+        let cert = if flags & FLAG_CERT != 0 {
+            Some(OmniCert::unpack(reader, depth + 1)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            root_hash,
+            file_name,
+            block_size,
+            layers,
+            property,
+            cert,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+    use tokio_util::bytes::Bytes;
+
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
+    use omnius_core_rocketpack::RocketMessage as _;
+
+    use super::{FileManifest, FileManifestLayer};
+
+    #[test]
+    pub fn sign_test() -> TestResult {
+        let author = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author")?;
+        let other = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "other")?;
+        let author_public_key = author.sign(b"test")?.public_key;
+        let other_public_key = other.sign(b"test")?.public_key;
+
+        let manifest = FileManifest {
+            root_hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+            file_name: "test.txt".to_string(),
+            block_size: 1024 * 1024,
+            layers: vec![
+                FileManifestLayer { depth: 0, block_count: 3 },
+                FileManifestLayer { depth: 1, block_count: 1 },
+            ],
+            property: Some("{}".to_string()),
+            cert: None,
+        };
+        assert!(manifest.verify(None).is_ok());
+        assert!(manifest.verify(Some(&author_public_key)).is_err());
+
+        let signed = manifest.sign(&author)?;
+        let mut b = Bytes::from(signed.export()?.to_vec());
+        let signed = FileManifest::import(&mut b)?;
+
+        assert!(signed.verify(Some(&author_public_key)).is_ok());
+        assert!(signed.verify(Some(&other_public_key)).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.block_size = 1;
+        assert!(tampered.verify(None).is_err());
+
+        Ok(())
+    }
+}
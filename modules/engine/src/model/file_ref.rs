@@ -1,6 +1,8 @@
 use omnius_core_omnikit::model::OmniHash;
 use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
+use crate::limits;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FileRef {
     pub name: String,
@@ -17,7 +19,9 @@ impl RocketMessage for FileRef {
     where
         Self: Sized,
     {
-        let name = reader.get_string(1024)?.parse()?;
+        limits::check_depth(depth)?;
+
+        let name = reader.get_string(limits::MAX_STRING_LENGTH)?.parse()?;
         let hash = OmniHash::unpack(reader, depth + 1)?;
 
         Ok(Self { name, hash })
@@ -1,23 +1,42 @@
 use std::fmt;
 
+use bitflags::bitflags;
 use omnius_core_omnikit::model::OmniAddr;
 use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
+use crate::limits;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct NodeProfile {
     pub id: Vec<u8>,
     pub addrs: Vec<OmniAddr>,
+    // 外部から直接接続できるか。できない場合はホールパンチングや中継に頼る
+    pub reachable: bool,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct NodeProfileFlags: u32 {
+        const REACHABLE = 1;
+    }
 }
 
 impl fmt::Display for NodeProfile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let addrs: Vec<String> = self.addrs.iter().map(|n| n.to_string()).collect();
-        write!(f, "id: {}, addrs: [{}]", hex::encode(&self.id), addrs.join(", "))
+        write!(
+            f,
+            "id: {}, addrs: [{}], reachable: {}",
+            hex::encode(&self.id),
+            addrs.join(", "),
+            self.reachable
+        )
     }
 }
 
-impl RocketMessage for NodeProfile {
-    fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
+impl NodeProfile {
+    // flags を追加する前の形式。reachable は伝わらない
+    pub fn pack_v1(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
         writer.put_bytes(&value.id);
 
         writer.put_u32(value.addrs.len().try_into()?);
@@ -28,21 +47,109 @@ impl RocketMessage for NodeProfile {
         Ok(())
     }
 
-    fn unpack(reader: &mut RocketMessageReader, _depth: u32) -> anyhow::Result<Self>
+    pub fn unpack_v1(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self> {
+        limits::check_depth(depth)?;
+
+        let id = reader.get_bytes(limits::MAX_ID_LENGTH)?;
+
+        let len = limits::check_len(reader.get_u32()?, limits::MAX_ADDR_COUNT)?;
+        let mut addrs = Vec::with_capacity(len);
+        for _ in 0..len {
+            addrs.push(OmniAddr::new(reader.get_string(limits::MAX_STRING_LENGTH)?.as_str()));
+        }
+
+        Ok(Self { id, addrs, reachable: false })
+    }
+}
+
+impl RocketMessage for NodeProfile {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        Self::pack_v1(writer, value, depth)?;
+
+        let mut flags = NodeProfileFlags::empty();
+        flags.set(NodeProfileFlags::REACHABLE, value.reachable);
+        writer.put_u32(flags.bits());
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let id = reader.get_bytes(128)?;
+        let mut v = Self::unpack_v1(reader, depth)?;
 
-        let len = reader.get_u32()?;
-        if len > 128 {
-            anyhow::bail!("len too large");
-        }
-        let mut addrs = Vec::with_capacity(len.try_into()?);
-        for _ in 0..len {
-            addrs.push(OmniAddr::new(reader.get_string(1024)?.as_str()));
+        // 未知のビットは無視する
+        let flags = NodeProfileFlags::from_bits_truncate(reader.get_u32()?);
+        v.reachable = flags.contains(NodeProfileFlags::REACHABLE);
+
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+    use tokio_util::bytes::Bytes;
+
+    use omnius_core_omnikit::model::OmniAddr;
+    use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
+
+    use super::NodeProfile;
+
+    #[test]
+    pub fn simple_test() -> TestResult {
+        let node_profile = NodeProfile {
+            id: vec![1, 2, 3],
+            addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+            reachable: true,
+        };
+        let mut b = Bytes::from(node_profile.export()?.to_vec());
+        assert_eq!(NodeProfile::import(&mut b)?, node_profile);
+
+        Ok(())
+    }
+
+    // flags を追加する前の形式は unpack_v1 でのみ読める
+    #[test]
+    pub fn legacy_format_test() -> TestResult {
+        let node_profile = NodeProfile {
+            id: vec![1, 2, 3],
+            addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+            reachable: true,
+        };
+        let b = LegacyNodeProfile(node_profile.clone()).export()?;
+
+        assert!(NodeProfile::import(&mut Bytes::from(b.to_vec())).is_err());
+
+        let decoded = LegacyNodeProfile::import(&mut Bytes::from(b.to_vec()))?.0;
+        assert_eq!(decoded.id, node_profile.id);
+        assert_eq!(decoded.addrs, node_profile.addrs);
+        assert!(!decoded.reachable);
+
+        Ok(())
+    }
+
+    // flags を持たない古い形式の NodeProfile
+    struct LegacyNodeProfile(NodeProfile);
+
+    impl RocketMessage for LegacyNodeProfile {
+        fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
+            writer.put_bytes(&value.0.id);
+
+            writer.put_u32(value.0.addrs.len().try_into()?);
+            for v in &value.0.addrs {
+                writer.put_str(v.as_str());
+            }
+
+            Ok(())
         }
 
-        Ok(Self { id, addrs })
+        fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+        where
+            Self: Sized,
+        {
+            Ok(Self(NodeProfile::unpack_v1(reader, depth)?))
+        }
     }
 }
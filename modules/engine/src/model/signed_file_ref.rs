@@ -0,0 +1,93 @@
+use omnius_core_omnikit::model::{OmniCert, OmniSigner};
+use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
+
+use crate::limits;
+
+use super::FileRef;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedFileRef {
+    pub file_ref: FileRef,
+    pub cert: OmniCert,
+}
+
+impl SignedFileRef {
+    pub fn sign(file_ref: FileRef, signer: &OmniSigner) -> anyhow::Result<Self> {
+        let cert = signer.sign(&file_ref.export()?)?;
+        Ok(Self { file_ref, cert })
+    }
+
+    // author を指定した場合は、署名者の公開鍵が一致することも確認する
+    pub fn verify(&self, author: Option<&[u8]>) -> anyhow::Result<()> {
+        if self.cert.verify(&self.file_ref.export()?).is_err() {
+            anyhow::bail!("invalid signature");
+        }
+        if let Some(author) = author {
+            if self.cert.public_key != author {
+                anyhow::bail!("unexpected author");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RocketMessage for SignedFileRef {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        FileRef::pack(writer, &value.file_ref, depth + 1)?;
+        OmniCert::pack(writer, &value.cert, depth + 1)?;
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        limits::check_depth(depth)?;
+
+        let file_ref = FileRef::unpack(reader, depth + 1)?;
+        let cert = OmniCert::unpack(reader, depth + 1)?;
+
+        Ok(Self { file_ref, cert })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+    use tokio_util::bytes::Bytes;
+
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
+    use omnius_core_rocketpack::RocketMessage as _;
+
+    use crate::model::FileRef;
+
+    use super::SignedFileRef;
+
+    #[test]
+    pub fn simple_test() -> TestResult {
+        let author = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author")?;
+        let other = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "other")?;
+        let author_public_key = author.sign(b"test")?.public_key;
+        let other_public_key = other.sign(b"test")?.public_key;
+
+        let file_ref = FileRef {
+            name: "test.txt".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+        let signed = SignedFileRef::sign(file_ref, &author)?;
+
+        let mut b = Bytes::from(signed.export()?.to_vec());
+        let signed = SignedFileRef::import(&mut b)?;
+
+        assert!(signed.verify(None).is_ok());
+        assert!(signed.verify(Some(&author_public_key)).is_ok());
+        assert!(signed.verify(Some(&other_public_key)).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.file_ref.name = "tampered.txt".to_string();
+        assert!(tampered.verify(None).is_err());
+
+        Ok(())
+    }
+}
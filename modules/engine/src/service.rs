@@ -1,5 +1,6 @@
 pub mod connection;
 pub mod engine;
 pub mod session;
+pub mod stats;
 pub mod storage;
-mod util;
+pub mod util;
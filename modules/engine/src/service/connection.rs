@@ -1,5 +1,9 @@
+mod bandwidth_schedule;
 mod stream;
 mod tcp;
+mod throttle;
 
+pub use bandwidth_schedule::*;
 pub use stream::*;
 pub use tcp::*;
+pub use throttle::*;
@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{Datelike as _, Local, NaiveDateTime, NaiveTime, Utc};
+use tokio::{sync::Mutex as TokioMutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
+
+use crate::service::util::{shutdown_task, sleep_or_cancelled, TASK_SHUTDOWN_GRACE_PERIOD};
+
+use super::BandwidthLimiter;
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// "Mon-Fri 09:00-18:00" や "22:00-06:00" の形式で表す、曜日毎の時間帯 (ローカル時刻)
+// 曜日を省略した場合と "*" は毎日。終了が開始より前の場合は日付を跨ぐ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleWindow {
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ScheduleWindow {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        let (days, times) = match parts.as_slice() {
+            [times] => ([true; 7], *times),
+            [days, times] => (Self::parse_days(days)?, *times),
+            _ => anyhow::bail!("invalid schedule window: {}", text),
+        };
+
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("invalid schedule window: {}", text))?;
+        let start = NaiveTime::parse_from_str(start, "%H:%M")?;
+        let end = NaiveTime::parse_from_str(end, "%H:%M")?;
+
+        Ok(Self { days, start, end })
+    }
+
+    fn parse_days(text: &str) -> anyhow::Result<[bool; 7]> {
+        if text == "*" {
+            return Ok([true; 7]);
+        }
+
+        let mut days = [false; 7];
+        for item in text.split(',') {
+            let (first, last) = item.split_once('-').unwrap_or((item, item));
+            let first = Self::parse_day(first)?;
+            let last = Self::parse_day(last)?;
+
+            // "Sat-Mon" のように週を跨ぐ指定も許す
+            let mut day = first;
+            loop {
+                days[day] = true;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+        Ok(days)
+    }
+
+    fn parse_day(text: &str) -> anyhow::Result<usize> {
+        let text = text.to_ascii_lowercase();
+        DAY_NAMES
+            .iter()
+            .position(|n| *n == text)
+            .ok_or_else(|| anyhow::anyhow!("invalid day: {}", text))
+    }
+
+    pub fn contains(&self, now: &NaiveDateTime) -> bool {
+        let day = now.weekday().num_days_from_monday() as usize;
+        let time = now.time();
+
+        if self.start == self.end {
+            return self.days[day];
+        }
+        if self.start < self.end {
+            return self.days[day] && self.start <= time && time < self.end;
+        }
+
+        // 日付を跨ぐ場合、0 時以降の部分は前日の指定に従う
+        (self.days[day] && self.start <= time) || (self.days[(day + 6) % 7] && time < self.end)
+    }
+}
+
+// 時間帯の間だけ全体の上限を置き換える。None は無制限
+#[derive(Debug, Clone)]
+pub struct BandwidthScheduleRule {
+    pub window: ScheduleWindow,
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub max_download_bytes_per_sec: Option<u64>,
+}
+
+// 時間帯の切り替わりに合わせて、帯域の上限を適用し直す
+#[derive(Clone)]
+pub struct TaskBandwidthScheduler {
+    bandwidth_limiter: Arc<BandwidthLimiter>,
+    clock: Arc<dyn Clock<Utc> + Send + Sync>,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
+}
+
+impl TaskBandwidthScheduler {
+    pub fn new(bandwidth_limiter: Arc<BandwidthLimiter>, clock: Arc<dyn Clock<Utc> + Send + Sync>, sleeper: Arc<dyn Sleeper + Send + Sync>) -> Self {
+        Self {
+            bandwidth_limiter,
+            clock,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    pub async fn run(&self) {
+        let bandwidth_limiter = self.bandwidth_limiter.clone();
+        let clock = self.clock.clone();
+        let sleeper = self.sleeper.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                bandwidth_limiter.apply_schedule(&clock.now().with_timezone(&Local).naive_local());
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(30), &cancellation_token).await {
+                    return;
+                }
+            }
+        });
+        *self.join_handle.lock().await = Some(join_handle);
+    }
+}
+
+#[async_trait]
+impl Terminable for TaskBandwidthScheduler {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        if let Some(join_handle) = self.join_handle.lock().await.take() {
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use testresult::TestResult;
+
+    use super::ScheduleWindow;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    pub fn window_test() -> TestResult {
+        // 2024-01-01 は月曜日
+        let window = ScheduleWindow::parse("Mon-Fri 09:00-18:00")?;
+        assert!(window.contains(&at("2024-01-01 09:00")));
+        assert!(!window.contains(&at("2024-01-01 18:00")));
+        assert!(!window.contains(&at("2024-01-06 12:00")));
+
+        // 日付を跨ぐ場合は、開始した曜日で判定する
+        let window = ScheduleWindow::parse("Fri 22:00-06:00")?;
+        assert!(window.contains(&at("2024-01-05 23:00")));
+        assert!(window.contains(&at("2024-01-06 05:59")));
+        assert!(!window.contains(&at("2024-01-05 05:00")));
+
+        let window = ScheduleWindow::parse("Sat-Sun,Wed 00:00-00:00")?;
+        assert!(window.contains(&at("2024-01-03 12:00")));
+        assert!(window.contains(&at("2024-01-07 12:00")));
+        assert!(!window.contains(&at("2024-01-01 12:00")));
+
+        assert_eq!(ScheduleWindow::parse("00:00-07:00")?, ScheduleWindow::parse("* 00:00-07:00")?);
+
+        assert!(ScheduleWindow::parse("Mon").is_err());
+        assert!(ScheduleWindow::parse("Xyz 00:00-01:00").is_err());
+        assert!(ScheduleWindow::parse("25:00-26:00").is_err());
+
+        Ok(())
+    }
+}
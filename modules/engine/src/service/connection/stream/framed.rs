@@ -6,7 +6,7 @@ use tokio::{
     sync::Mutex as TokioMutex,
 };
 
-const MAX_FRAME_LENGTH: usize = 64 * 1024 * 1024;
+use crate::limits::MAX_FRAME_LENGTH;
 
 #[derive(Clone)]
 pub struct FramedStream {
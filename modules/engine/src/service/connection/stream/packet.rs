@@ -2,9 +2,11 @@ use async_trait::async_trait;
 use omnius_core_omnikit::service::connection::codec::{FramedRecv, FramedSend};
 use omnius_core_rocketpack::RocketMessage;
 
+use crate::limits::MessageLimit;
+
 #[async_trait]
 pub trait FramedRecvExt: FramedRecv {
-    async fn recv_message<T: RocketMessage>(&mut self) -> anyhow::Result<T>;
+    async fn recv_message<T: RocketMessage + MessageLimit>(&mut self) -> anyhow::Result<T>;
 }
 
 #[async_trait]
@@ -12,8 +14,12 @@ impl<T: FramedRecv> FramedRecvExt for T
 where
     T: ?Sized + Send + Unpin,
 {
-    async fn recv_message<TItem: RocketMessage>(&mut self) -> anyhow::Result<TItem> {
+    async fn recv_message<TItem: RocketMessage + MessageLimit>(&mut self) -> anyhow::Result<TItem> {
+        // フレーム長は受信時に MAX_FRAME_LENGTH で領域の確保前に検査済みなので、ここではメッセージ型毎の上限のみを見る
         let mut b = self.recv().await?;
+        if b.len() > TItem::MAX_LENGTH {
+            anyhow::bail!("message too large: {} > {}", b.len(), TItem::MAX_LENGTH);
+        }
         let item = TItem::import(&mut b)?;
         Ok(item)
     }
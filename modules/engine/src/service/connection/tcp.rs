@@ -1,30 +1,46 @@
 mod accepter;
 mod connector;
+mod multi_accepter;
 mod upnp_client;
 
 pub use accepter::*;
 pub use connector::*;
+pub use multi_accepter::*;
 pub use upnp_client::*;
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use omnius_core_omnikit::model::OmniAddr;
     use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
     use testresult::TestResult;
 
-    use crate::service::connection::{
-        ConnectionTcpAccepter, ConnectionTcpAccepterImpl, ConnectionTcpConnector, ConnectionTcpConnectorImpl, FramedRecvExt as _, FramedSendExt as _,
-        TcpProxyOption, TcpProxyType,
+    use crate::{
+        limits::{MessageLimit, MAX_FRAME_LENGTH},
+        service::connection::{
+            BandwidthLimiter, ConnectionTcpAccepter, ConnectionTcpAccepterImpl, ConnectionTcpConnector, ConnectionTcpConnectorImpl,
+            FramedRecvExt as _, FramedSendExt as _, TcpProxyOption, TcpProxyType,
+        },
     };
 
     #[tokio::test]
     #[ignore]
     async fn simple_test() -> TestResult {
-        let accepter = ConnectionTcpAccepterImpl::new(&OmniAddr::create_tcp("127.0.0.1".parse()?, 50000), false).await?;
-        let connector = ConnectionTcpConnectorImpl::new(TcpProxyOption {
-            typ: TcpProxyType::None,
-            addr: None,
-        })
+        let accepter = ConnectionTcpAccepterImpl::new(
+            &OmniAddr::create_tcp("127.0.0.1".parse()?, 50000),
+            false,
+            Arc::new(BandwidthLimiter::default()),
+        )
+        .await?;
+        let connector = ConnectionTcpConnectorImpl::new(
+            TcpProxyOption {
+                typ: TcpProxyType::None,
+                addr: None,
+                credential: None,
+            },
+            Arc::new(BandwidthLimiter::default()),
+        )
         .await?;
 
         let connected_stream = connector.connect(&OmniAddr::new("tcp(ip4(127.0.0.1),50000)")).await?;
@@ -50,6 +66,10 @@ mod tests {
         pub value: String,
     }
 
+    impl MessageLimit for TestMessage {
+        const MAX_LENGTH: usize = MAX_FRAME_LENGTH;
+    }
+
     impl RocketMessage for TestMessage {
         fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
             writer.put_str(&value.value);
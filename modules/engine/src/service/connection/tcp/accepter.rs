@@ -1,6 +1,7 @@
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
     str::FromStr,
+    sync::Arc,
 };
 
 use async_trait::async_trait;
@@ -9,7 +10,7 @@ use tokio::net::TcpListener;
 use omnius_core_base::{net::Reachable, terminable::Terminable};
 use omnius_core_omnikit::model::OmniAddr;
 
-use crate::service::connection::FramedStream;
+use crate::service::connection::{BandwidthLimiter, FramedStream};
 
 use super::UpnpClient;
 
@@ -22,10 +23,11 @@ pub trait ConnectionTcpAccepter {
 pub struct ConnectionTcpAccepterImpl {
     listener: TcpListener,
     upnp_port_mapping: Option<UpnpPortMapping>,
+    bandwidth_limiter: Arc<BandwidthLimiter>,
 }
 
 impl ConnectionTcpAccepterImpl {
-    pub async fn new(addr: &OmniAddr, use_upnp: bool) -> anyhow::Result<Self> {
+    pub async fn new(addr: &OmniAddr, use_upnp: bool, bandwidth_limiter: Arc<BandwidthLimiter>) -> anyhow::Result<Self> {
         let socket_addr = addr.parse_tcp_ip()?;
         if socket_addr.is_ipv4() {
             let listener = TcpListener::bind(socket_addr).await?;
@@ -36,6 +38,7 @@ impl ConnectionTcpAccepterImpl {
                     return Ok(Self {
                         listener,
                         upnp_port_mapping: Some(upnp_port_mapping),
+                        bandwidth_limiter,
                     });
                 }
             }
@@ -43,12 +46,14 @@ impl ConnectionTcpAccepterImpl {
             return Ok(Self {
                 listener,
                 upnp_port_mapping: None,
+                bandwidth_limiter,
             });
         } else if socket_addr.is_ipv6() {
             let listener = TcpListener::bind(socket_addr).await?;
             return Ok(Self {
                 listener,
                 upnp_port_mapping: None,
+                bandwidth_limiter,
             });
         }
         anyhow::bail!("invalid address");
@@ -70,6 +75,7 @@ impl Terminable for ConnectionTcpAccepterImpl {
 impl ConnectionTcpAccepter for ConnectionTcpAccepterImpl {
     async fn accept(&self) -> anyhow::Result<(FramedStream, SocketAddr)> {
         let (stream, addr) = self.listener.accept().await?;
+        let stream = self.bandwidth_limiter.wrap(stream, Some(addr.ip()));
         let (reader, writer) = tokio::io::split(stream);
         let stream = FramedStream::new(reader, writer);
         Ok((stream, addr))
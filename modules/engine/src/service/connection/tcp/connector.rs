@@ -1,13 +1,21 @@
+use std::{net::IpAddr, sync::Arc};
+
 use async_trait::async_trait;
 use fast_socks5::client::Socks5Stream;
 use omnius_core_omnikit::model::OmniAddr;
 use tokio::net::TcpStream;
 
-use crate::service::connection::FramedStream;
+use crate::service::connection::{BandwidthLimiter, FramedStream};
 
 pub struct TcpProxyOption {
     pub typ: TcpProxyType,
     pub addr: Option<String>,
+    pub credential: Option<TcpProxyCredential>,
+}
+
+pub struct TcpProxyCredential {
+    pub username: String,
+    pub password: String,
 }
 
 pub enum TcpProxyType {
@@ -15,6 +23,20 @@ pub enum TcpProxyType {
     Socks5,
 }
 
+// 呼び出し側で downcast して、プロキシ起因の失敗を接続先の失敗と区別できるようにする
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("proxy address is not configured")]
+    AddressNotConfigured,
+    #[error("socks5 proxy {proxy_addr} failed to connect to {target}")]
+    Socks5 {
+        proxy_addr: String,
+        target: String,
+        #[source]
+        source: fast_socks5::SocksError,
+    },
+}
+
 #[async_trait]
 pub trait ConnectionTcpConnector {
     async fn connect(&self, addr: &OmniAddr) -> anyhow::Result<FramedStream>;
@@ -22,11 +44,15 @@ pub trait ConnectionTcpConnector {
 
 pub struct ConnectionTcpConnectorImpl {
     proxy_option: TcpProxyOption,
+    bandwidth_limiter: Arc<BandwidthLimiter>,
 }
 
 impl ConnectionTcpConnectorImpl {
-    pub async fn new(proxy_option: TcpProxyOption) -> anyhow::Result<Self> {
-        Ok(Self { proxy_option })
+    pub async fn new(proxy_option: TcpProxyOption, bandwidth_limiter: Arc<BandwidthLimiter>) -> anyhow::Result<Self> {
+        Ok(Self {
+            proxy_option,
+            bandwidth_limiter,
+        })
     }
 }
 
@@ -37,22 +63,85 @@ impl ConnectionTcpConnector for ConnectionTcpConnectorImpl {
             TcpProxyType::None => {
                 let socket_addr = addr.parse_tcp_ip()?;
                 let stream = TcpStream::connect(socket_addr).await?;
+                let stream = self.bandwidth_limiter.wrap(stream, Some(socket_addr.ip()));
                 let (reader, writer) = tokio::io::split(stream);
                 let stream = FramedStream::new(reader, writer);
                 Ok(stream)
             }
             TcpProxyType::Socks5 => {
                 let (host, port) = addr.parse_tcp_host()?;
-                if let Some(proxy_addr) = &self.proxy_option.addr {
-                    let config = fast_socks5::client::Config::default();
-                    let stream = Socks5Stream::connect(proxy_addr.as_str(), host, port, config).await?;
-                    let stream = stream.get_socket();
-                    let (reader, writer) = tokio::io::split(stream);
-                    let stream = FramedStream::new(reader, writer);
-                    return Ok(stream);
-                }
-                anyhow::bail!("failed to connect by socks5: {:?}", addr);
+                // LAN 判定はプロキシではなく接続先で行う
+                let peer_ip = host.parse::<IpAddr>().ok();
+                let Some(proxy_addr) = &self.proxy_option.addr else {
+                    return Err(ProxyError::AddressNotConfigured.into());
+                };
+
+                let target = format!("{}:{}", host, port);
+                let config = fast_socks5::client::Config::default();
+                let res = match &self.proxy_option.credential {
+                    Some(credential) => {
+                        Socks5Stream::connect_with_password(
+                            proxy_addr.as_str(),
+                            host,
+                            port,
+                            credential.username.clone(),
+                            credential.password.clone(),
+                            config,
+                        )
+                        .await
+                    }
+                    None => Socks5Stream::connect(proxy_addr.as_str(), host, port, config).await,
+                };
+                let stream = res.map_err(|source| ProxyError::Socks5 {
+                    proxy_addr: proxy_addr.clone(),
+                    target,
+                    source,
+                })?;
+
+                let stream = stream.get_socket();
+                let stream = self.bandwidth_limiter.wrap(stream, peer_ip);
+                let (reader, writer) = tokio::io::split(stream);
+                let stream = FramedStream::new(reader, writer);
+                Ok(stream)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use testresult::TestResult;
+
+    use omnius_core_omnikit::model::OmniAddr;
+
+    use crate::service::connection::BandwidthLimiter;
+
+    use super::{ConnectionTcpConnector as _, ConnectionTcpConnectorImpl, ProxyError, TcpProxyOption, TcpProxyType};
+
+    #[tokio::test]
+    pub async fn proxy_error_test() -> TestResult {
+        // 待ち受けていないポートをプロキシとして指定する
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let proxy_addr = listener.local_addr()?.to_string();
+        drop(listener);
+
+        let connector = ConnectionTcpConnectorImpl::new(
+            TcpProxyOption {
+                typ: TcpProxyType::Socks5,
+                addr: Some(proxy_addr),
+                credential: None,
+            },
+            Arc::new(BandwidthLimiter::default()),
+        )
+        .await?;
+
+        let Err(e) = connector.connect(&OmniAddr::new("tcp(ip4(127.0.0.1),60000)")).await else {
+            panic!("connect should fail");
+        };
+        assert!(matches!(e.downcast_ref::<ProxyError>(), Some(ProxyError::Socks5 { .. })));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use futures::future::{join_all, select_all};
+
+use omnius_core_base::terminable::Terminable;
+use omnius_core_omnikit::model::OmniAddr;
+
+use crate::service::connection::{BandwidthLimiter, FramedStream};
+
+use super::{ConnectionTcpAccepter, ConnectionTcpAccepterImpl};
+
+#[derive(Debug, Clone)]
+pub struct TcpListenerOption {
+    pub addr: OmniAddr,
+    pub use_upnp: bool,
+}
+
+// 複数の待ち受けアドレスを 1 つの ConnectionTcpAccepter として扱う
+pub struct ConnectionTcpMultiAccepterImpl {
+    accepters: Vec<ConnectionTcpAccepterImpl>,
+}
+
+impl ConnectionTcpMultiAccepterImpl {
+    pub async fn new(options: &[TcpListenerOption], bandwidth_limiter: Arc<BandwidthLimiter>) -> anyhow::Result<Self> {
+        if options.is_empty() {
+            anyhow::bail!("no listener configured");
+        }
+
+        let mut accepters = Vec::with_capacity(options.len());
+        for option in options {
+            accepters.push(ConnectionTcpAccepterImpl::new(&option.addr, option.use_upnp, bandwidth_limiter.clone()).await?);
+        }
+
+        Ok(Self { accepters })
+    }
+}
+
+#[async_trait]
+impl Terminable for ConnectionTcpMultiAccepterImpl {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        for res in join_all(self.accepters.iter().map(|n| n.terminate())).await {
+            res?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ConnectionTcpAccepter for ConnectionTcpMultiAccepterImpl {
+    async fn accept(&self) -> anyhow::Result<(FramedStream, SocketAddr)> {
+        // TcpListener::accept はキャンセル安全なので、選ばれなかった accept を破棄しても接続は失われない
+        let (res, _, _) = select_all(self.accepters.iter().map(|n| n.accept())).await;
+        res
+    }
+
+    async fn get_global_ip_addresses(&self) -> anyhow::Result<Vec<IpAddr>> {
+        let mut res: Vec<IpAddr> = Vec::new();
+        for accepter in self.accepters.iter() {
+            for ip in accepter.get_global_ip_addresses().await? {
+                if !res.contains(&ip) {
+                    res.push(ip);
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use testresult::TestResult;
+
+    use omnius_core_omnikit::model::OmniAddr;
+
+    use crate::service::connection::{
+        BandwidthLimiter, ConnectionTcpAccepter as _, ConnectionTcpConnector as _, ConnectionTcpConnectorImpl, TcpProxyOption, TcpProxyType,
+    };
+
+    use super::{ConnectionTcpMultiAccepterImpl, TcpListenerOption};
+
+    #[tokio::test]
+    #[ignore]
+    async fn simple_test() -> TestResult {
+        let accepter = ConnectionTcpMultiAccepterImpl::new(
+            &[
+                TcpListenerOption {
+                    addr: OmniAddr::create_tcp("127.0.0.1".parse()?, 50010),
+                    use_upnp: false,
+                },
+                TcpListenerOption {
+                    addr: OmniAddr::create_tcp("127.0.0.1".parse()?, 50011),
+                    use_upnp: false,
+                },
+            ],
+            Arc::new(BandwidthLimiter::default()),
+        )
+        .await?;
+        let connector = ConnectionTcpConnectorImpl::new(
+            TcpProxyOption {
+                typ: TcpProxyType::None,
+                addr: None,
+                credential: None,
+            },
+            Arc::new(BandwidthLimiter::default()),
+        )
+        .await?;
+
+        for port in [50010, 50011] {
+            let _connected_stream = connector.connect(&OmniAddr::create_tcp("127.0.0.1".parse()?, port)).await?;
+            let (_accepted_stream, _) = accepter.accept().await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    future::Future as _,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+use omnius_core_base::net::Reachable as _;
+
+use super::BandwidthScheduleRule;
+
+// 単位は bytes/sec。None は無制限
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthOption {
+    pub max_upload_bytes_per_sec: Option<u64>,
+    pub max_download_bytes_per_sec: Option<u64>,
+    pub max_session_upload_bytes_per_sec: Option<u64>,
+    pub max_session_download_bytes_per_sec: Option<u64>,
+    pub exempt_lan: bool,
+    // 最初に一致した時間帯の上限が、全体の上限の代わりに使われる
+    pub schedules: Vec<BandwidthScheduleRule>,
+}
+
+// 起動してからの累計。LAN 内の通信も含む
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficTotals {
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+}
+
+#[derive(Default)]
+struct TrafficCounter {
+    uploaded_bytes: AtomicU64,
+    downloaded_bytes: AtomicU64,
+}
+
+#[derive(Default)]
+pub struct BandwidthLimiter {
+    option: BandwidthOption,
+    upload: Option<Arc<RateLimiter>>,
+    download: Option<Arc<RateLimiter>>,
+    traffic: Arc<TrafficCounter>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(option: BandwidthOption) -> Self {
+        // 時間帯によって制限が掛かる場合があるので、スケジュールがあれば常に用意しておく
+        let scheduled = !option.schedules.is_empty();
+        let upload = (scheduled || option.max_upload_bytes_per_sec.is_some()).then(|| Arc::new(RateLimiter::new(option.max_upload_bytes_per_sec)));
+        let download =
+            (scheduled || option.max_download_bytes_per_sec.is_some()).then(|| Arc::new(RateLimiter::new(option.max_download_bytes_per_sec)));
+        Self {
+            option,
+            upload,
+            download,
+            traffic: Arc::new(TrafficCounter::default()),
+        }
+    }
+
+    pub fn traffic(&self) -> TrafficTotals {
+        TrafficTotals {
+            uploaded_bytes: self.traffic.uploaded_bytes.load(Ordering::Relaxed),
+            downloaded_bytes: self.traffic.downloaded_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn has_schedules(&self) -> bool {
+        !self.option.schedules.is_empty()
+    }
+
+    // now はローカル時刻
+    pub fn apply_schedule(&self, now: &NaiveDateTime) {
+        let (upload, download) = match self.option.schedules.iter().find(|n| n.window.contains(now)) {
+            Some(rule) => (rule.max_upload_bytes_per_sec, rule.max_download_bytes_per_sec),
+            None => (self.option.max_upload_bytes_per_sec, self.option.max_download_bytes_per_sec),
+        };
+        if let Some(limiter) = self.upload.as_ref() {
+            limiter.set_rate(upload);
+        }
+        if let Some(limiter) = self.download.as_ref() {
+            limiter.set_rate(download);
+        }
+    }
+
+    // 全体の上限と、セッション毎の上限の両方を適用する
+    pub fn wrap<S>(&self, stream: S, peer_ip: Option<IpAddr>) -> ThrottledStream<S> {
+        if self.option.exempt_lan && peer_ip.is_some_and(|n| is_lan(&n)) {
+            return ThrottledStream::new(stream, vec![], vec![], self.traffic.clone());
+        }
+
+        let mut read_limiters = vec![];
+        read_limiters.extend(self.download.clone());
+        read_limiters.extend(
+            self.option
+                .max_session_download_bytes_per_sec
+                .map(|n| Arc::new(RateLimiter::new(Some(n)))),
+        );
+
+        let mut write_limiters = vec![];
+        write_limiters.extend(self.upload.clone());
+        write_limiters.extend(self.option.max_session_upload_bytes_per_sec.map(|n| Arc::new(RateLimiter::new(Some(n)))));
+
+        ThrottledStream::new(stream, read_limiters, write_limiters, self.traffic.clone())
+    }
+}
+
+fn is_lan(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !ip.is_reachable(),
+        IpAddr::V6(ip) => !ip.is_reachable(),
+    }
+}
+
+// トークンバケット。最大で 1 秒分まで貯まる。上限が None の間は制限しない
+struct RateLimiter {
+    state: Mutex<RateLimiterState>,
+}
+
+struct RateLimiterState {
+    bytes_per_sec: Option<f64>,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_sec: Option<u64>) -> Self {
+        let bytes_per_sec = bytes_per_sec.map(|n| n.max(1) as f64);
+        Self {
+            state: Mutex::new(RateLimiterState {
+                bytes_per_sec,
+                tokens: bytes_per_sec.unwrap_or(0.0),
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn set_rate(&self, bytes_per_sec: Option<u64>) {
+        let bytes_per_sec = bytes_per_sec.map(|n| n.max(1) as f64);
+        let mut state = self.state.lock();
+        if state.bytes_per_sec == bytes_per_sec {
+            return;
+        }
+        // 無制限から切り替わった場合は 1 秒分が貯まった状態から始める
+        let tokens = match (state.bytes_per_sec, bytes_per_sec) {
+            (_, None) => 0.0,
+            (None, Some(n)) => n,
+            (Some(_), Some(n)) => state.tokens.min(n),
+        };
+        state.bytes_per_sec = bytes_per_sec;
+        state.tokens = tokens;
+        state.updated_at = Instant::now();
+    }
+
+    // 利用可能なバイト数を返す。無い場合は待つべき時間を返す
+    fn available(&self) -> Result<usize, Duration> {
+        let mut state = self.state.lock();
+        let Some(bytes_per_sec) = state.bytes_per_sec else {
+            return Ok(usize::MAX);
+        };
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.updated_at).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * bytes_per_sec).min(bytes_per_sec);
+        state.updated_at = now;
+
+        if state.tokens >= 1.0 {
+            return Ok(state.tokens as usize);
+        }
+        Err(Duration::from_secs_f64((1.0 - state.tokens) / bytes_per_sec))
+    }
+
+    // 他のセッションとの競合で一時的に負になることがあるが、次の補充で解消される
+    fn consume(&self, n: usize) {
+        let mut state = self.state.lock();
+        if state.bytes_per_sec.is_some() {
+            state.tokens -= n as f64;
+        }
+    }
+}
+
+pub struct ThrottledStream<S> {
+    inner: S,
+    read_limiters: Vec<Arc<RateLimiter>>,
+    write_limiters: Vec<Arc<RateLimiter>>,
+    read_sleep: Option<Pin<Box<Sleep>>>,
+    write_sleep: Option<Pin<Box<Sleep>>>,
+    traffic: Arc<TrafficCounter>,
+}
+
+impl<S> ThrottledStream<S> {
+    fn new(inner: S, read_limiters: Vec<Arc<RateLimiter>>, write_limiters: Vec<Arc<RateLimiter>>, traffic: Arc<TrafficCounter>) -> Self {
+        Self {
+            inner,
+            read_limiters,
+            write_limiters,
+            read_sleep: None,
+            write_sleep: None,
+            traffic,
+        }
+    }
+}
+
+fn poll_permit(limiters: &[Arc<RateLimiter>], sleep: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>, max: usize) -> Poll<usize> {
+    if limiters.is_empty() || max == 0 {
+        return Poll::Ready(max);
+    }
+
+    loop {
+        if let Some(s) = sleep.as_mut() {
+            ready!(s.as_mut().poll(cx));
+            *sleep = None;
+        }
+
+        let mut n = max;
+        let mut wait = Duration::ZERO;
+        for limiter in limiters {
+            match limiter.available() {
+                Ok(v) => n = n.min(v),
+                Err(d) => wait = wait.max(d),
+            }
+        }
+        if wait.is_zero() {
+            return Poll::Ready(n);
+        }
+        *sleep = Some(Box::pin(tokio::time::sleep(wait)));
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read_limiters.is_empty() {
+            let before = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            let filled = buf.filled().len() - before;
+            this.traffic.downloaded_bytes.fetch_add(filled as u64, Ordering::Relaxed);
+            return Poll::Ready(Ok(()));
+        }
+
+        let n = ready!(poll_permit(&this.read_limiters, &mut this.read_sleep, cx, buf.remaining()));
+        let filled = {
+            let mut sub = ReadBuf::new(buf.initialize_unfilled_to(n));
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut sub))?;
+            sub.filled().len()
+        };
+        buf.advance(filled);
+
+        for limiter in this.read_limiters.iter() {
+            limiter.consume(filled);
+        }
+        this.traffic.downloaded_bytes.fetch_add(filled as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(poll_permit(&this.write_limiters, &mut this.write_sleep, cx, buf.len()));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..n]))?;
+
+        for limiter in this.write_limiters.iter() {
+            limiter.consume(written);
+        }
+        this.traffic.uploaded_bytes.fetch_add(written as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::NaiveDateTime;
+    use testresult::TestResult;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    use crate::service::connection::{BandwidthScheduleRule, ScheduleWindow};
+
+    use super::{BandwidthLimiter, BandwidthOption};
+
+    #[tokio::test]
+    pub async fn throttle_test() -> TestResult {
+        let limiter = BandwidthLimiter::new(BandwidthOption {
+            max_session_upload_bytes_per_sec: Some(1024),
+            exempt_lan: true,
+            ..Default::default()
+        });
+
+        // 最初の 1 秒分はすぐに送れるので、残りの 1024 バイト分だけ待たされる
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let mut client = limiter.wrap(client, Some("203.0.113.1".parse()?));
+        let start = tokio::time::Instant::now();
+        client.write_all(&[0; 2048]).await?;
+        assert!(start.elapsed() >= Duration::from_millis(900));
+
+        let mut buf = vec![0; 2048];
+        server.read_exact(&mut buf).await?;
+
+        // LAN 内の通信は制限しない
+        let (client, _server) = tokio::io::duplex(64 * 1024);
+        let mut client = limiter.wrap(client, Some("192.168.0.1".parse()?));
+        let start = tokio::time::Instant::now();
+        client.write_all(&[0; 4096]).await?;
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // 制限の有無に関わらず累計に含まれる
+        assert_eq!(limiter.traffic().uploaded_bytes, 2048 + 4096);
+        assert_eq!(limiter.traffic().downloaded_bytes, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn schedule_test() -> TestResult {
+        let limiter = BandwidthLimiter::new(BandwidthOption {
+            schedules: vec![BandwidthScheduleRule {
+                window: ScheduleWindow::parse("Mon 09:00-18:00")?,
+                max_upload_bytes_per_sec: Some(1024),
+                max_download_bytes_per_sec: None,
+            }],
+            ..Default::default()
+        });
+
+        // 時間帯の外では全体の上限 (無制限) に従う
+        limiter.apply_schedule(&NaiveDateTime::parse_from_str("2024-01-01 08:00", "%Y-%m-%d %H:%M")?);
+        let (client, _server) = tokio::io::duplex(64 * 1024);
+        let mut client = limiter.wrap(client, None);
+        let start = tokio::time::Instant::now();
+        client.write_all(&[0; 8192]).await?;
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        limiter.apply_schedule(&NaiveDateTime::parse_from_str("2024-01-01 09:00", "%Y-%m-%d %H:%M")?);
+        let (client, _server) = tokio::io::duplex(64 * 1024);
+        let mut client = limiter.wrap(client, None);
+        let start = tokio::time::Instant::now();
+        client.write_all(&[0; 2048]).await?;
+        assert!(start.elapsed() >= Duration::from_millis(900));
+
+        Ok(())
+    }
+}
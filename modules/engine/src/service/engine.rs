@@ -1,6 +1,8 @@
 mod file;
 mod node;
+mod shutdown;
 
 #[allow(unused)]
 pub use file::*;
 pub use node::*;
+pub use shutdown::*;
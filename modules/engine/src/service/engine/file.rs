@@ -3,5 +3,8 @@ mod file_publisher;
 mod file_publisher_repo;
 mod model;
 mod session_status;
+mod task_scrubber;
 
+pub use file_publisher::*;
+pub use file_publisher_repo::*;
 pub use model::*;
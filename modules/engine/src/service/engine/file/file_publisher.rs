@@ -1,46 +1,213 @@
-use std::sync::Arc;
+use std::{collections::HashSet, path::Path, sync::Arc};
 
 use async_trait::async_trait;
 use chrono::Utc;
 use futures::FutureExt as _;
+use parking_lot::Mutex;
 use tokio::{
-    io::{AsyncRead, AsyncReadExt},
+    io::{AsyncRead, AsyncReadExt as _},
     sync::Mutex as TokioMutex,
     task::JoinHandle,
 };
+use tokio_util::bytes::Bytes;
 
 use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
-use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType};
+use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType, OmniSigner};
+use omnius_core_rocketpack::RocketMessage as _;
 
-use crate::service::storage::BlobStorage;
+use crate::{
+    model::{AssetKey, FileManifest, FileManifestLayer},
+    service::storage::{BlobStorage, DiskSpaceGate},
+};
+
+use super::{file_publisher_repo::FilePublisherRepo, FileSource, FileSourceMatch, PublishedBlock};
 
-use super::{file_publisher_repo::FilePublisherRepo, PublishedBlock};
+const FILE_ASSET_KEY_TYPE: &str = "file";
 
 #[allow(unused)]
 pub struct FilePublisher {
     file_publisher_repo: Arc<FilePublisherRepo>,
     blob_storage: Arc<TokioMutex<BlobStorage>>,
+    disk_space_gate: DiskSpaceGate,
+    read_only: bool,
+    // 他のノードへ配布する、公開中のファイルのキー
+    push_asset_keys: Arc<Mutex<HashSet<AssetKey>>>,
 
     clock: Arc<dyn Clock<Utc> + Send + Sync>,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
     join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishPlan {
+    AlreadyPublished(OmniHash),
+    Resume(String),
+    Start(String),
+}
+
 #[allow(unused)]
 impl FilePublisher {
-    pub async fn publish_file<R>(&self, reader: &mut R, file_name: &str, block_size: u64) -> anyhow::Result<Self>
-    where
-        R: AsyncRead + Unpin,
-    {
-        let mut buf = vec![0; block_size as usize];
-        loop {
-            let n = reader.read_exact(&mut buf).await?;
-            if n == 0 {
-                break;
+    pub async fn new(
+        file_publisher_repo: Arc<FilePublisherRepo>,
+        blob_storage: Arc<TokioMutex<BlobStorage>>,
+        clock: Arc<dyn Clock<Utc> + Send + Sync>,
+        sleeper: Arc<dyn Sleeper + Send + Sync>,
+        read_only: bool,
+        disk_space_gate: DiskSpaceGate,
+    ) -> anyhow::Result<Self> {
+        let push_asset_keys: HashSet<AssetKey> = file_publisher_repo
+            .get_published_files()
+            .await?
+            .into_iter()
+            .map(|n| Self::gen_asset_key(&n.root_hash))
+            .collect();
+
+        Ok(Self {
+            file_publisher_repo,
+            blob_storage,
+            disk_space_gate,
+            read_only,
+            push_asset_keys: Arc::new(Mutex::new(push_asset_keys)),
+            clock,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+        })
+    }
+
+    // 同じファイルを二重に符号化しないよう、公開済みであれば何もせず、取り込み中であれば続きから再開する
+    // force の場合は取り込み中のものを破棄し、最初からやり直す
+    pub async fn plan_publish(&self, path: &Path, force: bool) -> anyhow::Result<PublishPlan> {
+        self.ensure_writable()?;
+
+        let source = FileSource::from_path(path).await?;
+
+        match self.file_publisher_repo.find_file_source(&source).await? {
+            Some(FileSourceMatch::Committed(root_hash)) if !force => return Ok(PublishPlan::AlreadyPublished(root_hash)),
+            Some(FileSourceMatch::Uncommitted(id)) if !force => return Ok(PublishPlan::Resume(id)),
+            Some(FileSourceMatch::Uncommitted(id)) => self.discard_uncommitted(&id).await?,
+            _ => {}
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        self.file_publisher_repo.insert_uncommitted_file(&id, &source).await?;
+
+        Ok(PublishPlan::Start(id))
+    }
+
+    // 公開済みのファイルについて、他のノードへ渡すためのマニフェストを作る
+    pub async fn export_manifest(&self, root_hash: &OmniHash, file_name: &str, signer: Option<&OmniSigner>) -> anyhow::Result<FileManifest> {
+        let Some(file) = self.file_publisher_repo.get_published_file(root_hash, file_name).await? else {
+            anyhow::bail!("file not published: {}", root_hash);
+        };
+
+        let layers = self
+            .file_publisher_repo
+            .get_merkle_layers(root_hash)
+            .await?
+            .into_iter()
+            .map(|(depth, block_count)| FileManifestLayer { depth, block_count })
+            .collect();
+
+        let manifest = FileManifest {
+            root_hash: file.root_hash,
+            file_name: file.file_name,
+            block_size: file.block_size.try_into()?,
+            layers,
+            property: file.property,
+            cert: None,
+        };
+
+        match signer {
+            Some(signer) => manifest.sign(signer),
+            None => Ok(manifest),
+        }
+    }
+
+    // ファイルをブロックに分けて取り込み、公開する。公開済みであればその root_hash を返す
+    // 段 0 はファイルの内容、その上の段は下の段のブロックのハッシュを並べたもので、最上段の 1 ブロックのハッシュが root_hash になる
+    // 途中で停止した場合は、次の呼び出しで書き込み済みのブロックを再利用して続きから取り込む
+    pub async fn publish_file(&self, path: &Path, file_name: &str, block_size: u64, force: bool) -> anyhow::Result<OmniHash> {
+        if block_size == 0 {
+            anyhow::bail!("block_size must be positive");
+        }
+
+        let id = match self.plan_publish(path, force).await? {
+            PublishPlan::AlreadyPublished(root_hash) => return Ok(root_hash),
+            PublishPlan::Resume(id) | PublishPlan::Start(id) => id,
+        };
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let blocks = self.import_bytes(&id, &mut file, block_size, 0).await?;
+        if blocks.is_empty() {
+            self.discard_uncommitted(&id).await?;
+            anyhow::bail!("empty file: {}", path.display());
+        }
+
+        let mut layers: Vec<Vec<PublishedBlock>> = vec![blocks];
+        while let Some(lower) = layers.last().filter(|n| n.len() > 1) {
+            let value = Self::encode_block_hashes(lower)?;
+            let upper = self.import_bytes(&id, &mut value.as_slice(), block_size, layers.len() as u32).await?;
+            if upper.len() >= lower.len() {
+                self.discard_uncommitted(&id).await?;
+                anyhow::bail!("block_size is too small: {}", block_size);
             }
-            self.blob_storage.lock().await.put(file_name.as_bytes(), &buf[..n])?;
+            layers.push(upper);
         }
-        todo!()
+        let root_hash = layers.last().and_then(|n| n.first()).map(|n| n.block_hash.clone()).unwrap_or_default();
+
+        let blocks: Vec<PublishedBlock> = layers.into_iter().flatten().collect();
+        self.commit_blocks(&id, &root_hash, &blocks).await?;
+        self.file_publisher_repo
+            .commit_uncommitted(&id, &root_hash, file_name, block_size, &blocks)
+            .await?;
+
+        let prefix = format!("U/{}/", id);
+        self.blob_storage.lock().await.delete_prefix(prefix.as_bytes())?;
+
+        self.push_asset_keys.lock().insert(Self::gen_asset_key(&root_hash));
+
+        Ok(root_hash)
+    }
+
+    // NodeFinder から、配布するキーとして問い合わせられる
+    pub fn get_push_asset_keys(&self) -> Vec<AssetKey> {
+        self.push_asset_keys.lock().iter().cloned().collect()
+    }
+
+    // 取り込み中のブロックを公開済みの場所へ写し、root_hash からの参照を加える。記録より先に行い、記録されたファイルのブロックが欠けないようにする
+    // 同じ内容のブロックは公開済みのファイル間で共有するので、既にあれば参照を加えるだけにする
+    async fn commit_blocks(&self, id: &str, root_hash: &OmniHash, blocks: &[PublishedBlock]) -> anyhow::Result<()> {
+        let root_hash = root_hash.to_string();
+        let mut committed: HashSet<&OmniHash> = HashSet::new();
+        for block in blocks {
+            if !committed.insert(&block.block_hash) {
+                continue;
+            }
+
+            // 空き容量が不足している間は書き込みを待つ。待つ間は他の処理を妨げないよう、ロックを取る前に待つ
+            self.disk_space_gate.clone().wait_until_available().await?;
+
+            let committed_path = Self::gen_committed_block_path(&block.block_hash);
+            let blob_storage = self.blob_storage.lock().await;
+            if blob_storage.get_meta(committed_path.as_bytes())?.is_none() {
+                let uncommitted_path = Self::gen_uncommitted_block_path(id, &block.block_hash);
+                let Some(value) = blob_storage.get_async(uncommitted_path.as_bytes()).await? else {
+                    anyhow::bail!("uncommitted block not found: {}", block.block_hash);
+                };
+                blob_storage.put(committed_path.as_bytes(), &value)?;
+            }
+            blob_storage.add_ref(committed_path.as_bytes(), root_hash.as_bytes(), self.clock.now())?;
+        }
+        Ok(())
+    }
+
+    // 上の段のブロックの内容として、下の段のブロックのハッシュを順に並べる
+    fn encode_block_hashes(blocks: &[PublishedBlock]) -> anyhow::Result<Vec<u8>> {
+        let mut res = Vec::new();
+        for block in blocks {
+            res.extend_from_slice(&block.block_hash.export()?);
+        }
+        Ok(res)
     }
 
     async fn import_bytes<R>(&self, id: &str, reader: &mut R, max_block_size: u64, depth: u32) -> anyhow::Result<Vec<PublishedBlock>>
@@ -50,9 +217,12 @@ impl FilePublisher {
         let mut blocks: Vec<PublishedBlock> = Vec::new();
         let mut index = 0;
 
+        // 前回の実行で書き込みを終えているブロック
+        let mut completed_blocks = self.file_publisher_repo.get_uncommitted_blocks(id, depth).await?;
+
         let mut buf = vec![0; max_block_size as usize];
         loop {
-            let size = reader.read_exact(&mut buf).await?;
+            let size = Self::read_block(reader, &mut buf).await?;
             if size == 0 {
                 break;
             }
@@ -66,28 +236,149 @@ impl FilePublisher {
                 depth,
                 index,
             };
+
+            let resumed = match completed_blocks.get(index as usize) {
+                Some(completed) if completed.block_hash == block_hash => self.verify_uncommitted_block(id, &block_hash).await?,
+                Some(_) => {
+                    // 元のデータが変わっているので、ここから先の進捗は使えない
+                    self.discard_uncommitted_blocks(id, depth, index, &completed_blocks).await?;
+                    completed_blocks.truncate(index as usize);
+                    false
+                }
+                None => false,
+            };
+
+            if !resumed {
+                self.write_uncommitted_block(id, &block_hash, block).await?;
+                self.file_publisher_repo.insert_uncommitted_block(id, &published_block).await?;
+            }
+
             blocks.push(published_block);
 
             index += 1;
+        }
 
-            self.write_uncommitted_block(id, &block_hash, block).await?;
+        // 元のデータが短くなっている場合は、余った進捗を捨てる
+        if (index as usize) < completed_blocks.len() {
+            self.discard_uncommitted_blocks(id, depth, index, &completed_blocks).await?;
         }
 
         Ok(blocks)
     }
 
+    // read_exact は末尾の半端なブロックで失敗するので、埋まるか EOF に達するまで読む
+    async fn read_block<R>(reader: &mut R, buf: &mut [u8]) -> anyhow::Result<usize>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = reader.read(&mut buf[filled..]).await?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        Ok(filled)
+    }
+
+    // 公開を取り下げ、記録を削除してからブロックの参照を外す。公開されていなければ false を返す
+    // 同じ内容のブロックを他の公開済みファイルが参照している間は、ブロック自体は残る
+    pub async fn unpublish(&self, root_hash: &OmniHash) -> anyhow::Result<bool> {
+        self.ensure_writable()?;
+
+        if !self.file_publisher_repo.file_exists(root_hash.clone()).await? {
+            return Ok(false);
+        }
+
+        // 記録より先に配布を止め、取り下げたキーを他のノードへ伝え続けないようにする
+        self.push_asset_keys.lock().remove(&Self::gen_asset_key(root_hash));
+
+        let block_hashes = self.file_publisher_repo.delete_published_file(root_hash).await?;
+
+        let root_hash = root_hash.to_string();
+        let blob_storage = self.blob_storage.lock().await;
+        for block_hash in block_hashes {
+            let path = Self::gen_committed_block_path(&block_hash);
+            blob_storage.remove_ref(path.as_bytes(), root_hash.as_bytes())?;
+        }
+
+        Ok(true)
+    }
+
+    // 要求された root_hash に含まれないブロックでも、同じ内容のものを他の公開済みファイルが持っていればそれを返す
+    pub async fn read_committed_block(&self, root_hash: &OmniHash, block_hash: &OmniHash) -> anyhow::Result<Option<Bytes>> {
+        if self.file_publisher_repo.find_block_root_hash(root_hash, block_hash).await?.is_none() {
+            return Ok(None);
+        }
+
+        let path = Self::gen_committed_block_path(block_hash);
+        self.blob_storage.lock().await.get_async(path.as_bytes()).await
+    }
+
+    async fn verify_uncommitted_block(&self, id: &str, block_hash: &OmniHash) -> anyhow::Result<bool> {
+        let path = Self::gen_uncommitted_block_path(id, block_hash);
+        let Some(value) = self.blob_storage.lock().await.get_async(path.as_bytes()).await? else {
+            return Ok(false);
+        };
+        Ok(OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &value) == *block_hash)
+    }
+
+    async fn discard_uncommitted_blocks(&self, id: &str, depth: u32, from_index: u32, completed_blocks: &[PublishedBlock]) -> anyhow::Result<()> {
+        self.file_publisher_repo.delete_uncommitted_blocks(id, depth, from_index).await?;
+
+        // 手前で使われているブロックと同じ内容のものは残す
+        let (kept, discarded) = completed_blocks.split_at((from_index as usize).min(completed_blocks.len()));
+        let blob_storage = self.blob_storage.lock().await;
+        for block in discarded {
+            if kept.iter().any(|n| n.block_hash == block.block_hash) {
+                continue;
+            }
+            let path = Self::gen_uncommitted_block_path(id, &block.block_hash);
+            blob_storage.delete(path.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    // 取り込みを中断したファイルの、書き込み済みのブロックと進捗を全て削除する
+    async fn discard_uncommitted(&self, id: &str) -> anyhow::Result<()> {
+        self.file_publisher_repo.delete_uncommitted(id).await?;
+        let prefix = format!("U/{}/", id);
+        self.blob_storage.lock().await.delete_prefix(prefix.as_bytes())?;
+        Ok(())
+    }
+
     async fn write_uncommitted_block(&self, id: &str, block_hash: &OmniHash, value: &[u8]) -> anyhow::Result<()> {
+        // 空き容量が不足している間は書き込みを待つ
+        self.disk_space_gate.clone().wait_until_available().await?;
+
         let path = Self::gen_uncommitted_block_path(id, block_hash);
         self.blob_storage.lock().await.put(path.as_bytes(), value)?;
         Ok(())
     }
 
+    // 読み取り専用の構成では、公開済みのファイルを配るだけにする
+    fn ensure_writable(&self) -> anyhow::Result<()> {
+        if self.read_only {
+            anyhow::bail!("publishing is disabled in read-only mode");
+        }
+        Ok(())
+    }
+
+    fn gen_asset_key(root_hash: &OmniHash) -> AssetKey {
+        AssetKey {
+            typ: FILE_ASSET_KEY_TYPE.to_string(),
+            hash: root_hash.clone(),
+        }
+    }
+
     fn gen_uncommitted_block_path(id: &str, block_hash: &OmniHash) -> String {
         format!("U/{}/{}", id, block_hash)
     }
 
-    fn gen_committed_block_path(root_hash: &OmniHash, block_hash: &OmniHash) -> String {
-        format!("C/{}/{}", root_hash, block_hash)
+    fn gen_committed_block_path(block_hash: &OmniHash) -> String {
+        format!("C/{}", block_hash)
     }
 }
 
@@ -103,3 +394,278 @@ impl Terminable for FilePublisher {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use chrono::DateTime;
+    use futures::StreamExt as _;
+    use testresult::TestResult;
+    use tokio::sync::Mutex as TokioMutex;
+
+    use omnius_core_base::{clock::FakeClockUtc, sleeper::SleeperImpl};
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
+
+    use crate::{
+        model::FileManifestLayer,
+        service::storage::{BlobStorage, BlobStorageOption, DiskSpaceWatchdogOption, TaskDiskSpaceWatchdog},
+    };
+
+    use super::{FilePublisher, FilePublisherRepo, PublishPlan};
+
+    #[tokio::test]
+    pub async fn read_only_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), true).await?;
+
+        let file_path = dir.path().join("a.txt");
+        tokio::fs::write(&file_path, b"a").await?;
+
+        assert!(publisher.plan_publish(&file_path, false).await.is_err());
+        let root_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"a");
+        assert!(publisher.unpublish(&root_hash).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn plan_publish_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), false).await?;
+
+        let value: Vec<u8> = (0..3_000).map(|n| (n % 239) as u8).collect();
+        let file_path = dir.path().join("a.bin");
+        tokio::fs::write(&file_path, &value).await?;
+
+        let PublishPlan::Start(id) = publisher.plan_publish(&file_path, false).await? else {
+            panic!("unexpected plan");
+        };
+        publisher.import_bytes(&id, &mut &value[..1024], 1024, 0).await?;
+
+        // 取り込み中のものは続きから再開する
+        assert_eq!(publisher.plan_publish(&file_path, false).await?, PublishPlan::Resume(id.clone()));
+
+        // force の場合は取り込み中のものを破棄してやり直す
+        let PublishPlan::Start(forced_id) = publisher.plan_publish(&file_path, true).await? else {
+            panic!("unexpected plan");
+        };
+        assert_ne!(forced_id, id);
+        assert!(publisher.file_publisher_repo.get_uncommitted_blocks(&id, 0).await?.is_empty());
+        let keys: Vec<_> = publisher
+            .blob_storage
+            .lock()
+            .await
+            .keys(Some(format!("U/{}/", id).as_bytes()))
+            .collect()
+            .await;
+        assert!(keys.is_empty());
+
+        // 公開済みのものは取り込み直さない
+        let root_hash = publisher.publish_file(&file_path, "a.bin", 1024, false).await?;
+        assert_eq!(publisher.plan_publish(&file_path, false).await?, PublishPlan::AlreadyPublished(root_hash));
+        assert!(matches!(publisher.plan_publish(&file_path, true).await?, PublishPlan::Start(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn export_manifest_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), false).await?;
+
+        let value: Vec<u8> = (0..3_000).map(|n| (n % 233) as u8).collect();
+        let file_path = dir.path().join("a.bin");
+        tokio::fs::write(&file_path, &value).await?;
+        let root_hash = publisher.publish_file(&file_path, "a.bin", 1024, false).await?;
+
+        let manifest = publisher.export_manifest(&root_hash, "a.bin", None).await?;
+        assert_eq!(manifest.root_hash, root_hash);
+        assert_eq!(manifest.file_name, "a.bin");
+        assert_eq!(manifest.block_size, 1024);
+        assert_eq!(
+            manifest.layers,
+            vec![
+                FileManifestLayer { depth: 0, block_count: 3 },
+                FileManifestLayer { depth: 1, block_count: 1 },
+            ]
+        );
+        assert!(manifest.cert.is_none());
+
+        let author = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author")?;
+        let author_public_key = author.sign(b"test")?.public_key;
+        let signed = publisher.export_manifest(&root_hash, "a.bin", Some(&author)).await?;
+        assert!(signed.verify(Some(&author_public_key)).is_ok());
+
+        // 公開していないファイル名では作れない
+        assert!(publisher.export_manifest(&root_hash, "b.bin", None).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn read_committed_block_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), false).await?;
+
+        let value_a: Vec<u8> = vec![1; 1024];
+        let value_b: Vec<u8> = vec![2; 1024];
+        let path_a = dir.path().join("a.bin");
+        let path_b = dir.path().join("b.bin");
+        tokio::fs::write(&path_a, &value_a).await?;
+        tokio::fs::write(&path_b, &value_b).await?;
+
+        let root_a = publisher.publish_file(&path_a, "a.bin", 1024, false).await?;
+        publisher.publish_file(&path_b, "b.bin", 1024, false).await?;
+
+        // 要求された root_hash に含まれなくても、他の公開済みファイルのブロックであれば返す
+        let hash_b = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &value_b);
+        assert_eq!(
+            publisher.read_committed_block(&root_a, &hash_b).await?.as_deref(),
+            Some(value_b.as_slice())
+        );
+
+        // どの公開済みファイルにも含まれないブロックは返さない
+        let unknown_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"unknown");
+        assert_eq!(publisher.read_committed_block(&root_a, &unknown_hash).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn publish_file_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), false).await?;
+
+        let value: Vec<u8> = (0..10_000).map(|n| (n % 251) as u8).collect();
+        let file_path = dir.path().join("a.bin");
+        tokio::fs::write(&file_path, &value).await?;
+
+        let root_hash = publisher.publish_file(&file_path, "a.bin", 1024, false).await?;
+
+        // 10 ブロックのハッシュは 1 ブロックに収まる
+        assert_eq!(publisher.file_publisher_repo.get_merkle_layers(&root_hash).await?, vec![(0, 10), (1, 1)]);
+        assert!(publisher.file_publisher_repo.get_published_file(&root_hash, "a.bin").await?.is_some());
+
+        let block_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &value[..1024]);
+        let block = publisher.read_committed_block(&root_hash, &block_hash).await?;
+        assert_eq!(block.as_deref(), Some(&value[..1024]));
+
+        // 公開済みのブロックは内容のハッシュで保存され、root_hash から参照される
+        let meta = publisher.blob_storage.lock().await.get_meta(format!("C/{}", block_hash).as_bytes())?;
+        assert_eq!(meta.map(|n| n.ref_count()), Some(1));
+
+        // 取り込み中のブロックは残らない
+        let keys: Vec<_> = publisher.blob_storage.lock().await.keys(Some(b"U/")).collect().await;
+        assert!(keys.is_empty());
+
+        // 公開済みのものは取り込み直さない
+        assert_eq!(publisher.publish_file(&file_path, "a.bin", 1024, false).await?, root_hash);
+
+        let empty_path = dir.path().join("empty.bin");
+        tokio::fs::write(&empty_path, b"").await?;
+        assert!(publisher.publish_file(&empty_path, "empty.bin", 1024, false).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn resume_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), false).await?;
+
+        let value: Vec<u8> = (0..5_000).map(|n| (n % 241) as u8).collect();
+        let file_path = dir.path().join("a.bin");
+        tokio::fs::write(&file_path, &value).await?;
+
+        // 段 0 の途中まで取り込んだ状態で止まったものとする
+        let PublishPlan::Start(id) = publisher.plan_publish(&file_path, false).await? else {
+            panic!("unexpected plan");
+        };
+        publisher.import_bytes(&id, &mut &value[..2048], 1024, 0).await?;
+        assert_eq!(publisher.plan_publish(&file_path, false).await?, PublishPlan::Resume(id.clone()));
+
+        let root_hash = publisher.publish_file(&file_path, "a.bin", 1024, false).await?;
+
+        // 最初から取り込んだ場合と同じ結果になる
+        let other_dir = tempfile::tempdir()?;
+        let other = create_file_publisher(other_dir.path(), false).await?;
+        let other_path = other_dir.path().join("a.bin");
+        tokio::fs::write(&other_path, &value).await?;
+        assert_eq!(other.publish_file(&other_path, "a.bin", 1024, false).await?, root_hash);
+
+        assert!(publisher.file_publisher_repo.get_uncommitted_blocks(&id, 0).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn unpublish_shared_block_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let publisher = create_file_publisher(dir.path(), false).await?;
+
+        // 先頭のブロックだけが同じ内容の 2 つのファイル
+        let shared: Vec<u8> = vec![1; 1024];
+        let value_a: Vec<u8> = shared.iter().copied().chain(vec![2; 1024]).collect();
+        let value_b: Vec<u8> = shared.iter().copied().chain(vec![3; 1024]).collect();
+        let path_a = dir.path().join("a.bin");
+        let path_b = dir.path().join("b.bin");
+        tokio::fs::write(&path_a, &value_a).await?;
+        tokio::fs::write(&path_b, &value_b).await?;
+
+        let root_a = publisher.publish_file(&path_a, "a.bin", 1024, false).await?;
+        let root_b = publisher.publish_file(&path_b, "b.bin", 1024, false).await?;
+        assert_eq!(publisher.get_push_asset_keys().len(), 2);
+
+        let shared_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &shared);
+        let only_a_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &value_a[1024..]);
+
+        assert!(publisher.unpublish(&root_a).await?);
+        assert!(!publisher.unpublish(&root_a).await?);
+
+        // 共有していたブロックは残り、取り下げたファイルだけのブロックは消える
+        assert_eq!(
+            publisher.read_committed_block(&root_b, &shared_hash).await?.as_deref(),
+            Some(shared.as_slice())
+        );
+        assert_eq!(publisher.read_committed_block(&root_a, &only_a_hash).await?, None);
+        {
+            let blob_storage = publisher.blob_storage.lock().await;
+            assert_eq!(
+                blob_storage.get_meta(format!("C/{}", shared_hash).as_bytes())?.map(|n| n.ref_count()),
+                Some(1)
+            );
+            assert!(blob_storage.get(format!("C/{}", only_a_hash).as_bytes())?.is_none());
+        }
+
+        // 取り下げたファイルのキーは配布しない
+        let push_asset_keys = publisher.get_push_asset_keys();
+        assert_eq!(push_asset_keys.len(), 1);
+        assert_eq!(push_asset_keys[0].hash, root_b);
+
+        Ok(())
+    }
+
+    async fn create_file_publisher(dir_path: &Path, read_only: bool) -> anyhow::Result<FilePublisher> {
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+
+        let repo_dir_path = dir_path.join("repo");
+        tokio::fs::create_dir_all(&repo_dir_path).await?;
+        let file_publisher_repo = Arc::new(FilePublisherRepo::new(repo_dir_path.to_str().unwrap(), clock.clone()).await?);
+
+        let storage_dir_path = dir_path.join("storage");
+        let blob_storage = Arc::new(TokioMutex::new(BlobStorage::new(&storage_dir_path, BlobStorageOption::default())?));
+
+        let watchdog = TaskDiskSpaceWatchdog::new(&storage_dir_path, Arc::new(SleeperImpl), DiskSpaceWatchdogOption::default());
+
+        FilePublisher::new(
+            file_publisher_repo,
+            blob_storage,
+            clock,
+            Arc::new(SleeperImpl),
+            read_only,
+            watchdog.gate(),
+        )
+        .await
+    }
+}
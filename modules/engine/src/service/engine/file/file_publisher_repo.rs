@@ -1,14 +1,17 @@
 use std::{path::Path, str::FromStr as _, sync::Arc};
 
 use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::{stream::BoxStream, StreamExt as _, TryStreamExt as _};
 use sqlx::{migrate::MigrateDatabase, sqlite::SqlitePool, Sqlite};
 
 use omnius_core_base::clock::Clock;
 use omnius_core_omnikit::model::OmniHash;
 
-use crate::service::util::{MigrationRequest, SqliteMigrator};
+use crate::service::util::{MigrationRequest, SqliteBackup, SqliteMigrator};
 
-use super::PublishedFile;
+use super::{FileSource, FileSourceMatch, PublishedBlock, PublishedFile};
+
+const FETCH_CHUNK_SIZE: i64 = 256;
 
 #[allow(unused)]
 pub struct FilePublisherRepo {
@@ -38,9 +41,27 @@ impl FilePublisherRepo {
     async fn migrate(&self) -> anyhow::Result<()> {
         let migrator = SqliteMigrator::new(self.db.clone());
 
-        let requests = vec![MigrationRequest {
-            name: "2024-06-23_init".to_string(),
-            queries: r#"
+        let requests = vec![
+            // 2024-06-23_init の files は存在しない列を主キーに指定しており、空の DB では作成に失敗する
+            // 正しい主キーで先に作成し、2024-06-23_init の CREATE TABLE IF NOT EXISTS を何もしないようにする
+            MigrationRequest {
+                name: "2026-10-16_files_primary_key".to_string(),
+                queries: r#"
+CREATE TABLE IF NOT EXISTS files (
+    root_hash TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    block_size INTEGER NOT NULL,
+    property TEXT,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (root_hash, file_name)
+);
+"#
+                .to_string(),
+            },
+            MigrationRequest {
+                name: "2024-06-23_init".to_string(),
+                queries: r#"
 CREATE TABLE IF NOT EXISTS files (
     root_hash TEXT NOT NULL,
     file_name TEXT NOT NULL,
@@ -59,14 +80,58 @@ CREATE TABLE IF NOT EXISTS blocks (
 );
 CREATE INDEX IF NOT EXISTS index_root_hash_depth_index_for_blocks ON blocks (root_hash, depth ASC, `index` ASC);
 "#
-            .to_string(),
-        }];
+                .to_string(),
+            },
+            MigrationRequest {
+                name: "2026-10-16_uncommitted_blocks".to_string(),
+                queries: r#"
+CREATE TABLE IF NOT EXISTS uncommitted_blocks (
+    id TEXT NOT NULL,
+    block_hash TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    `index` INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (id, depth, `index`)
+);
+"#
+                .to_string(),
+            },
+            MigrationRequest {
+                name: "2026-10-16_file_sources".to_string(),
+                queries: r#"
+CREATE TABLE IF NOT EXISTS uncommitted_files (
+    id TEXT NOT NULL PRIMARY KEY,
+    file_path TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    file_mtime INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS index_file_path_for_uncommitted_files ON uncommitted_files (file_path);
+ALTER TABLE files ADD COLUMN file_path TEXT;
+ALTER TABLE files ADD COLUMN file_size INTEGER;
+ALTER TABLE files ADD COLUMN file_mtime INTEGER;
+CREATE INDEX IF NOT EXISTS index_file_path_for_files ON files (file_path);
+"#
+                .to_string(),
+            },
+            MigrationRequest {
+                name: "2026-10-16_block_hash_index".to_string(),
+                queries: r#"
+CREATE INDEX IF NOT EXISTS index_block_hash_for_blocks ON blocks (block_hash);
+"#
+                .to_string(),
+            },
+        ];
 
         migrator.migrate(requests).await?;
 
         Ok(())
     }
 
+    pub async fn backup_to(&self, path: &Path) -> anyhow::Result<()> {
+        SqliteBackup::backup(self.db.as_ref(), path).await
+    }
+
     pub async fn file_exists(&self, root_hash: OmniHash) -> anyhow::Result<bool> {
         let (res,): (i64,) = sqlx::query_as(
             r#"
@@ -84,17 +149,104 @@ SELECT COUNT(1)
     }
 
     pub async fn get_published_files(&self) -> anyhow::Result<Vec<PublishedFile>> {
-        let res: Vec<PublishedFileRow> = sqlx::query_as(
+        self.stream_published_files().try_collect().await
+    }
+
+    // テーブル全体を読み込まないよう、主キーの順に FETCH_CHUNK_SIZE 件ずつ取得しながら返す
+    pub fn stream_published_files(&self) -> BoxStream<'_, anyhow::Result<PublishedFile>> {
+        futures::stream::try_unfold(Some(None), move |cursor: Option<Option<(String, String)>>| async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+
+            let rows = self.fetch_published_files_chunk(cursor.as_ref()).await?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+
+            let next = if (rows.len() as i64) < FETCH_CHUNK_SIZE {
+                None
+            } else {
+                rows.last().map(|r| Some((r.root_hash.clone(), r.file_name.clone())))
+            };
+
+            // 壊れた行は読み飛ばさず、エラーとして呼び出し側に返す
+            let files: Vec<anyhow::Result<PublishedFile>> = rows.into_iter().map(PublishedFile::try_from).collect();
+            Ok(Some((futures::stream::iter(files), next)))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    async fn fetch_published_files_chunk(&self, cursor: Option<&(String, String)>) -> anyhow::Result<Vec<PublishedFileRow>> {
+        let res: Vec<PublishedFileRow> = match cursor {
+            None => {
+                sqlx::query_as(
+                    r#"
+SELECT root_hash, file_name, block_size, property, created_at, updated_at
+    FROM files
+    ORDER BY root_hash ASC, file_name ASC
+    LIMIT ?
+"#,
+                )
+                .bind(FETCH_CHUNK_SIZE)
+                .fetch_all(self.db.as_ref())
+                .await?
+            }
+            Some((root_hash, file_name)) => {
+                sqlx::query_as(
+                    r#"
+SELECT root_hash, file_name, block_size, property, created_at, updated_at
+    FROM files
+    WHERE (root_hash, file_name) > (?, ?)
+    ORDER BY root_hash ASC, file_name ASC
+    LIMIT ?
+"#,
+                )
+                .bind(root_hash)
+                .bind(file_name)
+                .bind(FETCH_CHUNK_SIZE)
+                .fetch_all(self.db.as_ref())
+                .await?
+            }
+        };
+        Ok(res)
+    }
+
+    pub async fn get_published_file(&self, root_hash: &OmniHash, file_name: &str) -> anyhow::Result<Option<PublishedFile>> {
+        let res: Option<PublishedFileRow> = sqlx::query_as(
             r#"
 SELECT root_hash, file_name, block_size, property, created_at, updated_at
     FROM files
+    WHERE root_hash = ? AND file_name = ?
+"#,
+        )
+        .bind(root_hash.to_string())
+        .bind(file_name)
+        .fetch_optional(self.db.as_ref())
+        .await?;
+
+        res.map(PublishedFile::try_from).transpose()
+    }
+
+    // マークルツリーの段毎のブロック数を depth の昇順で返す
+    pub async fn get_merkle_layers(&self, root_hash: &OmniHash) -> anyhow::Result<Vec<(u32, u32)>> {
+        let rows: Vec<(i64, i64)> = sqlx::query_as(
+            r#"
+SELECT depth, COUNT(1)
+    FROM blocks
+    WHERE root_hash = ?
+    GROUP BY depth
+    ORDER BY depth ASC
 "#,
         )
+        .bind(root_hash.to_string())
         .fetch_all(self.db.as_ref())
         .await?;
 
-        let res: Vec<PublishedFile> = res.into_iter().filter_map(|r| r.into().ok()).collect();
-        Ok(res)
+        rows.into_iter()
+            .map(|(depth, count)| Ok((depth.try_into()?, count.try_into()?)))
+            .collect()
     }
 
     pub async fn block_exists(&self, root_hash: OmniHash, block_hash: OmniHash) -> anyhow::Result<bool> {
@@ -113,6 +265,295 @@ SELECT COUNT(1)
 
         Ok(res > 0)
     }
+
+    // 公開を取り下げたファイルの記録を、ブロックも含めて全て削除する
+    // 保存済みのブロックの参照を外せるよう、削除したブロックのハッシュを重複無く返す
+    pub async fn delete_published_file(&self, root_hash: &OmniHash) -> anyhow::Result<Vec<OmniHash>> {
+        let mut tx = self.db.begin().await?;
+
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+SELECT DISTINCT block_hash
+    FROM blocks
+    WHERE root_hash = ?
+"#,
+        )
+        .bind(root_hash.to_string())
+        .fetch_all(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+DELETE FROM blocks
+    WHERE root_hash = ?
+"#,
+        )
+        .bind(root_hash.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+DELETE FROM files
+    WHERE root_hash = ?
+"#,
+        )
+        .bind(root_hash.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        rows.into_iter().map(|(n,)| OmniHash::from_str(&n)).collect()
+    }
+
+    // 同じ内容のブロックは公開済みのどのファイルのものでも配れるよう、そのブロックを保持している root_hash を返す
+    // 要求された root_hash に含まれていれば、それを優先する
+    pub async fn find_block_root_hash(&self, root_hash: &OmniHash, block_hash: &OmniHash) -> anyhow::Result<Option<OmniHash>> {
+        let res: Option<(String,)> = sqlx::query_as(
+            r#"
+SELECT root_hash
+    FROM blocks
+    WHERE block_hash = ?
+    ORDER BY root_hash = ? DESC
+    LIMIT 1
+"#,
+        )
+        .bind(block_hash.to_string())
+        .bind(root_hash.to_string())
+        .fetch_optional(self.db.as_ref())
+        .await?;
+
+        res.map(|(n,)| OmniHash::from_str(&n)).transpose()
+    }
+
+    // 符号化の途中で停止した場合に再開できるよう、書き込みを終えたブロックを記録しておく
+    pub async fn get_uncommitted_blocks(&self, id: &str, depth: u32) -> anyhow::Result<Vec<PublishedBlock>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            r#"
+SELECT block_hash, `index`
+    FROM uncommitted_blocks
+    WHERE id = ? AND depth = ?
+    ORDER BY `index` ASC
+"#,
+        )
+        .bind(id)
+        .bind(depth as i64)
+        .fetch_all(self.db.as_ref())
+        .await?;
+
+        let mut res: Vec<PublishedBlock> = Vec::with_capacity(rows.len());
+        for (block_hash, index) in rows {
+            // 途中が欠けている場合は、そこまでを完了済みとみなす
+            if index != res.len() as i64 {
+                break;
+            }
+            res.push(PublishedBlock {
+                root_hash: OmniHash::default(),
+                block_hash: OmniHash::from_str(block_hash.as_str())?,
+                depth,
+                index: index as u32,
+            });
+        }
+
+        Ok(res)
+    }
+
+    pub async fn insert_uncommitted_block(&self, id: &str, block: &PublishedBlock) -> anyhow::Result<()> {
+        let now = self.clock.now().naive_utc();
+        sqlx::query(
+            r#"
+INSERT OR REPLACE INTO uncommitted_blocks (id, block_hash, depth, `index`, created_at)
+    VALUES (?, ?, ?, ?, ?)
+"#,
+        )
+        .bind(id)
+        .bind(block.block_hash.to_string())
+        .bind(block.depth as i64)
+        .bind(block.index as i64)
+        .bind(now)
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_uncommitted_blocks(&self, id: &str, depth: u32, from_index: u32) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+DELETE FROM uncommitted_blocks
+    WHERE id = ? AND depth = ? AND `index` >= ?
+"#,
+        )
+        .bind(id)
+        .bind(depth as i64)
+        .bind(from_index as i64)
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_uncommitted(&self, id: &str) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            r#"
+DELETE FROM uncommitted_blocks
+    WHERE id = ?
+"#,
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+DELETE FROM uncommitted_files
+    WHERE id = ?
+"#,
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_uncommitted_file(&self, id: &str, source: &FileSource) -> anyhow::Result<()> {
+        let now = self.clock.now().naive_utc();
+        sqlx::query(
+            r#"
+INSERT INTO uncommitted_files (id, file_path, file_size, file_mtime, created_at)
+    VALUES (?, ?, ?, ?, ?)
+"#,
+        )
+        .bind(id)
+        .bind(source.path.as_str())
+        .bind(source.size)
+        .bind(source.mtime)
+        .bind(now)
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    // 取り込みを終えたファイルを公開済みとして記録し、取り込み中の記録を削除する
+    // 元のファイルの情報は取り込み開始時に記録したものを引き継ぐ
+    pub async fn commit_uncommitted(
+        &self,
+        id: &str,
+        root_hash: &OmniHash,
+        file_name: &str,
+        block_size: u64,
+        blocks: &[PublishedBlock],
+    ) -> anyhow::Result<()> {
+        let now = self.clock.now().naive_utc();
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            r#"
+INSERT INTO files (root_hash, file_name, block_size, property, created_at, updated_at, file_path, file_size, file_mtime)
+    SELECT ?, ?, ?, NULL, ?, ?, file_path, file_size, file_mtime
+        FROM uncommitted_files
+        WHERE id = ?
+    ON CONFLICT (root_hash, file_name) DO UPDATE SET
+        file_path = excluded.file_path,
+        file_size = excluded.file_size,
+        file_mtime = excluded.file_mtime,
+        updated_at = excluded.updated_at
+"#,
+        )
+        .bind(root_hash.to_string())
+        .bind(file_name)
+        .bind(i64::try_from(block_size)?)
+        .bind(now)
+        .bind(now)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        for block in blocks {
+            sqlx::query(
+                r#"
+INSERT OR IGNORE INTO blocks (root_hash, block_hash, depth, `index`)
+    VALUES (?, ?, ?, ?)
+"#,
+            )
+            .bind(root_hash.to_string())
+            .bind(block.block_hash.to_string())
+            .bind(block.depth as i64)
+            .bind(block.index as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            r#"
+DELETE FROM uncommitted_blocks
+    WHERE id = ?
+"#,
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+DELETE FROM uncommitted_files
+    WHERE id = ?
+"#,
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 同じ内容のファイルが公開済みか取り込み中であれば、それを返す
+    pub async fn find_file_source(&self, source: &FileSource) -> anyhow::Result<Option<FileSourceMatch>> {
+        let res: Option<(String,)> = sqlx::query_as(
+            r#"
+SELECT root_hash
+    FROM files
+    WHERE file_path = ? AND file_size = ? AND file_mtime = ?
+    LIMIT 1
+"#,
+        )
+        .bind(source.path.as_str())
+        .bind(source.size)
+        .bind(source.mtime)
+        .fetch_optional(self.db.as_ref())
+        .await?;
+        if let Some((root_hash,)) = res {
+            return Ok(Some(FileSourceMatch::Committed(OmniHash::from_str(root_hash.as_str())?)));
+        }
+
+        let res: Option<(String,)> = sqlx::query_as(
+            r#"
+SELECT id
+    FROM uncommitted_files
+    WHERE file_path = ? AND file_size = ? AND file_mtime = ?
+    LIMIT 1
+"#,
+        )
+        .bind(source.path.as_str())
+        .bind(source.size)
+        .bind(source.mtime)
+        .fetch_optional(self.db.as_ref())
+        .await?;
+        if let Some((id,)) = res {
+            return Ok(Some(FileSourceMatch::Uncommitted(id)));
+        }
+
+        Ok(None)
+    }
 }
 
 #[derive(sqlx::FromRow)]
@@ -125,18 +566,22 @@ struct PublishedFileRow {
     updated_at: NaiveDateTime,
 }
 
-impl PublishedFileRow {
-    pub fn into(self) -> anyhow::Result<PublishedFile> {
-        Ok(PublishedFile {
-            root_hash: OmniHash::from_str(self.root_hash.as_str()).unwrap(),
-            file_name: self.file_name,
-            block_size: self.block_size,
-            property: self.property,
-            created_at: DateTime::from_naive_utc_and_offset(self.created_at, Utc),
-            updated_at: DateTime::from_naive_utc_and_offset(self.updated_at, Utc),
+impl TryFrom<PublishedFileRow> for PublishedFile {
+    type Error = anyhow::Error;
+
+    fn try_from(row: PublishedFileRow) -> anyhow::Result<Self> {
+        Ok(Self {
+            root_hash: OmniHash::from_str(row.root_hash.as_str())?,
+            file_name: row.file_name,
+            block_size: row.block_size,
+            property: row.property,
+            created_at: DateTime::from_naive_utc_and_offset(row.created_at, Utc),
+            updated_at: DateTime::from_naive_utc_and_offset(row.updated_at, Utc),
         })
     }
+}
 
+impl PublishedFileRow {
     #[allow(unused)]
     pub fn from(item: PublishedFile) -> anyhow::Result<Self> {
         Ok(Self {
@@ -152,10 +597,184 @@ impl PublishedFileRow {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+    use futures::StreamExt as _;
     use testresult::TestResult;
 
+    use omnius_core_base::clock::FakeClockUtc;
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType};
+
+    use super::{FilePublisherRepo, FileSource, FileSourceMatch, PublishedBlock};
+
     #[tokio::test]
     pub async fn simple_test() -> TestResult {
         Ok(())
     }
+
+    #[tokio::test]
+    pub async fn uncommitted_blocks_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = FilePublisherRepo::new(path, clock).await?;
+
+        for index in [0, 1, 3] {
+            let block = PublishedBlock {
+                root_hash: OmniHash::default(),
+                block_hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &[index as u8]),
+                depth: 0,
+                index,
+            };
+            repo.insert_uncommitted_block("a", &block).await?;
+        }
+
+        // 欠けている index 2 より後は完了済みとみなさない
+        let res = repo.get_uncommitted_blocks("a", 0).await?;
+        assert_eq!(res.iter().map(|n| n.index).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(repo.get_uncommitted_blocks("a", 1).await?.is_empty());
+
+        repo.delete_uncommitted_blocks("a", 0, 1).await?;
+        assert_eq!(repo.get_uncommitted_blocks("a", 0).await?.len(), 1);
+
+        repo.delete_uncommitted("a").await?;
+        assert!(repo.get_uncommitted_blocks("a", 0).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_published_files_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = FilePublisherRepo::new(path, clock).await?;
+
+        let root_a = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"a");
+        let root_b = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"b");
+        for root_hash in [root_a.to_string(), "broken".to_string(), root_b.to_string()] {
+            sqlx::query(
+                "INSERT INTO files (root_hash, file_name, block_size, created_at, updated_at) VALUES (?, 'f', 1024, '2000-01-01 00:00:00', '2000-01-01 00:00:00')",
+            )
+            .bind(root_hash)
+            .execute(repo.db.as_ref())
+            .await?;
+        }
+
+        // 壊れた行は落とさずにエラーとして返り、他の行も読める
+        let res: Vec<_> = repo.stream_published_files().collect().await;
+        assert_eq!(res.len(), 3);
+        assert_eq!(res.iter().filter(|n| n.is_err()).count(), 1);
+        let mut root_hashes: Vec<_> = res.into_iter().filter_map(|n| n.ok()).map(|n| n.root_hash).collect();
+        root_hashes.sort_by_key(|n| n.to_string());
+        let mut expected = vec![root_a, root_b];
+        expected.sort_by_key(|n| n.to_string());
+        assert_eq!(root_hashes, expected);
+
+        assert!(repo.get_published_files().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn find_block_root_hash_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = FilePublisherRepo::new(path, clock).await?;
+
+        let root_a = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"a");
+        let root_b = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"b");
+        let root_c = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"c");
+        let shared = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"shared");
+        let only_b = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"only_b");
+
+        for (root_hash, block_hash) in [(&root_a, &shared), (&root_b, &shared), (&root_b, &only_b)] {
+            sqlx::query("INSERT INTO blocks (root_hash, block_hash, depth, `index`) VALUES (?, ?, 0, 0)")
+                .bind(root_hash.to_string())
+                .bind(block_hash.to_string())
+                .execute(repo.db.as_ref())
+                .await?;
+        }
+
+        // 要求された root_hash に含まれていればそれを返す
+        assert_eq!(repo.find_block_root_hash(&root_a, &shared).await?, Some(root_a.clone()));
+        assert_eq!(repo.find_block_root_hash(&root_b, &shared).await?, Some(root_b.clone()));
+
+        // 含まれていなければ、他の公開済みファイルのものを返す
+        assert_eq!(repo.find_block_root_hash(&root_a, &only_b).await?, Some(root_b.clone()));
+        assert_eq!(repo.find_block_root_hash(&root_c, &only_b).await?, Some(root_b.clone()));
+
+        let unknown = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"unknown");
+        assert_eq!(repo.find_block_root_hash(&root_a, &unknown).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn delete_published_file_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = FilePublisherRepo::new(path, clock).await?;
+
+        let root_a = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"a");
+        let root_b = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"b");
+        let shared = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"shared");
+
+        for root_hash in [&root_a, &root_b] {
+            sqlx::query("INSERT INTO files (root_hash, file_name, block_size, created_at, updated_at) VALUES (?, 'test.bin', 1024, 0, 0)")
+                .bind(root_hash.to_string())
+                .execute(repo.db.as_ref())
+                .await?;
+            sqlx::query("INSERT INTO blocks (root_hash, block_hash, depth, `index`) VALUES (?, ?, 0, 0)")
+                .bind(root_hash.to_string())
+                .bind(shared.to_string())
+                .execute(repo.db.as_ref())
+                .await?;
+        }
+
+        assert_eq!(repo.delete_published_file(&root_a).await?, vec![shared.clone()]);
+        assert!(!repo.file_exists(root_a.clone()).await?);
+        assert!(!repo.block_exists(root_a.clone(), shared.clone()).await?);
+
+        // 同じブロックを持つ他のファイルには影響しない
+        assert!(repo.file_exists(root_b.clone()).await?);
+        assert_eq!(repo.find_block_root_hash(&root_a, &shared).await?, Some(root_b.clone()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn file_source_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = FilePublisherRepo::new(path, clock).await?;
+
+        let source = FileSource {
+            path: "/tmp/a.bin".to_string(),
+            size: 1024,
+            mtime: 1,
+        };
+        assert!(repo.find_file_source(&source).await?.is_none());
+
+        repo.insert_uncommitted_file("a", &source).await?;
+        assert!(matches!(repo.find_file_source(&source).await?, Some(FileSourceMatch::Uncommitted(id)) if id == "a"));
+
+        // 更新されたファイルは別のものとして扱う
+        let modified = FileSource { mtime: 2, ..source.clone() };
+        assert!(repo.find_file_source(&modified).await?.is_none());
+
+        repo.delete_uncommitted("a").await?;
+        assert!(repo.find_file_source(&source).await?.is_none());
+
+        Ok(())
+    }
 }
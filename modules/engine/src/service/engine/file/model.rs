@@ -1,7 +1,9 @@
+mod file_source;
 mod merkle_layer;
 mod published_block;
 mod published_file;
 
+pub use file_source::*;
 pub use merkle_layer::*;
 pub use published_block::*;
 pub use published_file::*;
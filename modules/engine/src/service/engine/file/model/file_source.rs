@@ -0,0 +1,31 @@
+use std::{path::Path, time::UNIX_EPOCH};
+
+use omnius_core_omnikit::model::OmniHash;
+
+// 公開元のファイルを識別する。パス、サイズ、更新日時のいずれかが変われば別のファイルとみなす
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSource {
+    pub path: String,
+    pub size: i64,
+    pub mtime: i64,
+}
+
+impl FileSource {
+    pub async fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let path = tokio::fs::canonicalize(path).await?;
+        let metadata = tokio::fs::metadata(&path).await?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_millis();
+
+        Ok(Self {
+            path: path.to_str().ok_or_else(|| anyhow::anyhow!("Invalid path"))?.to_string(),
+            size: metadata.len().try_into()?,
+            mtime: mtime.try_into()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSourceMatch {
+    Committed(OmniHash),
+    Uncommitted(String),
+}
@@ -0,0 +1,221 @@
+use std::{str::FromStr as _, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::StreamExt as _;
+use parking_lot::Mutex;
+use tokio::{sync::Mutex as TokioMutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
+use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType};
+
+use crate::service::{
+    storage::BlobStorage,
+    util::{shutdown_task, sleep_or_cancelled, TASK_SHUTDOWN_GRACE_PERIOD},
+};
+
+const COMMITTED_BLOCK_PREFIX: &str = "C/";
+const QUARANTINED_BLOCK_PREFIX: &str = "Q/";
+
+#[allow(unused)]
+#[derive(Debug, Clone, Default)]
+pub struct ScrubStatus {
+    pub scanned_block_count: u64,
+    pub corrupted_block_keys: Vec<String>,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_completed_at: Option<DateTime<Utc>>,
+}
+
+#[allow(unused)]
+#[derive(Clone)]
+pub struct TaskScrubber {
+    inner: Inner,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
+}
+
+#[allow(unused)]
+impl TaskScrubber {
+    pub fn new(
+        blob_storage: Arc<TokioMutex<BlobStorage>>,
+        clock: Arc<dyn Clock<Utc> + Send + Sync>,
+        sleeper: Arc<dyn Sleeper + Send + Sync>,
+    ) -> Self {
+        let inner = Inner {
+            blob_storage,
+            clock,
+            status: Arc::new(Mutex::new(ScrubStatus::default())),
+        };
+        Self {
+            inner,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    pub async fn run(&self) {
+        let sleeper = self.sleeper.clone();
+        let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(60 * 60), &cancellation_token).await {
+                    return;
+                }
+                let res = inner.scrub(&cancellation_token).await;
+                if let Err(e) = res {
+                    warn!(error_message = e.to_string(), "scrub failed");
+                }
+            }
+        });
+        *self.join_handle.lock().await = Some(join_handle);
+    }
+
+    pub fn status(&self) -> ScrubStatus {
+        self.inner.status.lock().clone()
+    }
+}
+
+#[async_trait]
+impl Terminable for TaskScrubber {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        if let Some(join_handle) = self.join_handle.lock().await.take() {
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Inner {
+    blob_storage: Arc<TokioMutex<BlobStorage>>,
+    clock: Arc<dyn Clock<Utc> + Send + Sync>,
+    status: Arc<Mutex<ScrubStatus>>,
+}
+
+impl Inner {
+    async fn scrub(&self, cancellation_token: &CancellationToken) -> anyhow::Result<()> {
+        {
+            let mut status = self.status.lock();
+            status.scanned_block_count = 0;
+            status.corrupted_block_keys.clear();
+            status.last_started_at = Some(self.clock.now());
+        }
+
+        let mut keys = self.blob_storage.lock().await.keys(Some(COMMITTED_BLOCK_PREFIX.as_bytes()));
+        while let Some(key) = keys.next().await {
+            // 隔離の途中で止めないよう、ブロックの処理の合間でのみ停止要求を確認する
+            if cancellation_token.is_cancelled() {
+                info!("scrub cancelled");
+                return Ok(());
+            }
+
+            let key = String::from_utf8(key?.to_vec())?;
+
+            if !self.verify(&key).await? {
+                warn!(key, "corrupted block found");
+                self.quarantine(&key).await?;
+                self.status.lock().corrupted_block_keys.push(key);
+            }
+            self.status.lock().scanned_block_count += 1;
+
+            // 他の処理を妨げないよう、ブロック毎に実行権を譲る
+            tokio::task::yield_now().await;
+        }
+
+        let status = {
+            let mut status = self.status.lock();
+            status.last_completed_at = Some(self.clock.now());
+            status.clone()
+        };
+        info!(
+            scanned_block_count = status.scanned_block_count,
+            corrupted_block_count = status.corrupted_block_keys.len(),
+            "scrub completed"
+        );
+
+        Ok(())
+    }
+
+    async fn verify(&self, key: &str) -> anyhow::Result<bool> {
+        // C/<block_hash>
+        let block_hash = key.rsplit_once('/').ok_or_else(|| anyhow::anyhow!("invalid block key: {}", key))?.1;
+        let block_hash = OmniHash::from_str(block_hash)?;
+
+        let Some(value) = self.blob_storage.lock().await.get_async(key.as_bytes()).await? else {
+            return Ok(true);
+        };
+
+        Ok(OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, &value) == block_hash)
+    }
+
+    async fn quarantine(&self, key: &str) -> anyhow::Result<()> {
+        let blob_storage = self.blob_storage.lock().await;
+        let Some(value) = blob_storage.get(key.as_bytes())? else {
+            return Ok(());
+        };
+
+        let quarantined_key = format!("{}{}", QUARANTINED_BLOCK_PREFIX, &key[COMMITTED_BLOCK_PREFIX.len()..]);
+        blob_storage.put(quarantined_key.as_bytes(), &value)?;
+        blob_storage.delete(key.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+    use parking_lot::Mutex;
+    use testresult::TestResult;
+    use tokio::sync::Mutex as TokioMutex;
+    use tokio_util::sync::CancellationToken;
+
+    use omnius_core_base::clock::FakeClockUtc;
+    use omnius_core_omnikit::model::{OmniHash, OmniHashAlgorithmType};
+
+    use crate::service::storage::{BlobStorage, BlobStorageOption};
+
+    use super::{Inner, ScrubStatus};
+
+    #[tokio::test]
+    pub async fn scrub_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let blob_storage = Arc::new(TokioMutex::new(BlobStorage::new(dir.path(), BlobStorageOption::default())?));
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+
+        let valid_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"valid");
+        let broken_hash = OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"broken");
+
+        let valid_key = format!("C/{}", valid_hash);
+        let broken_key = format!("C/{}", broken_hash);
+        blob_storage.lock().await.put(valid_key.as_bytes(), b"valid")?;
+        blob_storage.lock().await.put(broken_key.as_bytes(), b"corrupted")?;
+
+        let inner = Inner {
+            blob_storage: blob_storage.clone(),
+            clock,
+            status: Arc::new(Mutex::new(ScrubStatus::default())),
+        };
+        inner.scrub(&CancellationToken::new()).await?;
+
+        let status = inner.status.lock().clone();
+        assert_eq!(status.scanned_block_count, 2);
+        assert_eq!(status.corrupted_block_keys, vec![broken_key.clone()]);
+
+        let blob_storage = blob_storage.lock().await;
+        assert!(blob_storage.get(valid_key.as_bytes())?.is_some());
+        assert!(blob_storage.get(broken_key.as_bytes())?.is_none());
+        assert!(blob_storage.get(format!("Q/{}", broken_hash).as_bytes())?.is_some());
+
+        Ok(())
+    }
+}
@@ -1,17 +1,31 @@
+mod connection_pacer;
 mod node_finder;
+mod node_profile_bundle;
 mod node_profile_fetcher;
 mod node_profile_repo;
+mod node_profile_validator;
+mod session_registry;
 mod session_status;
 mod task_accepter;
 mod task_communicator;
 mod task_computer;
 mod task_connector;
+mod task_reaper;
+mod task_replacer;
 
+pub use connection_pacer::*;
 pub use node_finder::*;
+pub use node_profile_bundle::*;
 pub use node_profile_fetcher::*;
-use node_profile_repo::*;
+pub use node_profile_repo::*;
+use node_profile_validator::*;
+pub use session_registry::*;
 use session_status::*;
+pub use session_status::{AssetKeyLocationFoundEvent, SessionCloseReason, SessionClosedEvent};
 use task_accepter::*;
 use task_communicator::*;
+pub use task_communicator::{DataMessage, NodeCapabilities, NODE_FINDER_PROTOCOL_VERSION, SUPPORTED_CAPABILITIES};
 use task_computer::*;
 use task_connector::*;
+use task_reaper::*;
+use task_replacer::*;
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionPacerOption {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub idle_interval: Duration,
+    // これより短く終わったセッションは、不安定な接続によるものとみなす
+    pub short_session_lifetime: Duration,
+}
+
+impl Default for ConnectionPacerOption {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(30),
+            idle_interval: Duration::from_secs(10),
+            short_session_lifetime: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionPacerStatus {
+    pub failure_rate: f64,
+    pub consecutive_failure_count: u32,
+    pub established_session_count: u64,
+    pub closed_session_count: u64,
+    // 閉じたセッションの寿命の移動平均
+    pub average_session_lifetime_secs: f64,
+    // 閉じたセッションのうち、短命だったものの割合の移動平均
+    pub churn_rate: f64,
+}
+
+// 接続試行の間隔を、現在のセッション数と失敗率、セッションの入れ替わりの激しさから決める
+// 不足が大きいほど短く、目標に近いほど長くし、失敗が続く間は指数的に延ばす
+// 繋いでもすぐ切れる環境では、繋ぎ直す度に CPU や電池を使うだけなので間隔を延ばす
+pub struct ConnectionPacer {
+    option: ConnectionPacerOption,
+    status: Mutex<ConnectionPacerStatus>,
+}
+
+const FAILURE_RATE_WEIGHT: f64 = 0.2;
+const LIFETIME_WEIGHT: f64 = 0.2;
+const CHURN_PENALTY: f64 = 3.0;
+const MAX_BACKOFF_SHIFT: u32 = 5;
+
+impl ConnectionPacer {
+    pub fn new(option: ConnectionPacerOption) -> Self {
+        Self {
+            option,
+            status: Mutex::new(ConnectionPacerStatus::default()),
+        }
+    }
+
+    pub fn next_delay(&self, session_count: usize, target_count: usize) -> Duration {
+        if session_count >= target_count {
+            return self.option.idle_interval;
+        }
+
+        let deficit = (target_count - session_count) as f64 / target_count as f64;
+        let min = self.option.min_interval.as_secs_f64();
+        let max = self.option.max_interval.as_secs_f64();
+        let base = min + (max - min) * (1.0 - deficit);
+
+        let status = self.status.lock();
+        let backoff = (1_u32 << status.consecutive_failure_count.min(MAX_BACKOFF_SHIFT)) as f64;
+        let delay = base * (1.0 + status.failure_rate) * (1.0 + status.churn_rate * CHURN_PENALTY) * backoff;
+
+        Duration::from_secs_f64(delay.min(max).max(min))
+    }
+
+    pub fn record(&self, success: bool) {
+        let mut status = self.status.lock();
+        let sample = if success { 0.0 } else { 1.0 };
+        status.failure_rate = status.failure_rate * (1.0 - FAILURE_RATE_WEIGHT) + sample * FAILURE_RATE_WEIGHT;
+        status.consecutive_failure_count = if success {
+            0
+        } else {
+            status.consecutive_failure_count.saturating_add(1)
+        };
+    }
+
+    pub fn record_session_established(&self) {
+        self.status.lock().established_session_count += 1;
+    }
+
+    pub fn record_session_closed(&self, lifetime: Duration) {
+        let mut status = self.status.lock();
+        let lifetime_secs = lifetime.as_secs_f64();
+        status.average_session_lifetime_secs = if status.closed_session_count == 0 {
+            lifetime_secs
+        } else {
+            status.average_session_lifetime_secs * (1.0 - LIFETIME_WEIGHT) + lifetime_secs * LIFETIME_WEIGHT
+        };
+        let sample = if lifetime < self.option.short_session_lifetime { 1.0 } else { 0.0 };
+        status.churn_rate = status.churn_rate * (1.0 - LIFETIME_WEIGHT) + sample * LIFETIME_WEIGHT;
+        status.closed_session_count += 1;
+    }
+
+    pub fn status(&self) -> ConnectionPacerStatus {
+        self.status.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ConnectionPacer, ConnectionPacerOption};
+
+    #[test]
+    pub fn simple_test() {
+        let pacer = ConnectionPacer::new(ConnectionPacerOption::default());
+
+        // セッションが無い場合は最短、目標に達している場合は待機間隔
+        assert_eq!(pacer.next_delay(0, 8), Duration::from_secs(1));
+        assert_eq!(pacer.next_delay(8, 8), Duration::from_secs(10));
+        assert!(pacer.next_delay(6, 8) > pacer.next_delay(2, 8));
+
+        // 失敗が続くと間隔が延び、成功すると戻る
+        for _ in 0..3 {
+            pacer.record(false);
+        }
+        assert!(pacer.next_delay(0, 8) >= Duration::from_secs(8));
+        assert_eq!(pacer.status().consecutive_failure_count, 3);
+
+        for _ in 0..64 {
+            pacer.record(false);
+        }
+        assert_eq!(pacer.next_delay(0, 8), Duration::from_secs(30));
+
+        pacer.record(true);
+        assert_eq!(pacer.status().consecutive_failure_count, 0);
+        assert!(pacer.next_delay(0, 8) < Duration::from_secs(3));
+    }
+
+    #[test]
+    pub fn churn_test() {
+        let pacer = ConnectionPacer::new(ConnectionPacerOption::default());
+        let calm = pacer.next_delay(0, 8);
+
+        // すぐに切れるセッションが続くと間隔が延びる
+        for _ in 0..16 {
+            pacer.record_session_established();
+            pacer.record_session_closed(Duration::from_secs(5));
+        }
+        let status = pacer.status();
+        assert_eq!(status.established_session_count, 16);
+        assert_eq!(status.closed_session_count, 16);
+        assert!(status.churn_rate > 0.9);
+        assert!((status.average_session_lifetime_secs - 5.0).abs() < 0.001);
+        assert!(pacer.next_delay(0, 8) > calm * 2);
+
+        // 長く続くようになれば戻る
+        for _ in 0..64 {
+            pacer.record_session_closed(Duration::from_secs(3600));
+        }
+        assert!(pacer.status().churn_rate < 0.01);
+        assert!(pacer.next_delay(0, 8) < calm + Duration::from_millis(100));
+    }
+}
@@ -1,50 +1,74 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use async_trait::async_trait;
 use chrono::{Duration, Utc};
-use futures::future::join_all;
+use futures::{future::join_all, stream::BoxStream, StreamExt as _};
 use parking_lot::Mutex;
 use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use tokio::sync::{mpsc, Mutex as TokioMutex, RwLock as TokioRwLock};
+use tokio::sync::{mpsc, Mutex as TokioMutex};
+use tracing::{info, warn};
 
 use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
 
 use crate::{
-    model::{AssetKey, NodeProfile},
+    model::{AssetKey, AssetPointer, NodeProfile},
     service::{
-        connection::{ConnectionTcpAccepterImpl, ConnectionTcpConnectorImpl},
+        connection::{ConnectionTcpAccepter, ConnectionTcpConnector},
         session::{model::Session, SessionAccepter, SessionConnector},
-        util::{FnHub, VolatileHashSet},
+        storage::DiskSpaceGate,
+        util::{FnHandle, FnHub, FnRegistrar, VolatileHashSet},
     },
 };
 
-use super::{HandshakeType, NodeProfileFetcher, NodeProfileRepo, SessionStatus, TaskAccepter, TaskCommunicator, TaskComputer, TaskConnector};
+use super::{
+    AssetKeyLocationFoundEvent, ConnectionPacer, ConnectionPacerOption, ConnectionPacerStatus, HandshakeType, NodeProfileBundle, NodeProfileFetcher,
+    NodeProfileRepo, SessionCloseReason, SessionClosedEvent, SessionRegistry, SessionStatus, TaskAccepter, TaskCommunicator, TaskComputer,
+    TaskConnector, TaskReaper, TaskReplacer,
+};
 
 #[allow(dead_code)]
 pub struct NodeFinder {
     my_node_profile: Arc<Mutex<NodeProfile>>,
-    tcp_connector: Arc<ConnectionTcpConnectorImpl>,
-    tcp_accepter: Arc<ConnectionTcpAccepterImpl>,
+    tcp_connector: Arc<dyn ConnectionTcpConnector + Send + Sync>,
+    tcp_accepter: Arc<dyn ConnectionTcpAccepter + Send + Sync>,
     session_connector: Arc<SessionConnector>,
     session_accepter: Arc<SessionAccepter>,
     node_profile_repo: Arc<NodeProfileRepo>,
     node_profile_fetcher: Arc<dyn NodeProfileFetcher + Send + Sync>,
     clock: Arc<dyn Clock<Utc> + Send + Sync>,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
+    disk_space_gate: DiskSpaceGate,
     option: NodeFinderOption,
 
     session_receiver: Arc<TokioMutex<mpsc::Receiver<(HandshakeType, Session)>>>,
     session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
-    sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
     connected_node_profiles: Arc<Mutex<VolatileHashSet<NodeProfile>>>,
+    priority_node_profiles: Arc<Mutex<VecDeque<NodeProfile>>>,
+    connection_pacer: Arc<ConnectionPacer>,
+    draining: Arc<AtomicBool>,
     get_want_asset_keys_fn: Arc<FnHub<Vec<AssetKey>, ()>>,
+    lookup_asset_keys: Arc<Mutex<HashMap<AssetKey, usize>>>,
+    lookup_want_asset_keys_handle: FnHandle<Vec<AssetKey>, ()>,
     get_push_asset_keys_fn: Arc<FnHub<Vec<AssetKey>, ()>>,
+    get_push_asset_pointers_fn: Arc<FnHub<Vec<AssetPointer>, ()>>,
+    session_closed_fn: Arc<FnHub<(), SessionClosedEvent>>,
+    asset_key_location_found_fn: Arc<FnHub<(), AssetKeyLocationFoundEvent>>,
 
     task_connectors: Arc<TokioMutex<Vec<TaskConnector>>>,
     task_acceptors: Arc<TokioMutex<Vec<TaskAccepter>>>,
     task_computer: Arc<TokioMutex<Option<TaskComputer>>>,
     task_communicator: Arc<TokioMutex<Option<TaskCommunicator>>>,
+    task_reaper: Arc<TokioMutex<Option<TaskReaper>>>,
+    task_replacer: Arc<TokioMutex<Option<TaskReplacer>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -52,27 +76,49 @@ pub struct NodeFinderOption {
     pub state_dir_path: String,
     pub max_connected_session_count: usize,
     pub max_accepted_session_count: usize,
+    pub connection_pacer: ConnectionPacerOption,
+    // この間受信の無いセッションは閉じる
+    pub session_idle_timeout: std::time::Duration,
+    // 他のノードから教わったアセットの場所を、再起動後も使えるように残しておく期間
+    pub asset_key_location_ttl: std::time::Duration,
+    // アセットの場所やポインタを配布する、距離の近いノードの数
+    pub replication_factor: usize,
+    // want しているキーを伝える、距離の近いノードの数
+    pub lookup_width: usize,
 }
 
 impl NodeFinder {
     #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        tcp_connector: Arc<ConnectionTcpConnectorImpl>,
-        tcp_accepter: Arc<ConnectionTcpAccepterImpl>,
+        tcp_connector: Arc<dyn ConnectionTcpConnector + Send + Sync>,
+        tcp_accepter: Arc<dyn ConnectionTcpAccepter + Send + Sync>,
         session_connector: Arc<SessionConnector>,
         session_accepter: Arc<SessionAccepter>,
         node_profile_repo: Arc<NodeProfileRepo>,
         node_profile_fetcher: Arc<dyn NodeProfileFetcher + Send + Sync>,
         clock: Arc<dyn Clock<Utc> + Send + Sync>,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
+        disk_space_gate: DiskSpaceGate,
         option: NodeFinderOption,
     ) -> Self {
         let (tx, rx) = mpsc::channel(20);
+        let connection_pacer = Arc::new(ConnectionPacer::new(option.connection_pacer.clone()));
+
+        // 探索中のキーは、他の利用者と同じく want として近いノードへ伝える
+        let get_want_asset_keys_fn = Arc::new(FnHub::new());
+        let lookup_asset_keys: Arc<Mutex<HashMap<AssetKey, usize>>> = Arc::new(Mutex::new(HashMap::new()));
+        let lookup_want_asset_keys_handle = {
+            let lookup_asset_keys = lookup_asset_keys.clone();
+            get_want_asset_keys_fn
+                .registrar()
+                .register(move |_| lookup_asset_keys.lock().keys().cloned().collect())
+        };
 
         let result = Self {
             my_node_profile: Arc::new(Mutex::new(NodeProfile {
                 id: Self::gen_id(),
                 addrs: Vec::new(),
+                reachable: false,
             })),
             tcp_connector,
             tcp_accepter,
@@ -82,19 +128,30 @@ impl NodeFinder {
             node_profile_fetcher,
             clock: clock.clone(),
             sleeper,
+            disk_space_gate,
             option,
 
             session_receiver: Arc::new(TokioMutex::new(rx)),
             session_sender: Arc::new(TokioMutex::new(tx)),
-            sessions: Arc::new(TokioRwLock::new(HashMap::new())),
+            sessions: Arc::new(SessionRegistry::new()),
             connected_node_profiles: Arc::new(Mutex::new(VolatileHashSet::new(Duration::seconds(180), clock))),
-            get_want_asset_keys_fn: Arc::new(FnHub::new()),
+            priority_node_profiles: Arc::new(Mutex::new(VecDeque::new())),
+            connection_pacer,
+            draining: Arc::new(AtomicBool::new(false)),
+            get_want_asset_keys_fn,
+            lookup_asset_keys,
+            lookup_want_asset_keys_handle,
             get_push_asset_keys_fn: Arc::new(FnHub::new()),
+            get_push_asset_pointers_fn: Arc::new(FnHub::new()),
+            session_closed_fn: Arc::new(FnHub::new()),
+            asset_key_location_found_fn: Arc::new(FnHub::new()),
 
             task_connectors: Arc::new(TokioMutex::new(Vec::new())),
             task_acceptors: Arc::new(TokioMutex::new(Vec::new())),
             task_computer: Arc::new(TokioMutex::new(None)),
             task_communicator: Arc::new(TokioMutex::new(None)),
+            task_reaper: Arc::new(TokioMutex::new(None)),
+            task_replacer: Arc::new(TokioMutex::new(None)),
         };
         result.run().await;
 
@@ -102,7 +159,188 @@ impl NodeFinder {
     }
 
     pub async fn get_session_count(&self) -> usize {
-        self.sessions.read().await.len()
+        self.sessions.len()
+    }
+
+    // 重みの大きい順に limit 件までを書き出す
+    pub async fn export_node_profiles(&self, path: &Path, limit: usize) -> anyhow::Result<usize> {
+        let bundle = self.node_profile_repo.export_bundle(limit).await?;
+        bundle.write_to(path).await?;
+        Ok(bundle.entries.len())
+    }
+
+    pub async fn import_node_profiles(&self, path: &Path) -> anyhow::Result<usize> {
+        let bundle = NodeProfileBundle::read_from(path).await?;
+        self.node_profile_repo.import_bundle(&bundle).await?;
+        Ok(bundle.entries.len())
+    }
+
+    // 返された FnHandle を保持している間、配布する自身のキーを問い合わせるために呼ばれる
+    pub fn on_get_push_asset_keys(&self) -> FnRegistrar<Vec<AssetKey>, ()> {
+        self.get_push_asset_keys_fn.registrar()
+    }
+
+    // 返された FnHandle を保持している間、配布する自身のポインタを問い合わせるために呼ばれる
+    pub fn on_get_push_asset_pointers(&self) -> FnRegistrar<Vec<AssetPointer>, ()> {
+        self.get_push_asset_pointers_fn.registrar()
+    }
+
+    // 接続中のノードから受け取ったもののうち、最も新しいものを返す
+    // 手元に無い場合は、キーを want することで近いノードから届くようになる
+    pub fn find_asset_pointer(&self, key: &AssetKey) -> Option<AssetPointer> {
+        let key = Arc::new(key.clone());
+        self.sessions
+            .snapshot()
+            .into_iter()
+            .filter_map(|(_, status)| status.received_data_message.lock().push_asset_pointers.get(&key).cloned())
+            .max_by_key(|n| n.sequence)
+            .map(|n| n.as_ref().clone())
+    }
+
+    // 接続中のノードから受け取ったものと、過去に受け取って残しているものを合わせて返す
+    // 再起動した直後はセッションが揃うまで後者のみになる
+    pub async fn find_node_profiles(&self, key: &AssetKey) -> anyhow::Result<Vec<NodeProfile>> {
+        let key = Arc::new(key.clone());
+        let mut res: Vec<NodeProfile> = Vec::new();
+        for (_, status) in self.sessions.snapshot() {
+            let received_data_message = status.received_data_message.lock();
+            for locations in [
+                received_data_message.give_asset_key_locations.get(&key),
+                received_data_message.push_asset_key_locations.get(&key),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                res.extend(locations.iter().map(|n| n.as_ref().clone()));
+            }
+        }
+        res.extend(self.node_profile_repo.get_asset_key_locations(&key).await?);
+
+        let mut seen: HashSet<Vec<u8>> = HashSet::new();
+        res.retain(|n| seen.insert(n.id.clone()));
+        Ok(res)
+    }
+
+    // 既知のものを返した後、ストリームが破棄されるか timeout を過ぎるまでキーを want し、
+    // 新たに見つかったものを順に返す
+    pub fn lookup_node_profiles(&self, key: &AssetKey, timeout: std::time::Duration) -> BoxStream<'_, anyhow::Result<NodeProfile>> {
+        let guard = LookupGuard::new(self.lookup_asset_keys.clone(), key.clone());
+        let started_at = self.clock.now();
+        let timeout = Duration::from_std(timeout).unwrap_or(Duration::MAX);
+
+        // 教わった場所はすぐに流し、取りこぼした分は定期的に探し直して拾う
+        let (found_sender, found_receiver) = mpsc::unbounded_channel::<NodeProfile>();
+        let found_handle = {
+            let key = key.clone();
+            self.asset_key_location_found_fn.registrar().register(move |event| {
+                if event.asset_key == key {
+                    for node_profile in event.node_profiles.iter() {
+                        let _ = found_sender.send(node_profile.clone());
+                    }
+                }
+            })
+        };
+
+        let state = (
+            guard,
+            found_handle,
+            found_receiver,
+            HashSet::<Vec<u8>>::new(),
+            VecDeque::<NodeProfile>::new(),
+            true,
+        );
+        futures::stream::try_unfold(
+            state,
+            move |(guard, found_handle, mut found_receiver, mut seen, mut pending, mut first)| async move {
+                loop {
+                    if let Some(node_profile) = pending.pop_front() {
+                        return Ok(Some((node_profile, (guard, found_handle, found_receiver, seen, pending, first))));
+                    }
+                    if !first {
+                        if self.clock.now() - started_at >= timeout {
+                            return Ok(None);
+                        }
+                        let found = tokio::select! {
+                            found = found_receiver.recv() => found,
+                            _ = self.sleeper.sleep(std::time::Duration::from_secs(1)) => None,
+                        };
+                        if let Some(node_profile) = found {
+                            if seen.insert(node_profile.id.clone()) {
+                                pending.push_back(node_profile);
+                            }
+                            continue;
+                        }
+                    }
+                    first = false;
+
+                    for node_profile in self.find_node_profiles(&guard.key).await? {
+                        if seen.insert(node_profile.id.clone()) {
+                            pending.push_back(node_profile);
+                        }
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    // 新しいセッションの確立を止め、送り残しの無いセッションから閉じていく
+    // deadline を過ぎても残ったセッションは閉じ、その数を返す。resume を呼ぶまで新しいセッションは張らない
+    pub async fn drain(&self, deadline: std::time::Duration) -> usize {
+        self.draining.store(true, Ordering::SeqCst);
+        self.session_accepter.pause();
+        info!(session_count = self.sessions.len(), "draining sessions");
+
+        let started_at = self.clock.now();
+        let deadline = Duration::from_std(deadline).unwrap_or(Duration::MAX);
+        loop {
+            self.close_idle_sessions();
+            if self.sessions.is_empty() || self.clock.now() - started_at >= deadline {
+                break;
+            }
+            self.sleeper.sleep(std::time::Duration::from_secs(1)).await;
+        }
+
+        let remaining = self.sessions.snapshot();
+        for (_, status) in remaining.iter() {
+            status.close(SessionCloseReason::Drained);
+        }
+        remaining.len()
+    }
+
+    pub fn resume(&self) {
+        if self.draining.swap(false, Ordering::SeqCst) {
+            self.session_accepter.resume();
+            info!("resuming sessions");
+        }
+    }
+
+    // セッションは相手から閉じられない限り続くので、送るものが無くなったものはこちらから閉じる
+    fn close_idle_sessions(&self) {
+        for (_, status) in self.sessions.snapshot().iter() {
+            if status.sending_data_message.lock().is_empty() {
+                status.close(SessionCloseReason::Drained);
+            }
+        }
+    }
+
+    // 接続の試行とセッションの入れ替わりの状況
+    pub fn connection_status(&self) -> ConnectionPacerStatus {
+        self.connection_pacer.status()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    // 返された FnHandle を保持している間、want しているキーの新しい場所を教わる度に呼ばれる
+    pub fn on_asset_key_location_found(&self) -> FnRegistrar<(), AssetKeyLocationFoundEvent> {
+        self.asset_key_location_found_fn.registrar()
+    }
+
+    // 返された FnHandle を保持している間、セッションが閉じる度に呼ばれる
+    pub fn on_session_closed(&self) -> FnRegistrar<(), SessionClosedEvent> {
+        self.session_closed_fn.registrar()
     }
 
     fn gen_id() -> Vec<u8> {
@@ -112,7 +350,57 @@ impl NodeFinder {
         id.to_vec()
     }
 
+    // 自身のノード情報で、外部から直接接続できると伝えているか
+    pub fn is_reachable(&self) -> bool {
+        self.my_node_profile.lock().reachable
+    }
+
+    // 待ち受けに使えるグローバルアドレス (UPnP で得た外部アドレスを含む) があれば、直接接続できるとみなす
+    async fn update_reachability(&self) {
+        let reachable = match self.tcp_accepter.get_global_ip_addresses().await {
+            Ok(addrs) => !addrs.is_empty(),
+            Err(e) => {
+                warn!(error_message = e.to_string(), "get global ip addresses failed");
+                false
+            }
+        };
+        self.my_node_profile.lock().reachable = reachable;
+        info!(reachable, "reachability detected");
+    }
+
+    // 前回接続できていたノードを、ブートストラップやノード情報の交換を待たずに最初に試す
+    async fn load_warm_start_node_profiles(&self) {
+        match self
+            .node_profile_repo
+            .get_last_connected_node_profiles(self.option.max_connected_session_count * 2)
+            .await
+        {
+            Ok(node_profiles) => {
+                info!(count = node_profiles.len(), "warm start node profiles loaded");
+                *self.priority_node_profiles.lock() = node_profiles.into();
+            }
+            Err(e) => warn!(error_message = e.to_string(), "load warm start node profiles failed"),
+        }
+    }
+
+    // 停止時点で繋がっていたノードを、次回の起動時に最初に試せるよう残す
+    async fn save_last_connected_node_profiles(&self) -> anyhow::Result<()> {
+        let node_profiles: Vec<NodeProfile> = self
+            .sessions
+            .snapshot()
+            .into_iter()
+            .filter(|(_, status)| status.handshake_type == HandshakeType::Connected)
+            .map(|(_, status)| status.node_profile.clone())
+            .collect();
+        let node_profiles: Vec<&NodeProfile> = node_profiles.iter().collect();
+        self.node_profile_repo.upsert_last_connected_node_profiles(&node_profiles).await?;
+        Ok(())
+    }
+
     async fn run(&self) {
+        self.update_reachability().await;
+        self.load_warm_start_node_profiles().await;
+
         for _ in 0..3 {
             let task = TaskConnector::new(
                 self.sessions.clone(),
@@ -120,6 +408,9 @@ impl NodeFinder {
                 self.session_connector.clone(),
                 self.connected_node_profiles.clone(),
                 self.node_profile_repo.clone(),
+                self.connection_pacer.clone(),
+                self.draining.clone(),
+                self.priority_node_profiles.clone(),
                 self.sleeper.clone(),
                 self.option.clone(),
             );
@@ -132,6 +423,7 @@ impl NodeFinder {
                 self.sessions.clone(),
                 self.session_sender.clone(),
                 self.session_accepter.clone(),
+                self.draining.clone(),
                 self.option.clone(),
                 self.sleeper.clone(),
             );
@@ -146,7 +438,9 @@ impl NodeFinder {
             self.sessions.clone(),
             self.get_want_asset_keys_fn.executor(),
             self.get_push_asset_keys_fn.executor(),
+            self.get_push_asset_pointers_fn.executor(),
             self.sleeper.clone(),
+            self.option.clone(),
         );
         task.run().await;
         self.task_computer.lock().await.replace(task);
@@ -156,11 +450,60 @@ impl NodeFinder {
             self.sessions.clone(),
             self.node_profile_repo.clone(),
             self.session_receiver.clone(),
+            self.connection_pacer.clone(),
+            self.session_closed_fn.executor(),
+            self.get_want_asset_keys_fn.executor(),
+            self.asset_key_location_found_fn.executor(),
             self.clock.clone(),
             self.sleeper.clone(),
+            self.disk_space_gate.clone(),
+            self.option.clone(),
         );
         task.run().await;
         self.task_communicator.lock().await.replace(task);
+
+        let task = TaskReaper::new(self.sessions.clone(), self.clock.clone(), self.sleeper.clone(), self.option.clone());
+        task.run().await;
+        self.task_reaper.lock().await.replace(task);
+
+        let task = TaskReplacer::new(
+            self.sessions.clone(),
+            self.node_profile_repo.clone(),
+            self.session_sender.clone(),
+            self.session_connector.clone(),
+            self.connected_node_profiles.clone(),
+            self.get_want_asset_keys_fn.executor(),
+            self.draining.clone(),
+            self.sleeper.clone(),
+            self.option.clone(),
+        );
+        task.run().await;
+        self.task_replacer.lock().await.replace(task);
+    }
+}
+
+// 破棄されるまで、探索中のキーとして数える
+struct LookupGuard {
+    lookup_asset_keys: Arc<Mutex<HashMap<AssetKey, usize>>>,
+    key: AssetKey,
+}
+
+impl LookupGuard {
+    fn new(lookup_asset_keys: Arc<Mutex<HashMap<AssetKey, usize>>>, key: AssetKey) -> Self {
+        *lookup_asset_keys.lock().entry(key.clone()).or_default() += 1;
+        Self { lookup_asset_keys, key }
+    }
+}
+
+impl Drop for LookupGuard {
+    fn drop(&mut self) {
+        let mut lookup_asset_keys = self.lookup_asset_keys.lock();
+        if let Some(count) = lookup_asset_keys.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                lookup_asset_keys.remove(&self.key);
+            }
+        }
     }
 }
 
@@ -168,6 +511,10 @@ impl NodeFinder {
 impl Terminable for NodeFinder {
     type Error = anyhow::Error;
     async fn terminate(&self) -> anyhow::Result<()> {
+        if let Err(e) = self.save_last_connected_node_profiles().await {
+            warn!(error_message = e.to_string(), "save last connected node profiles failed");
+        }
+
         {
             let mut task_connectors = self.task_connectors.lock().await;
             let task_connectors: Vec<TaskConnector> = task_connectors.drain(..).collect();
@@ -187,6 +534,20 @@ impl Terminable for NodeFinder {
             }
         }
 
+        {
+            let mut task_reaper = self.task_reaper.lock().await;
+            if let Some(task_reaper) = task_reaper.take() {
+                task_reaper.terminate().await?;
+            }
+        }
+
+        {
+            let mut task_replacer = self.task_replacer.lock().await;
+            if let Some(task_replacer) = task_replacer.take() {
+                task_replacer.terminate().await?;
+            }
+        }
+
         {
             let mut task_communicator = self.task_communicator.lock().await;
             if let Some(task_communicator) = task_communicator.take() {
@@ -194,8 +555,8 @@ impl Terminable for NodeFinder {
             }
         }
 
+        // 待ち受けは他の利用者と共有しているため、所有者が停止する
         self.session_accepter.terminate().await?;
-        self.tcp_accepter.terminate().await?;
 
         Ok(())
     }
@@ -221,13 +582,14 @@ mod tests {
     use crate::{
         model::NodeProfile,
         service::{
-            connection::{ConnectionTcpAccepterImpl, ConnectionTcpConnectorImpl, TcpProxyOption, TcpProxyType},
+            connection::{BandwidthLimiter, ConnectionTcpAccepterImpl, ConnectionTcpConnectorImpl, TcpProxyOption, TcpProxyType},
             engine::{node::NodeProfileRepo, NodeFinder, NodeProfileFetcherMock},
-            session::{SessionAccepter, SessionConnector},
+            session::{SessionAccepter, SessionAccepterOption, SessionConnector, SessionConnectorOption},
+            storage::{DiskSpaceWatchdogOption, TaskDiskSpaceWatchdog},
         },
     };
 
-    use super::NodeFinderOption;
+    use super::{ConnectionPacerOption, NodeFinderOption};
 
     #[ignore]
     #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
@@ -239,10 +601,12 @@ mod tests {
         let np1 = NodeProfile {
             id: "1".as_bytes().to_vec(),
             addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60001)")],
+            reachable: false,
         };
         let np2 = NodeProfile {
             id: "2".as_bytes().to_vec(),
             addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60002)")],
+            reachable: false,
         };
 
         let nf1_path = dir.path().join("1");
@@ -275,12 +639,23 @@ mod tests {
     }
 
     async fn create_node_finder(dir_path: &Path, name: &str, port: u16, other_node_profile: NodeProfile) -> anyhow::Result<NodeFinder> {
-        let tcp_accepter = Arc::new(ConnectionTcpAccepterImpl::new(&OmniAddr::create_tcp("127.0.0.1".parse()?, port), false).await?);
+        let tcp_accepter = Arc::new(
+            ConnectionTcpAccepterImpl::new(
+                &OmniAddr::create_tcp("127.0.0.1".parse()?, port),
+                false,
+                Arc::new(BandwidthLimiter::default()),
+            )
+            .await?,
+        );
         let tcp_connector = Arc::new(
-            ConnectionTcpConnectorImpl::new(TcpProxyOption {
-                typ: TcpProxyType::None,
-                addr: None,
-            })
+            ConnectionTcpConnectorImpl::new(
+                TcpProxyOption {
+                    typ: TcpProxyType::None,
+                    addr: None,
+                    credential: None,
+                },
+                Arc::new(BandwidthLimiter::default()),
+            )
             .await?,
         );
 
@@ -289,9 +664,22 @@ mod tests {
         let signer = Arc::new(OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, name)?);
         let random_bytes_provider = Arc::new(Mutex::new(RandomBytesProviderImpl::new()));
 
-        let session_accepter =
-            Arc::new(SessionAccepter::new(tcp_accepter.clone(), signer.clone(), random_bytes_provider.clone(), sleeper.clone()).await);
-        let session_connector = Arc::new(SessionConnector::new(tcp_connector.clone(), signer, random_bytes_provider));
+        let session_accepter = Arc::new(
+            SessionAccepter::new(
+                tcp_accepter.clone(),
+                signer.clone(),
+                random_bytes_provider.clone(),
+                sleeper.clone(),
+                SessionAccepterOption::default(),
+            )
+            .await,
+        );
+        let session_connector = Arc::new(SessionConnector::new(
+            tcp_connector.clone(),
+            signer,
+            random_bytes_provider,
+            SessionConnectorOption::default(),
+        ));
 
         let node_ref_repo_dir = dir_path.join(name).join("repo");
         fs::create_dir_all(&node_ref_repo_dir)?;
@@ -305,6 +693,8 @@ mod tests {
         let node_finder_dir = dir_path.join(name).join("finder");
         fs::create_dir_all(&node_finder_dir)?;
 
+        let disk_space_watchdog = TaskDiskSpaceWatchdog::new(&node_finder_dir, sleeper.clone(), DiskSpaceWatchdogOption::default());
+
         let result = NodeFinder::new(
             tcp_connector,
             tcp_accepter,
@@ -314,10 +704,16 @@ mod tests {
             node_profile_fetcher,
             clock,
             sleeper,
+            disk_space_watchdog.gate(),
             NodeFinderOption {
                 state_dir_path: node_finder_dir.as_os_str().to_str().unwrap().to_string(),
                 max_connected_session_count: 3,
                 max_accepted_session_count: 3,
+                connection_pacer: ConnectionPacerOption::default(),
+                session_idle_timeout: std::time::Duration::from_secs(180),
+                asset_key_location_ttl: std::time::Duration::from_secs(60 * 60),
+                replication_factor: 1,
+                lookup_width: 1,
             },
         )
         .await;
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use crate::{model::NodeProfile, service::util::UriConverter};
+
+const HEADER: &str = "axus-node-profiles 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeProfileBundleEntry {
+    pub node_profile: NodeProfile,
+    pub weight: i64,
+}
+
+// 既知のノード情報を別のマシンへ持ち運ぶためのファイル
+// 1 行目がヘッダで、以降は 1 行につき「重み<TAB>URI」。空行と # で始まる行は無視する
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeProfileBundle {
+    pub entries: Vec<NodeProfileBundleEntry>,
+}
+
+impl NodeProfileBundle {
+    pub fn to_text(&self) -> anyhow::Result<String> {
+        let mut res = String::new();
+        res.push_str(HEADER);
+        res.push('\n');
+        for entry in self.entries.iter() {
+            res.push_str(format!("{}\t{}\n", entry.weight, UriConverter::encode_node_profile(&entry.node_profile)?).as_str());
+        }
+        Ok(res)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines().map(|n| n.trim()).filter(|n| !n.is_empty() && !n.starts_with('#'));
+        if lines.next() != Some(HEADER) {
+            anyhow::bail!("invalid header");
+        }
+
+        let mut entries = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let (weight, uri) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow::anyhow!("invalid entry at line {}", i + 2))?;
+            entries.push(NodeProfileBundleEntry {
+                node_profile: UriConverter::decode_node_profile(uri.trim())?,
+                weight: weight.parse()?,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub async fn read_from(path: &Path) -> anyhow::Result<Self> {
+        let text = tokio::fs::read_to_string(path).await?;
+        Self::parse(&text)
+    }
+
+    pub async fn write_to(&self, path: &Path) -> anyhow::Result<()> {
+        // 書き込み途中で停止しても壊れないよう、一時ファイルを経由して置き換える
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, self.to_text()?).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+
+    use omnius_core_omnikit::model::OmniAddr;
+
+    use crate::model::NodeProfile;
+
+    use super::{NodeProfileBundle, NodeProfileBundleEntry};
+
+    #[tokio::test]
+    pub async fn simple_test() -> TestResult {
+        let bundle = NodeProfileBundle {
+            entries: vec![
+                NodeProfileBundleEntry {
+                    node_profile: NodeProfile {
+                        id: vec![1],
+                        addrs: vec![OmniAddr::new("tcp(ip4(192.0.2.1),60000)")],
+                        reachable: false,
+                    },
+                    weight: 2,
+                },
+                NodeProfileBundleEntry {
+                    node_profile: NodeProfile {
+                        id: vec![2],
+                        addrs: vec![OmniAddr::new("tcp(ip4(192.0.2.2),60000)")],
+                        reachable: false,
+                    },
+                    weight: -1,
+                },
+            ],
+        };
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("node_profiles.txt");
+        bundle.write_to(&path).await?;
+        assert_eq!(NodeProfileBundle::read_from(&path).await?, bundle);
+
+        // 手で編集したコメントや空行は読み飛ばす
+        let text = format!("# exported\n\n{}", bundle.to_text()?);
+        assert_eq!(NodeProfileBundle::parse(&text)?, bundle);
+
+        assert!(NodeProfileBundle::parse("").is_err());
+        assert!(NodeProfileBundle::parse("axus-node-profiles 1\nabc\n").is_err());
+
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
+use std::path::{Path, PathBuf};
+
 use async_trait::async_trait;
+use tokio::sync::Mutex as TokioMutex;
 
 use crate::{model::NodeProfile, service::util::UriConverter};
 
@@ -40,6 +43,83 @@ impl NodeProfileFetcher for NodeProfileFetcherImpl {
     }
 }
 
+// 設定で指定された URI と、実行時に追加された (ファイルに永続化される) URI から取得する
+pub struct NodeProfileFetcherBootstrap {
+    static_uris: Vec<String>,
+    file_path: PathBuf,
+    dynamic_uris: TokioMutex<Vec<String>>,
+}
+
+impl NodeProfileFetcherBootstrap {
+    pub async fn new<P: AsRef<Path>>(static_uris: &[&str], file_path: P) -> anyhow::Result<Self> {
+        for uri in static_uris {
+            UriConverter::decode_node_profile(uri)?;
+        }
+
+        let file_path = file_path.as_ref().to_path_buf();
+        let dynamic_uris = match tokio::fs::read_to_string(&file_path).await {
+            Ok(text) => text.split_whitespace().map(|n| n.to_string()).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            static_uris: static_uris.iter().map(|&n| n.to_string()).collect(),
+            file_path,
+            dynamic_uris: TokioMutex::new(dynamic_uris),
+        })
+    }
+
+    pub async fn uris(&self) -> Vec<String> {
+        let mut res = self.static_uris.clone();
+        res.extend(self.dynamic_uris.lock().await.iter().cloned());
+        res
+    }
+
+    pub async fn add(&self, uri: &str) -> anyhow::Result<()> {
+        UriConverter::decode_node_profile(uri)?;
+
+        let mut dynamic_uris = self.dynamic_uris.lock().await;
+        if self.static_uris.iter().any(|n| n == uri) || dynamic_uris.iter().any(|n| n == uri) {
+            return Ok(());
+        }
+        dynamic_uris.push(uri.to_string());
+        self.save(&dynamic_uris).await
+    }
+
+    pub async fn remove(&self, uri: &str) -> anyhow::Result<bool> {
+        let mut dynamic_uris = self.dynamic_uris.lock().await;
+        let len = dynamic_uris.len();
+        dynamic_uris.retain(|n| n != uri);
+        if dynamic_uris.len() == len {
+            return Ok(false);
+        }
+        self.save(&dynamic_uris).await?;
+        Ok(true)
+    }
+
+    async fn save(&self, uris: &[String]) -> anyhow::Result<()> {
+        // 書き込み途中で停止しても壊れないよう、一時ファイルを経由して置き換える
+        let tmp_path = self.file_path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, uris.join("\n")).await?;
+        tokio::fs::rename(&tmp_path, &self.file_path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NodeProfileFetcher for NodeProfileFetcherBootstrap {
+    async fn fetch(&self) -> anyhow::Result<Vec<NodeProfile>> {
+        let mut vs: Vec<NodeProfile> = vec![];
+        for uri in self.uris().await {
+            if let Ok(node_profile) = UriConverter::decode_node_profile(&uri) {
+                vs.push(node_profile);
+            }
+        }
+        Ok(vs)
+    }
+}
+
 pub struct NodeProfileFetcherMock {
     pub node_profiles: Vec<NodeProfile>,
 }
@@ -50,3 +130,49 @@ impl NodeProfileFetcher for NodeProfileFetcherMock {
         Ok(self.node_profiles.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+
+    use omnius_core_omnikit::model::OmniAddr;
+
+    use crate::{model::NodeProfile, service::util::UriConverter};
+
+    use super::{NodeProfileFetcher as _, NodeProfileFetcherBootstrap};
+
+    #[tokio::test]
+    pub async fn bootstrap_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let file_path = dir.path().join("bootstrap.txt");
+
+        let np1 = NodeProfile {
+            id: vec![1],
+            addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60001)")],
+            reachable: false,
+        };
+        let np2 = NodeProfile {
+            id: vec![2],
+            addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60002)")],
+            reachable: false,
+        };
+        let uri1 = UriConverter::encode_node_profile(&np1)?;
+        let uri2 = UriConverter::encode_node_profile(&np2)?;
+
+        let fetcher = NodeProfileFetcherBootstrap::new(&[uri1.as_str()], &file_path).await?;
+        assert!(fetcher.add("invalid").await.is_err());
+        fetcher.add(&uri2).await?;
+        assert_eq!(fetcher.fetch().await?, vec![np1.clone(), np2.clone()]);
+
+        // 実行時に追加したものは再起動後も残る
+        let fetcher = NodeProfileFetcherBootstrap::new(&[uri1.as_str()], &file_path).await?;
+        assert_eq!(fetcher.uris().await, vec![uri1.clone(), uri2.clone()]);
+
+        assert!(fetcher.remove(&uri2).await?);
+        assert!(!fetcher.remove(&uri1).await?);
+        let fetcher = NodeProfileFetcherBootstrap::new(&[uri1.as_str()], &file_path).await?;
+        assert_eq!(fetcher.fetch().await?, vec![np1]);
+
+        Ok(())
+    }
+}
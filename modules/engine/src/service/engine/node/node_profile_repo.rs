@@ -1,13 +1,21 @@
 use std::{path::Path, sync::Arc};
 
-use chrono::Utc;
+use chrono::{Duration, NaiveDateTime, Utc};
+use futures::{stream::BoxStream, StreamExt as _, TryStreamExt as _};
 use omnius_core_base::clock::Clock;
 use sqlx::migrate::MigrateDatabase;
 use sqlx::QueryBuilder;
 use sqlx::{sqlite::SqlitePool, Sqlite};
 
-use crate::service::util::{MigrationRequest, SqliteMigrator};
-use crate::{model::NodeProfile, service::util::UriConverter};
+use crate::service::util::{MigrationRequest, SqliteBackup, SqliteMigrator};
+use crate::{
+    model::{AssetKey, NodeProfile},
+    service::util::UriConverter,
+};
+
+use super::{NodeProfileBundle, NodeProfileBundleEntry};
+
+const FETCH_CHUNK_SIZE: i64 = 256;
 
 pub struct NodeProfileRepo {
     db: Arc<SqlitePool>,
@@ -35,9 +43,10 @@ impl NodeProfileRepo {
     async fn migrate(&self) -> anyhow::Result<()> {
         let migrator = SqliteMigrator::new(self.db.clone());
 
-        let requests = vec![MigrationRequest {
-            name: "2024-03-19_init".to_string(),
-            queries: r#"
+        let requests = vec![
+            MigrationRequest {
+                name: "2024-03-19_init".to_string(),
+                queries: r#"
 CREATE TABLE IF NOT EXISTS node_profiles (
     value TEXT NOT NULL PRIMARY KEY,
     weight INTEGER NOT NULL,
@@ -45,28 +54,114 @@ CREATE TABLE IF NOT EXISTS node_profiles (
     updated_time TIMESTAMP NOT NULL
 );
 "#
-            .to_string(),
-        }];
+                .to_string(),
+            },
+            MigrationRequest {
+                name: "2024-10-05_asset_key_locations".to_string(),
+                queries: r#"
+CREATE TABLE IF NOT EXISTS asset_key_locations (
+    asset_key_typ TEXT NOT NULL,
+    asset_key_hash TEXT NOT NULL,
+    node_profile TEXT NOT NULL,
+    expires_time TIMESTAMP NOT NULL,
+    PRIMARY KEY (asset_key_typ, asset_key_hash, node_profile)
+);
+CREATE INDEX IF NOT EXISTS index_expires_time_for_asset_key_locations ON asset_key_locations (expires_time);
+"#
+                .to_string(),
+            },
+            MigrationRequest {
+                name: "2024-10-12_last_connected_node_profiles".to_string(),
+                queries: r#"
+CREATE TABLE IF NOT EXISTS last_connected_node_profiles (
+    value TEXT NOT NULL PRIMARY KEY,
+    connected_time TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS index_connected_time_for_last_connected_node_profiles ON last_connected_node_profiles (connected_time);
+"#
+                .to_string(),
+            },
+        ];
 
         migrator.migrate(requests).await?;
 
         Ok(())
     }
 
+    pub async fn backup_to(&self, path: &Path) -> anyhow::Result<()> {
+        SqliteBackup::backup(self.db.as_ref(), path).await
+    }
+
     pub async fn get_node_profiles(&self) -> anyhow::Result<Vec<NodeProfile>> {
-        let res: Vec<(String,)> = sqlx::query_as(
-            r#"
-SELECT value FROM node_profiles
-ORDER BY weight DESC, updated_time DESC
-"#,
-        )
-        .fetch_all(self.db.as_ref())
-        .await?;
+        self.stream_node_profiles().try_collect().await
+    }
 
-        let res: Vec<NodeProfile> = res
-            .into_iter()
-            .filter_map(|(v,)| UriConverter::decode_node_profile(v.as_str()).ok())
-            .collect();
+    // テーブル全体を読み込まないよう、FETCH_CHUNK_SIZE 件ずつ取得しながら返す
+    pub fn stream_node_profiles(&self) -> BoxStream<'_, anyhow::Result<NodeProfile>> {
+        futures::stream::try_unfold(Some(None), move |cursor: Option<Option<NodeProfileCursor>>| async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+
+            let rows = self.fetch_node_profiles_chunk(cursor.as_ref()).await?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+
+            let next = if (rows.len() as i64) < FETCH_CHUNK_SIZE {
+                None
+            } else {
+                rows.last().map(|(rowid, _, weight, updated_time)| {
+                    Some(NodeProfileCursor {
+                        weight: *weight,
+                        updated_time: *updated_time,
+                        rowid: *rowid,
+                    })
+                })
+            };
+
+            let node_profiles: Vec<anyhow::Result<NodeProfile>> = rows
+                .into_iter()
+                .filter_map(|(_, v, _, _)| UriConverter::decode_node_profile(v.as_str()).ok())
+                .map(Ok)
+                .collect();
+            Ok(Some((futures::stream::iter(node_profiles), next)))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    async fn fetch_node_profiles_chunk(&self, cursor: Option<&NodeProfileCursor>) -> anyhow::Result<Vec<(i64, String, i64, NaiveDateTime)>> {
+        let res: Vec<(i64, String, i64, NaiveDateTime)> = match cursor {
+            None => {
+                sqlx::query_as(
+                    r#"
+SELECT rowid, value, weight, updated_time FROM node_profiles
+ORDER BY weight DESC, updated_time DESC, rowid ASC
+LIMIT ?
+"#,
+                )
+                .bind(FETCH_CHUNK_SIZE)
+                .fetch_all(self.db.as_ref())
+                .await?
+            }
+            Some(cursor) => {
+                sqlx::query_as(
+                    r#"
+SELECT rowid, value, weight, updated_time FROM node_profiles
+WHERE weight < ?1 OR (weight = ?1 AND (updated_time < ?2 OR (updated_time = ?2 AND rowid > ?3)))
+ORDER BY weight DESC, updated_time DESC, rowid ASC
+LIMIT ?4
+"#,
+                )
+                .bind(cursor.weight)
+                .bind(cursor.updated_time)
+                .bind(cursor.rowid)
+                .bind(FETCH_CHUNK_SIZE)
+                .fetch_all(self.db.as_ref())
+                .await?
+            }
+        };
         Ok(res)
     }
 
@@ -91,6 +186,52 @@ INSERT OR IGNORE INTO node_profiles (value, weight, created_time, updated_time)
         Ok(())
     }
 
+    pub async fn export_bundle(&self, limit: usize) -> anyhow::Result<NodeProfileBundle> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            r#"
+SELECT value, weight FROM node_profiles
+ORDER BY weight DESC, updated_time DESC, rowid ASC
+LIMIT ?
+"#,
+        )
+        .bind(limit as i64)
+        .fetch_all(self.db.as_ref())
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|(v, weight)| {
+                let node_profile = UriConverter::decode_node_profile(v.as_str()).ok()?;
+                Some(NodeProfileBundleEntry { node_profile, weight })
+            })
+            .collect();
+        Ok(NodeProfileBundle { entries })
+    }
+
+    // 既に存在するものは重みを大きい方に揃える
+    pub async fn import_bundle(&self, bundle: &NodeProfileBundle) -> anyhow::Result<()> {
+        let now = self.clock.now().naive_utc();
+        let mut tx = self.db.begin().await?;
+        for entry in bundle.entries.iter() {
+            let value = UriConverter::encode_node_profile(&entry.node_profile)?;
+            sqlx::query(
+                r#"
+INSERT INTO node_profiles (value, weight, created_time, updated_time)
+VALUES (?1, ?2, ?3, ?3)
+ON CONFLICT(value) DO UPDATE SET weight = MAX(weight, excluded.weight), updated_time = excluded.updated_time
+"#,
+            )
+            .bind(value)
+            .bind(entry.weight)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     pub async fn shrink(&self, limit: usize) -> anyhow::Result<()> {
         let total: i64 = sqlx::query_scalar(
             r#"
@@ -120,19 +261,169 @@ WHERE rowid IN (
 
         Ok(())
     }
+
+    // 接続に成功したノードを、その時刻と共に残す。既に存在するものは時刻を更新する
+    pub async fn upsert_last_connected_node_profiles(&self, vs: &[&NodeProfile]) -> anyhow::Result<()> {
+        let now = self.clock.now().naive_utc();
+        let mut tx = self.db.begin().await?;
+        for node_profile in vs {
+            let value = UriConverter::encode_node_profile(node_profile)?;
+            sqlx::query(
+                r#"
+INSERT INTO last_connected_node_profiles (value, connected_time)
+VALUES (?1, ?2)
+ON CONFLICT(value) DO UPDATE SET connected_time = excluded.connected_time
+"#,
+            )
+            .bind(value)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 最近接続に成功したものから順に返す
+    pub async fn get_last_connected_node_profiles(&self, limit: usize) -> anyhow::Result<Vec<NodeProfile>> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+SELECT value FROM last_connected_node_profiles
+ORDER BY connected_time DESC, rowid DESC
+LIMIT ?
+"#,
+        )
+        .bind(limit as i64)
+        .fetch_all(self.db.as_ref())
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(v,)| UriConverter::decode_node_profile(v.as_str()).ok())
+            .collect())
+    }
+
+    pub async fn shrink_last_connected_node_profiles(&self, limit: usize) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+DELETE FROM last_connected_node_profiles
+WHERE rowid NOT IN (
+    SELECT rowid FROM last_connected_node_profiles
+    ORDER BY connected_time DESC, rowid DESC
+    LIMIT ?
+)
+"#,
+        )
+        .bind(limit as i64)
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    // 他のノードから教わった、アセットを持つノードの情報を残す。既に存在するものは期限を延ばす
+    pub async fn upsert_asset_key_locations(&self, vs: &[(&AssetKey, &NodeProfile)], ttl: Duration) -> anyhow::Result<()> {
+        let expires_time = (self.clock.now() + ttl).naive_utc();
+        let mut tx = self.db.begin().await?;
+        for (asset_key, node_profile) in vs {
+            let node_profile = UriConverter::encode_node_profile(node_profile)?;
+            sqlx::query(
+                r#"
+INSERT INTO asset_key_locations (asset_key_typ, asset_key_hash, node_profile, expires_time)
+VALUES (?1, ?2, ?3, ?4)
+ON CONFLICT(asset_key_typ, asset_key_hash, node_profile) DO UPDATE SET expires_time = MAX(expires_time, excluded.expires_time)
+"#,
+            )
+            .bind(asset_key.typ.as_str())
+            .bind(asset_key.hash.to_string())
+            .bind(node_profile)
+            .bind(expires_time)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // 期限切れのものは返さない
+    pub async fn get_asset_key_locations(&self, asset_key: &AssetKey) -> anyhow::Result<Vec<NodeProfile>> {
+        let now = self.clock.now().naive_utc();
+        let rows: Vec<(String,)> = sqlx::query_as(
+            r#"
+SELECT node_profile FROM asset_key_locations
+WHERE asset_key_typ = ?1 AND asset_key_hash = ?2 AND expires_time > ?3
+ORDER BY expires_time DESC
+"#,
+        )
+        .bind(asset_key.typ.as_str())
+        .bind(asset_key.hash.to_string())
+        .bind(now)
+        .fetch_all(self.db.as_ref())
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(v,)| UriConverter::decode_node_profile(v.as_str()).ok())
+            .collect())
+    }
+
+    // 期限切れのものを消し、それでも limit を超える分は期限の近いものから消す
+    pub async fn shrink_asset_key_locations(&self, limit: usize) -> anyhow::Result<()> {
+        let now = self.clock.now().naive_utc();
+        sqlx::query(
+            r#"
+DELETE FROM asset_key_locations
+WHERE expires_time <= ?
+"#,
+        )
+        .bind(now)
+        .execute(self.db.as_ref())
+        .await?;
+
+        sqlx::query(
+            r#"
+DELETE FROM asset_key_locations
+WHERE rowid NOT IN (
+    SELECT rowid FROM asset_key_locations
+    ORDER BY expires_time DESC, rowid DESC
+    LIMIT ?
+)
+"#,
+        )
+        .bind(limit as i64)
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+}
+
+struct NodeProfileCursor {
+    weight: i64,
+    updated_time: NaiveDateTime,
+    rowid: i64,
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use chrono::DateTime;
+    use chrono::{DateTime, Duration};
+    use futures::TryStreamExt as _;
     use testresult::TestResult;
 
     use omnius_core_base::clock::FakeClockUtc;
-    use omnius_core_omnikit::model::OmniAddr;
+    use omnius_core_omnikit::model::{OmniAddr, OmniHash, OmniHashAlgorithmType};
 
-    use crate::model::NodeProfile;
+    use crate::{
+        model::{AssetKey, NodeProfile},
+        service::{
+            engine::{NodeProfileBundle, NodeProfileBundleEntry},
+            util::ManualClock,
+        },
+    };
 
     use super::NodeProfileRepo;
 
@@ -148,10 +439,12 @@ mod tests {
             NodeProfile {
                 id: vec![0],
                 addrs: vec![OmniAddr::new("test")],
+                reachable: false,
             },
             NodeProfile {
                 id: vec![1],
                 addrs: vec![OmniAddr::new("test")],
+                reachable: false,
             },
         ];
         let vs_ref: Vec<&NodeProfile> = vs.iter().collect();
@@ -170,4 +463,158 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    pub async fn stream_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = NodeProfileRepo::new(path, clock).await?;
+
+        // チャンクの境界をまたぐ件数で、重みの順序と件数が保たれることを確認する
+        let gen = |weight: i64, n: usize| -> Vec<NodeProfile> {
+            (0..n)
+                .map(|i| NodeProfile {
+                    id: format!("{}-{}", weight, i).into_bytes(),
+                    addrs: vec![OmniAddr::new("test")],
+                    reachable: false,
+                })
+                .collect()
+        };
+        let low = gen(0, 300);
+        let high = gen(1, 300);
+        repo.insert_bulk_node_profile(&low.iter().collect::<Vec<_>>(), 0).await?;
+        repo.insert_bulk_node_profile(&high.iter().collect::<Vec<_>>(), 1).await?;
+
+        let res: Vec<NodeProfile> = repo.stream_node_profiles().try_collect().await?;
+        assert_eq!(res, high.into_iter().chain(low).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn bundle_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let clock = Arc::new(FakeClockUtc::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+
+        let src_path = dir.path().join("src");
+        std::fs::create_dir_all(&src_path)?;
+        let src = NodeProfileRepo::new(src_path.to_str().unwrap(), clock.clone()).await?;
+
+        let np1 = NodeProfile {
+            id: vec![1],
+            addrs: vec![OmniAddr::new("test")],
+            reachable: false,
+        };
+        let np2 = NodeProfile {
+            id: vec![2],
+            addrs: vec![OmniAddr::new("test")],
+            reachable: false,
+        };
+        src.insert_bulk_node_profile(&[&np1], 0).await?;
+        src.insert_bulk_node_profile(&[&np2], 3).await?;
+
+        let bundle = src.export_bundle(10).await?;
+        assert_eq!(
+            bundle,
+            NodeProfileBundle {
+                entries: vec![
+                    NodeProfileBundleEntry {
+                        node_profile: np2.clone(),
+                        weight: 3,
+                    },
+                    NodeProfileBundleEntry {
+                        node_profile: np1.clone(),
+                        weight: 0,
+                    },
+                ],
+            }
+        );
+        assert_eq!(src.export_bundle(1).await?.entries.len(), 1);
+
+        let dst_path = dir.path().join("dst");
+        std::fs::create_dir_all(&dst_path)?;
+        let dst = NodeProfileRepo::new(dst_path.to_str().unwrap(), clock).await?;
+        dst.insert_bulk_node_profile(&[&np1], 5).await?;
+        dst.import_bundle(&bundle).await?;
+
+        // 既にあった np1 は重みの大きい方が残る
+        assert_eq!(dst.get_node_profiles().await?, vec![np1, np2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn asset_key_location_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(ManualClock::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = NodeProfileRepo::new(path, clock.clone()).await?;
+
+        let asset_key = AssetKey {
+            typ: "test".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+        let np1 = NodeProfile {
+            id: vec![1],
+            addrs: vec![OmniAddr::new("test")],
+            reachable: false,
+        };
+        let np2 = NodeProfile {
+            id: vec![2],
+            addrs: vec![OmniAddr::new("test")],
+            reachable: false,
+        };
+
+        repo.upsert_asset_key_locations(&[(&asset_key, &np1)], Duration::minutes(10)).await?;
+        clock.advance(Duration::minutes(5));
+        repo.upsert_asset_key_locations(&[(&asset_key, &np2)], Duration::minutes(10)).await?;
+        assert_eq!(repo.get_asset_key_locations(&asset_key).await?, vec![np2.clone(), np1.clone()]);
+
+        // 期限を過ぎたものは返さず、shrink で消える
+        clock.advance(Duration::minutes(6));
+        assert_eq!(repo.get_asset_key_locations(&asset_key).await?, vec![np2.clone()]);
+
+        repo.upsert_asset_key_locations(&[(&asset_key, &np1)], Duration::minutes(1)).await?;
+        repo.shrink_asset_key_locations(1).await?;
+        assert_eq!(repo.get_asset_key_locations(&asset_key).await?, vec![np2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn last_connected_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+
+        let clock = Arc::new(ManualClock::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let repo = NodeProfileRepo::new(path, clock.clone()).await?;
+
+        let nps: Vec<NodeProfile> = (0..3)
+            .map(|i| NodeProfile {
+                id: vec![i],
+                addrs: vec![OmniAddr::new("test")],
+                reachable: false,
+            })
+            .collect();
+
+        repo.upsert_last_connected_node_profiles(&[&nps[0], &nps[1]]).await?;
+        clock.advance(Duration::minutes(1));
+        repo.upsert_last_connected_node_profiles(&[&nps[2]]).await?;
+        clock.advance(Duration::minutes(1));
+        repo.upsert_last_connected_node_profiles(&[&nps[0]]).await?;
+
+        // 最近接続に成功したものから返す
+        assert_eq!(
+            repo.get_last_connected_node_profiles(10).await?,
+            vec![nps[0].clone(), nps[2].clone(), nps[1].clone()]
+        );
+
+        repo.shrink_last_connected_node_profiles(2).await?;
+        assert_eq!(repo.get_last_connected_node_profiles(10).await?, vec![nps[0].clone(), nps[2].clone()]);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,136 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use omnius_core_omnikit::model::OmniAddr;
+
+use crate::model::NodeProfile;
+
+// 接続を試みる価値のあるアドレスは、1 つのノードにつきこの数までとする
+pub const MAX_VALID_ADDR_COUNT: usize = 8;
+
+// 他のノードから受け取ったノード情報のうち、接続先として使えるものだけを残す
+// 自分自身、アドレスが 1 つも残らないもの、同じ ID の 2 件目以降は捨てる
+pub fn validate_node_profiles(node_profiles: &[NodeProfile], my_node_profile: &NodeProfile) -> Vec<NodeProfile> {
+    let my_addrs: HashSet<&OmniAddr> = my_node_profile.addrs.iter().collect();
+    let mut ids: HashSet<&[u8]> = HashSet::new();
+    let mut res: Vec<NodeProfile> = Vec::new();
+
+    for node_profile in node_profiles {
+        if node_profile.id.is_empty() || node_profile.id == my_node_profile.id {
+            continue;
+        }
+        if !ids.insert(node_profile.id.as_slice()) {
+            continue;
+        }
+
+        let mut seen: HashSet<&OmniAddr> = HashSet::new();
+        let addrs: Vec<OmniAddr> = node_profile
+            .addrs
+            .iter()
+            .filter(|n| !my_addrs.contains(n) && is_valid_addr(n))
+            .filter(|n| seen.insert(n))
+            .take(MAX_VALID_ADDR_COUNT)
+            .cloned()
+            .collect();
+        if addrs.is_empty() {
+            continue;
+        }
+
+        res.push(NodeProfile {
+            id: node_profile.id.clone(),
+            addrs,
+            reachable: node_profile.reachable,
+        });
+    }
+
+    res
+}
+
+fn is_valid_addr(addr: &OmniAddr) -> bool {
+    let Ok((host, port)) = addr.parse_tcp_host() else {
+        return false;
+    };
+    if host.is_empty() || port == 0 {
+        return false;
+    }
+
+    // ホスト名の場合は解決するまで分からないので通す
+    let Ok(ip) = host.parse::<IpAddr>() else {
+        return true;
+    };
+    match ip {
+        IpAddr::V4(ip) => !(ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast()),
+        IpAddr::V6(ip) => !(ip.is_unspecified() || ip.is_multicast()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use omnius_core_omnikit::model::OmniAddr;
+
+    use crate::model::NodeProfile;
+
+    use super::{validate_node_profiles, MAX_VALID_ADDR_COUNT};
+
+    #[test]
+    pub fn simple_test() {
+        let my_node_profile = NodeProfile {
+            id: vec![0],
+            addrs: vec![OmniAddr::new("tcp(ip4(192.0.2.1),60000)")],
+            reachable: false,
+        };
+
+        let node_profiles = vec![
+            // 自分自身
+            NodeProfile {
+                id: vec![0],
+                addrs: vec![OmniAddr::new("tcp(ip4(192.0.2.2),60000)")],
+                reachable: false,
+            },
+            NodeProfile {
+                id: vec![1],
+                addrs: vec![
+                    OmniAddr::new("tcp(ip4(0.0.0.0),60000)"),
+                    OmniAddr::new("tcp(ip4(255.255.255.255),60000)"),
+                    OmniAddr::new("tcp(ip4(192.0.2.1),60000)"),
+                    OmniAddr::new("tcp(ip4(192.0.2.3),0)"),
+                    OmniAddr::new("invalid"),
+                    OmniAddr::new("tcp(ip4(192.0.2.3),60000)"),
+                    OmniAddr::new("tcp(ip4(192.0.2.3),60000)"),
+                ],
+                reachable: false,
+            },
+            // 同じ ID の 2 件目
+            NodeProfile {
+                id: vec![1],
+                addrs: vec![OmniAddr::new("tcp(ip4(192.0.2.4),60000)")],
+                reachable: false,
+            },
+            // 有効なアドレスが残らない
+            NodeProfile {
+                id: vec![2],
+                addrs: vec![OmniAddr::new("tcp(ip4(0.0.0.0),60000)")],
+                reachable: false,
+            },
+            NodeProfile {
+                id: vec![3],
+                addrs: (0..MAX_VALID_ADDR_COUNT + 4)
+                    .map(|i| OmniAddr::new(format!("tcp(ip4(198.51.100.{}),60000)", i + 1).as_str()))
+                    .collect(),
+                reachable: false,
+            },
+        ];
+
+        let res = validate_node_profiles(&node_profiles, &my_node_profile);
+        assert_eq!(res.len(), 2);
+        assert_eq!(
+            res[0],
+            NodeProfile {
+                id: vec![1],
+                addrs: vec![OmniAddr::new("tcp(ip4(192.0.2.3),60000)")],
+                reachable: false,
+            }
+        );
+        assert_eq!(res[1].id, vec![3]);
+        assert_eq!(res[1].addrs.len(), MAX_VALID_ADDR_COUNT);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher as _,
+};
+
+use parking_lot::RwLock;
+
+const SHARD_COUNT: usize = 16;
+
+// ノード ID をキーとしたセッションの表。ロックの競合を減らすためシャードに分割する
+pub struct SessionRegistry<V> {
+    shards: Vec<RwLock<HashMap<Vec<u8>, V>>>,
+    hash_builder: RandomState,
+}
+
+impl<V: Clone> SessionRegistry<V> {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, id: &[u8]) -> &RwLock<HashMap<Vec<u8>, V>> {
+        let index = self.hash_builder.hash_one(id) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    // 既に存在する場合は追加せずに false を返す
+    pub fn try_insert(&self, id: Vec<u8>, value: V) -> bool {
+        let mut shard = self.shard(&id).write();
+        if shard.contains_key(&id) {
+            return false;
+        }
+        shard.insert(id, value);
+        true
+    }
+
+    pub fn remove(&self, id: &[u8]) -> Option<V> {
+        self.shard(id).write().remove(id)
+    }
+
+    pub fn get(&self, id: &[u8]) -> Option<V> {
+        self.shard(id).read().get(id).cloned()
+    }
+
+    pub fn contains(&self, id: &[u8]) -> bool {
+        self.shard(id).read().contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|n| n.read().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|n| n.read().is_empty())
+    }
+
+    pub fn count<F: Fn(&V) -> bool>(&self, f: F) -> usize {
+        self.shards.iter().map(|n| n.read().values().filter(|v| f(v)).count()).sum()
+    }
+
+    pub fn any<F: Fn(&V) -> bool>(&self, f: F) -> bool {
+        self.shards.iter().any(|n| n.read().values().any(&f))
+    }
+
+    // シャード毎にロックを取るので、全体として一貫した時点のものではない
+    pub fn snapshot(&self) -> Vec<(Vec<u8>, V)> {
+        let mut res = Vec::new();
+        for shard in self.shards.iter() {
+            res.extend(shard.read().iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        res
+    }
+}
+
+impl<V: Clone> Default for SessionRegistry<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use testresult::TestResult;
+
+    use super::SessionRegistry;
+
+    #[test]
+    pub fn simple_test() -> TestResult {
+        let registry: SessionRegistry<u32> = SessionRegistry::new();
+        assert!(registry.is_empty());
+
+        assert!(registry.try_insert(vec![1], 1));
+        assert!(registry.try_insert(vec![2], 2));
+        assert!(!registry.try_insert(vec![1], 3));
+
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get(&[1]), Some(1));
+        assert_eq!(registry.count(|v| *v % 2 == 0), 1);
+        assert!(registry.any(|v| *v == 2));
+
+        let mut snapshot = registry.snapshot();
+        snapshot.sort();
+        assert_eq!(snapshot, vec![(vec![1], 1), (vec![2], 2)]);
+
+        assert_eq!(registry.remove(&[1]), Some(1));
+        assert!(!registry.contains(&[1]));
+        assert_eq!(registry.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn concurrent_test() -> TestResult {
+        let registry: Arc<SessionRegistry<usize>> = Arc::new(SessionRegistry::new());
+
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    for i in 0..500 {
+                        let id = (t * 500 + i).to_be_bytes().to_vec();
+                        assert!(registry.try_insert(id.clone(), i));
+                        registry.count(|v| *v == 0);
+                        if i % 2 == 0 {
+                            registry.remove(&id);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(registry.len(), 8 * 250);
+
+        Ok(())
+    }
+}
@@ -1,38 +1,95 @@
 use std::{collections::HashMap, sync::Arc};
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use parking_lot::Mutex;
+use tokio_util::sync::CancellationToken;
 
 use omnius_core_base::clock::Clock;
 
 use crate::{
-    model::{AssetKey, NodeProfile},
+    model::{AssetKey, AssetPointer, NodeProfile},
     service::{
         session::model::Session,
         util::{VolatileHashMap, VolatileHashSet},
     },
 };
 
+use super::NodeCapabilities;
+
 #[derive(Clone)]
 pub struct SessionStatus {
     pub handshake_type: HandshakeType,
     pub session: Session,
     pub node_profile: NodeProfile,
+    // 自身と相手の双方が対応している機能
+    pub capabilities: NodeCapabilities,
 
     pub sending_data_message: Arc<Mutex<SendingDataMessage>>,
     pub received_data_message: Arc<Mutex<ReceivedDataMessage>>,
+
+    pub last_activity_time: Arc<Mutex<DateTime<Utc>>>,
+    pub close_reason: Arc<Mutex<Option<SessionCloseReason>>>,
+    pub cancellation_token: CancellationToken,
 }
 
 impl SessionStatus {
-    pub fn new(handshake_type: HandshakeType, session: Session, node_profile: NodeProfile, clock: Arc<dyn Clock<Utc> + Send + Sync>) -> Self {
+    pub fn new(
+        handshake_type: HandshakeType,
+        session: Session,
+        node_profile: NodeProfile,
+        capabilities: NodeCapabilities,
+        cancellation_token: CancellationToken,
+        clock: Arc<dyn Clock<Utc> + Send + Sync>,
+    ) -> Self {
         Self {
             handshake_type,
             session,
             node_profile,
+            capabilities,
             sending_data_message: Arc::new(Mutex::new(SendingDataMessage::new())),
-            received_data_message: Arc::new(Mutex::new(ReceivedDataMessage::new(clock))),
+            received_data_message: Arc::new(Mutex::new(ReceivedDataMessage::new(clock.clone()))),
+            last_activity_time: Arc::new(Mutex::new(clock.now())),
+            close_reason: Arc::new(Mutex::new(None)),
+            cancellation_token,
         }
     }
+
+    pub fn touch(&self, now: DateTime<Utc>) {
+        *self.last_activity_time.lock() = now;
+    }
+
+    pub fn idle_duration(&self, now: DateTime<Utc>) -> Duration {
+        now - *self.last_activity_time.lock()
+    }
+
+    // 最初に指定された理由を残し、送受信のタスクを止める
+    pub fn close(&self, reason: SessionCloseReason) {
+        self.close_reason.lock().get_or_insert(reason);
+        self.cancellation_token.cancel();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionCloseReason {
+    Disconnected,
+    Idle,
+    Drained,
+    // より近いノードと入れ替えるために閉じた
+    Replaced,
+    Shutdown,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionClosedEvent {
+    pub node_profile: NodeProfile,
+    pub reason: SessionCloseReason,
+}
+
+// want しているキーについて、まだ知らなかった場所を教わった
+#[derive(Debug, Clone)]
+pub struct AssetKeyLocationFoundEvent {
+    pub asset_key: AssetKey,
+    pub node_profiles: Vec<NodeProfile>,
 }
 
 #[allow(dead_code)]
@@ -48,6 +105,7 @@ pub struct SendingDataMessage {
     pub want_asset_keys: Vec<AssetKey>,
     pub give_asset_key_locations: HashMap<AssetKey, Vec<NodeProfile>>,
     pub push_asset_key_locations: HashMap<AssetKey, Vec<NodeProfile>>,
+    pub push_asset_pointers: Vec<AssetPointer>,
 }
 
 impl SendingDataMessage {
@@ -57,8 +115,17 @@ impl SendingDataMessage {
             want_asset_keys: vec![],
             give_asset_key_locations: HashMap::new(),
             push_asset_key_locations: HashMap::new(),
+            push_asset_pointers: vec![],
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.push_node_profiles.is_empty()
+            && self.want_asset_keys.is_empty()
+            && self.give_asset_key_locations.is_empty()
+            && self.push_asset_key_locations.is_empty()
+            && self.push_asset_pointers.is_empty()
+    }
 }
 
 impl Default for SendingDataMessage {
@@ -71,6 +138,7 @@ pub struct ReceivedDataMessage {
     pub want_asset_keys: VolatileHashSet<Arc<AssetKey>>,
     pub give_asset_key_locations: VolatileHashMap<Arc<AssetKey>, Vec<Arc<NodeProfile>>>,
     pub push_asset_key_locations: VolatileHashMap<Arc<AssetKey>, Vec<Arc<NodeProfile>>>,
+    pub push_asset_pointers: VolatileHashMap<Arc<AssetKey>, Arc<AssetPointer>>,
 }
 
 impl ReceivedDataMessage {
@@ -78,7 +146,8 @@ impl ReceivedDataMessage {
         Self {
             want_asset_keys: VolatileHashSet::new(Duration::minutes(30), clock.clone()),
             give_asset_key_locations: VolatileHashMap::new(Duration::minutes(30), clock.clone()),
-            push_asset_key_locations: VolatileHashMap::new(Duration::minutes(30), clock),
+            push_asset_key_locations: VolatileHashMap::new(Duration::minutes(30), clock.clone()),
+            push_asset_pointers: VolatileHashMap::new(Duration::minutes(30), clock),
         }
     }
 }
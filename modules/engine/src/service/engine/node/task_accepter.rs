@@ -1,34 +1,42 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use async_trait::async_trait;
-use futures::FutureExt;
 use tokio::{
-    sync::{mpsc, Mutex as TokioMutex, RwLock as TokioRwLock},
+    sync::{mpsc, Mutex as TokioMutex},
     task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
 use tracing::warn;
 
 use omnius_core_base::{sleeper::Sleeper, terminable::Terminable};
 
-use crate::service::session::{
-    model::{Session, SessionType},
-    SessionAccepter,
+use crate::service::{
+    session::{
+        model::{Session, SessionType},
+        SessionAccepter,
+    },
+    util::{shutdown_task, sleep_or_cancelled, TASK_SHUTDOWN_GRACE_PERIOD},
 };
 
-use super::{HandshakeType, NodeFinderOption, SessionStatus};
+use super::{HandshakeType, NodeFinderOption, SessionRegistry, SessionStatus};
 
 #[derive(Clone)]
 pub struct TaskAccepter {
     inner: Inner,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
     join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
 }
 
 impl TaskAccepter {
     pub fn new(
-        sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+        sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
         session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
         session_accepter: Arc<SessionAccepter>,
+        draining: Arc<AtomicBool>,
         option: NodeFinderOption,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
     ) -> Self {
@@ -36,22 +44,31 @@ impl TaskAccepter {
             sessions,
             session_sender,
             session_accepter,
+            draining,
             option,
         };
         Self {
             inner,
             sleeper,
             join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
         }
     }
 
     pub async fn run(&self) {
         let sleeper = self.sleeper.clone();
         let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
         let join_handle = tokio::spawn(async move {
             loop {
-                sleeper.sleep(std::time::Duration::from_secs(1)).await;
-                let res = inner.accept().await;
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(1), &cancellation_token).await {
+                    return;
+                }
+                // 受け入れ待ちの間はまだ何も書き込んでいないので、ここで中断してよい
+                let res = tokio::select! {
+                    _ = cancellation_token.cancelled() => return,
+                    res = inner.accept() => res,
+                };
                 if let Err(e) = res {
                     warn!("{:?}", e);
                 }
@@ -66,8 +83,7 @@ impl Terminable for TaskAccepter {
     type Error = anyhow::Error;
     async fn terminate(&self) -> anyhow::Result<()> {
         if let Some(join_handle) = self.join_handle.lock().await.take() {
-            join_handle.abort();
-            let _ = join_handle.fuse().await;
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
         }
 
         Ok(())
@@ -77,22 +93,22 @@ impl Terminable for TaskAccepter {
 #[allow(dead_code)]
 #[derive(Clone)]
 struct Inner {
-    sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
     session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
     session_accepter: Arc<SessionAccepter>,
+    draining: Arc<AtomicBool>,
     option: NodeFinderOption,
 }
 
 #[allow(dead_code)]
 impl Inner {
     async fn accept(&self) -> anyhow::Result<()> {
-        let session_count = self
-            .sessions
-            .read()
-            .await
-            .iter()
-            .filter(|(_, status)| status.handshake_type == HandshakeType::Accepted)
-            .count();
+        // 受け入れを止めている間は、届いた接続を取り出さない
+        if self.draining.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let session_count = self.sessions.count(|status| status.handshake_type == HandshakeType::Accepted);
         if session_count >= self.option.max_accepted_session_count {
             return Ok(());
         }
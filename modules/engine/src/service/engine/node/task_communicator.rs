@@ -1,13 +1,16 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 use bitflags::bitflags;
 use chrono::Utc;
-use futures::FutureExt;
+use futures::future::join_all;
 use parking_lot::Mutex;
 use tokio::{
     select,
-    sync::{mpsc, Mutex as TokioMutex, RwLock as TokioRwLock},
+    sync::{mpsc, Mutex as TokioMutex},
     task::JoinHandle,
 };
 use tokio_util::sync::CancellationToken;
@@ -17,14 +20,20 @@ use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
 use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
 use crate::{
-    model::{AssetKey, NodeProfile},
+    limits::{self, MessageLimit},
+    model::{AssetKey, AssetPointer, NodeProfile},
     service::{
         connection::{FramedRecvExt as _, FramedSendExt as _},
         session::model::Session,
+        storage::DiskSpaceGate,
+        util::{shutdown_task, sleep_or_cancelled, FnExecutor, TASK_SHUTDOWN_GRACE_PERIOD},
     },
 };
 
-use super::{HandshakeType, NodeProfileRepo, SessionStatus};
+use super::{
+    validate_node_profiles, AssetKeyLocationFoundEvent, ConnectionPacer, HandshakeType, NodeFinderOption, NodeProfileRepo, SessionCloseReason,
+    SessionClosedEvent, SessionRegistry, SessionStatus,
+};
 
 #[derive(Clone)]
 pub struct TaskCommunicator {
@@ -38,19 +47,31 @@ pub struct TaskCommunicator {
 impl TaskCommunicator {
     pub fn new(
         my_node_profile: Arc<Mutex<NodeProfile>>,
-        sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+        sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
         node_profile_repo: Arc<NodeProfileRepo>,
         session_receiver: Arc<TokioMutex<mpsc::Receiver<(HandshakeType, Session)>>>,
+        connection_pacer: Arc<ConnectionPacer>,
+        session_closed_fn: FnExecutor<(), SessionClosedEvent>,
+        get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+        asset_key_location_found_fn: FnExecutor<(), AssetKeyLocationFoundEvent>,
         clock: Arc<dyn Clock<Utc> + Send + Sync>,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
+        disk_space_gate: DiskSpaceGate,
+        option: NodeFinderOption,
     ) -> Self {
         let cancellation_token = CancellationToken::new();
         let inner = Inner {
             my_node_profile,
             sessions,
             node_profile_repo,
+            connection_pacer,
+            session_closed_fn,
+            get_want_asset_keys_fn,
+            asset_key_location_found_fn,
             clock,
             sleeper,
+            disk_space_gate,
+            option,
             cancellation_token: cancellation_token.clone(),
         };
         Self {
@@ -66,12 +87,17 @@ impl TaskCommunicator {
         let session_receiver = self.session_receiver.clone();
         let inner = self.inner.clone();
         let communicate_join_handles = self.communicate_join_handles.clone();
+        let cancellation_token = self.cancellation_token.clone();
         let join_handle = tokio::spawn(async move {
             loop {
                 // 終了済みのタスクを削除
                 communicate_join_handles.lock().await.retain(|join_handle| !join_handle.is_finished());
 
-                if let Some((handshake_type, session)) = session_receiver.lock().await.recv().await {
+                let received = select! {
+                    _ = cancellation_token.cancelled() => return,
+                    v = async { session_receiver.lock().await.recv().await } => v,
+                };
+                if let Some((handshake_type, session)) = received {
                     let inner = inner.clone();
                     let join_handle = tokio::spawn(async move {
                         let res = inner.communicate(handshake_type, session).await;
@@ -92,16 +118,16 @@ impl Terminable for TaskCommunicator {
     type Error = anyhow::Error;
     async fn terminate(&self) -> anyhow::Result<()> {
         if let Some(join_handle) = self.join_handle.lock().await.take() {
-            join_handle.abort();
-            let _ = join_handle.fuse().await;
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
         }
 
-        self.cancellation_token.cancel();
-
-        for join_handle in self.communicate_join_handles.lock().await.drain(..) {
-            join_handle.abort();
-            let _ = join_handle.fuse().await;
-        }
+        let communicate_join_handles: Vec<JoinHandle<()>> = self.communicate_join_handles.lock().await.drain(..).collect();
+        join_all(
+            communicate_join_handles
+                .into_iter()
+                .map(|join_handle| shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD)),
+        )
+        .await;
 
         Ok(())
     }
@@ -110,114 +136,184 @@ impl Terminable for TaskCommunicator {
 #[derive(Clone)]
 struct Inner {
     my_node_profile: Arc<Mutex<NodeProfile>>,
-    sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
     node_profile_repo: Arc<NodeProfileRepo>,
+    connection_pacer: Arc<ConnectionPacer>,
+    session_closed_fn: FnExecutor<(), SessionClosedEvent>,
+    get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+    asset_key_location_found_fn: FnExecutor<(), AssetKeyLocationFoundEvent>,
     clock: Arc<dyn Clock<Utc> + Send + Sync>,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
+    disk_space_gate: DiskSpaceGate,
+    option: NodeFinderOption,
     cancellation_token: CancellationToken,
 }
 
 impl Inner {
     async fn communicate(&self, handshake_type: HandshakeType, session: Session) -> anyhow::Result<()> {
         let my_node_profile = self.my_node_profile.lock().clone();
-        let other_node_profile = Self::handshake(&session, &my_node_profile).await?;
+        let (version, other_node_profile, other_capabilities) = Self::handshake(&session, &my_node_profile).await?;
 
         let status = Arc::new(SessionStatus::new(
             handshake_type,
             session,
             other_node_profile.clone(),
+            SUPPORTED_CAPABILITIES & other_capabilities,
+            self.cancellation_token.child_token(),
             self.clock.clone(),
         ));
 
-        {
-            let mut sessions = self.sessions.write().await;
-            if sessions.contains_key(&status.node_profile.id) {
-                return Err(anyhow::anyhow!("Session already exists"));
-            }
-            sessions.insert(status.node_profile.id.clone(), status.clone());
+        if !self.sessions.try_insert(status.node_profile.id.clone(), status.clone()) {
+            return Err(anyhow::anyhow!("Session already exists"));
         }
 
         info!(node_profile = status.node_profile.to_string(), "Session established");
+        let established_time = self.clock.now();
+        self.connection_pacer.record_session_established();
 
-        let s = self.send(status.clone()).await;
-        let r = self.receive(status.clone()).await;
+        // 再起動した直後に真っ先に繋ぎ直せるよう、こちらから接続できたノードを残す
+        if handshake_type == HandshakeType::Connected {
+            if let Err(e) = self.record_last_connected(&status.node_profile).await {
+                warn!(error_message = e.to_string(), "record last connected node profile failed");
+            }
+        }
+
+        let s = self.send(status.clone(), version).await;
+        let r = self.receive(status.clone(), version).await;
         let _ = tokio::join!(s, r);
 
-        info!(node_profile = status.node_profile.to_string(), "Session closed");
+        let reason = if self.cancellation_token.is_cancelled() {
+            SessionCloseReason::Shutdown
+        } else {
+            status.close_reason.lock().unwrap_or(SessionCloseReason::Disconnected)
+        };
 
-        {
-            let mut sessions = self.sessions.write().await;
-            sessions.remove(&other_node_profile.id);
+        info!(node_profile = status.node_profile.to_string(), ?reason, "Session closed");
+
+        // 相手や経路の都合で切れたものだけを数える
+        // 停止や入れ替えなど、こちらから閉じたものは接続の不安定さとは関係ない
+        if reason == SessionCloseReason::Disconnected {
+            let lifetime = (self.clock.now() - established_time).to_std().unwrap_or_default();
+            self.connection_pacer.record_session_closed(lifetime);
         }
 
+        self.sessions.remove(&other_node_profile.id);
+
+        self.session_closed_fn.execute(&SessionClosedEvent {
+            node_profile: other_node_profile,
+            reason,
+        });
+
         Ok(())
     }
 
-    pub async fn handshake(session: &Session, node_profile: &NodeProfile) -> anyhow::Result<NodeProfile> {
+    async fn record_last_connected(&self, node_profile: &NodeProfile) -> anyhow::Result<()> {
+        self.node_profile_repo.upsert_last_connected_node_profiles(&[node_profile]).await?;
+        self.node_profile_repo.shrink_last_connected_node_profiles(64).await?;
+        Ok(())
+    }
+
+    // 互いに対応しているうち最も新しいバージョンと、相手のノード情報と対応機能を返す
+    async fn handshake(session: &Session, node_profile: &NodeProfile) -> anyhow::Result<(NodeFinderVersion, NodeProfile, NodeCapabilities)> {
         let send_hello_message = HelloMessage {
-            version: NodeFinderVersion::V1,
+            version: NodeFinderVersion::all(),
         };
         session.stream.sender.lock().await.send_message(&send_hello_message).await?;
         let received_hello_message: HelloMessage = session.stream.receiver.lock().await.recv_message().await?;
 
-        let version = send_hello_message.version | received_hello_message.version;
+        let version = send_hello_message.version & received_hello_message.version;
 
-        if version.contains(NodeFinderVersion::V1) {
+        if version.contains(NodeFinderVersion::V2) {
             let send_profile_message = ProfileMessage {
                 node_profile: node_profile.clone(),
+                capabilities: SUPPORTED_CAPABILITIES,
             };
             session.stream.sender.lock().await.send_message(&send_profile_message).await?;
             let received_profile_message: ProfileMessage = session.stream.receiver.lock().await.recv_message().await?;
 
-            Ok(received_profile_message.node_profile)
+            Ok((
+                NodeFinderVersion::V2,
+                received_profile_message.node_profile,
+                received_profile_message.capabilities,
+            ))
+        } else if version.contains(NodeFinderVersion::V1) {
+            // V1 の相手は対応機能を伝えてこないため、何も対応していないものとして扱う
+            let send_profile_message = ProfileMessageV1 {
+                node_profile: node_profile.clone(),
+            };
+            session.stream.sender.lock().await.send_message(&send_profile_message).await?;
+            let received_profile_message: ProfileMessageV1 = session.stream.receiver.lock().await.recv_message().await?;
+
+            Ok((NodeFinderVersion::V1, received_profile_message.node_profile, NodeCapabilities::empty()))
         } else {
             anyhow::bail!("Invalid version")
         }
     }
 
-    async fn send(&self, status: Arc<SessionStatus>) -> JoinHandle<()> {
-        let sender = TaskSender { status: status.clone() };
+    async fn send(&self, status: Arc<SessionStatus>, version: NodeFinderVersion) -> JoinHandle<()> {
+        let sender = TaskSender {
+            status: status.clone(),
+            version,
+        };
         let sleeper = self.sleeper.clone();
-        let cancellation_token = self.cancellation_token.clone();
+        let cancellation_token = status.cancellation_token.clone();
         tokio::spawn(async move {
-            let f = async {
-                loop {
-                    sleeper.sleep(std::time::Duration::from_secs(20)).await;
-                    let res = sender.send().await;
-                    if let Err(e) = res {
-                        warn!(error_message = e.to_string(), "send failed",);
-                        return;
-                    }
+            loop {
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(20), &cancellation_token).await {
+                    return;
                 }
-            };
-            select! {
-                _ = cancellation_token.cancelled() => {}
-                _ = f => {}
-            };
+                // 相手が受信しないまま止まっている場合も、閉じる際に抜けられるようにする
+                let res = select! {
+                    _ = cancellation_token.cancelled() => return,
+                    res = sender.send() => res,
+                };
+                if let Err(e) = res {
+                    warn!(error_message = e.to_string(), "send failed",);
+                    // 受信側も止める
+                    status.close(SessionCloseReason::Disconnected);
+                    return;
+                }
+            }
         })
     }
 
-    async fn receive(&self, status: Arc<SessionStatus>) -> JoinHandle<()> {
+    async fn receive(&self, status: Arc<SessionStatus>, version: NodeFinderVersion) -> JoinHandle<()> {
         let receiver = TaskReceiver {
             status: status.clone(),
+            version,
+            my_node_profile: self.my_node_profile.clone(),
             node_profile_repo: self.node_profile_repo.clone(),
+            asset_key_location_ttl: self.option.asset_key_location_ttl,
+            get_want_asset_keys_fn: self.get_want_asset_keys_fn.clone(),
+            asset_key_location_found_fn: self.asset_key_location_found_fn.clone(),
+            disk_space_gate: self.disk_space_gate.clone(),
         };
+        let clock = self.clock.clone();
         let sleeper = self.sleeper.clone();
-        let cancellation_token = self.cancellation_token.clone();
+        let cancellation_token = status.cancellation_token.clone();
         tokio::spawn(async move {
-            let f = async {
-                loop {
-                    sleeper.sleep(std::time::Duration::from_secs(20)).await;
-                    let res = receiver.receive().await;
-                    if let Err(e) = res {
-                        warn!(error_message = e.to_string(), "receive failed",);
-                        return;
+            loop {
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(20), &cancellation_token).await {
+                    return;
+                }
+                // 受信待ちの間のみ中断し、受信したノード情報の保存は最後まで行う
+                let res = select! {
+                    _ = cancellation_token.cancelled() => return,
+                    res = receiver.recv() => res,
+                };
+                let res = match res {
+                    Ok(data_message) => {
+                        status.touch(clock.now());
+                        receiver.apply(data_message).await
                     }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    warn!(error_message = e.to_string(), "receive failed",);
+                    // 送信側も止める
+                    status.close(SessionCloseReason::Disconnected);
+                    return;
                 }
-            };
-            select! {
-                _ = cancellation_token.cancelled() => {}
-                _ = f => {}
             }
         })
     }
@@ -225,6 +321,7 @@ impl Inner {
 
 struct TaskSender {
     status: Arc<SessionStatus>,
+    version: NodeFinderVersion,
 }
 
 impl TaskSender {
@@ -236,10 +333,17 @@ impl TaskSender {
                 want_asset_keys: sending_data_message.want_asset_keys.drain(..).collect(),
                 give_asset_key_locations: sending_data_message.give_asset_key_locations.drain().collect(),
                 push_asset_key_locations: sending_data_message.push_asset_key_locations.drain().collect(),
+                push_asset_pointers: sending_data_message.push_asset_pointers.drain(..).collect(),
             }
         };
 
-        self.status.session.stream.sender.lock().await.send_message(&data_message).await?;
+        let mut sender = self.status.session.stream.sender.lock().await;
+        if self.version == NodeFinderVersion::V2 {
+            sender.send_message(&data_message).await?;
+        } else {
+            // V1 の相手には push_asset_pointers を送れないため捨てる
+            sender.send_message(&DataMessageV1(data_message)).await?;
+        }
 
         Ok(())
     }
@@ -247,19 +351,87 @@ impl TaskSender {
 
 struct TaskReceiver {
     status: Arc<SessionStatus>,
+    version: NodeFinderVersion,
+    my_node_profile: Arc<Mutex<NodeProfile>>,
     node_profile_repo: Arc<NodeProfileRepo>,
+    asset_key_location_ttl: std::time::Duration,
+    get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+    asset_key_location_found_fn: FnExecutor<(), AssetKeyLocationFoundEvent>,
+    disk_space_gate: DiskSpaceGate,
 }
 
 impl TaskReceiver {
-    async fn receive(&self) -> anyhow::Result<()> {
-        let data_message = self.status.session.stream.receiver.lock().await.recv_message::<DataMessage>().await?;
+    async fn recv(&self) -> anyhow::Result<DataMessage> {
+        let mut receiver = self.status.session.stream.receiver.lock().await;
+        if self.version == NodeFinderVersion::V2 {
+            receiver.recv_message::<DataMessage>().await
+        } else {
+            Ok(receiver.recv_message::<DataMessageV1>().await?.0)
+        }
+    }
+
+    async fn apply(&self, data_message: DataMessage) -> anyhow::Result<()> {
+        // 空き容量が不足している間は、教わったノードや場所を保存しない
+        // 待つとセッションが止まるため、保存だけを読み飛ばし、want しているキーの通知は続ける
+        let persist = !self.disk_space_gate.is_paused();
+
+        let my_node_profile = self.my_node_profile.lock().clone();
+        let push_node_profiles = validate_node_profiles(&data_message.push_node_profiles, &my_node_profile);
+        let push_node_profiles: Vec<&NodeProfile> = push_node_profiles.iter().take(32).collect();
+        if persist {
+            self.node_profile_repo.insert_bulk_node_profile(&push_node_profiles, 0).await?;
+            self.node_profile_repo.shrink(1024).await?;
+        }
+
+        // 再起動した直後でも探索に使えるよう、教わった場所を期限付きで残す
+        if persist {
+            let locations: Vec<(&AssetKey, &NodeProfile)> = data_message
+                .give_asset_key_locations
+                .iter()
+                .chain(data_message.push_asset_key_locations.iter())
+                .flat_map(|(k, vs)| vs.iter().map(move |v| (k, v)))
+                .collect();
+            if !locations.is_empty() {
+                self.node_profile_repo
+                    .upsert_asset_key_locations(&locations, chrono::Duration::from_std(self.asset_key_location_ttl)?)
+                    .await?;
+                self.node_profile_repo.shrink_asset_key_locations(1024 * 64).await?;
+            }
+        }
 
-        let push_node_profiles: Vec<&NodeProfile> = data_message.push_node_profiles.iter().take(32).collect();
-        self.node_profile_repo.insert_bulk_node_profile(&push_node_profiles, 0).await?;
-        self.node_profile_repo.shrink(1024).await?;
+        let want_asset_keys: HashSet<AssetKey> = self.get_want_asset_keys_fn.execute(&()).into_iter().flatten().collect();
+        let mut found_events: Vec<AssetKeyLocationFoundEvent> = Vec::new();
 
         {
             let mut received_data_message = self.status.received_data_message.lock();
+
+            // 次の探索を待たずに取りに行けるよう、want しているキーの新しい場所は知らせる
+            for (asset_key, node_profiles) in data_message
+                .give_asset_key_locations
+                .iter()
+                .chain(data_message.push_asset_key_locations.iter())
+            {
+                if !want_asset_keys.contains(asset_key) {
+                    continue;
+                }
+                let key = Arc::new(asset_key.clone());
+                let known: HashSet<&NodeProfile> = [
+                    received_data_message.give_asset_key_locations.get(&key),
+                    received_data_message.push_asset_key_locations.get(&key),
+                ]
+                .into_iter()
+                .flatten()
+                .flat_map(|vs| vs.iter().map(|v| v.as_ref()))
+                .collect();
+                let node_profiles: Vec<NodeProfile> = node_profiles.iter().filter(|v| !known.contains(v)).cloned().collect();
+                if !node_profiles.is_empty() {
+                    found_events.push(AssetKeyLocationFoundEvent {
+                        asset_key: asset_key.clone(),
+                        node_profiles,
+                    });
+                }
+            }
+
             received_data_message
                 .want_asset_keys
                 .extend(data_message.want_asset_keys.into_iter().map(Arc::new));
@@ -276,9 +448,28 @@ impl TaskReceiver {
                     .map(|(k, v)| (Arc::new(k), v.into_iter().map(Arc::new).collect())),
             );
 
+            // 署名の正しいもののうち、手元より新しいものだけを残す
+            for pointer in data_message.push_asset_pointers {
+                if pointer.verify().is_err() {
+                    continue;
+                }
+                let key = Arc::new(pointer.key());
+                if let Some(current) = received_data_message.push_asset_pointers.get(&key) {
+                    if current.sequence >= pointer.sequence {
+                        continue;
+                    }
+                }
+                received_data_message.push_asset_pointers.insert(key, Arc::new(pointer));
+            }
+
             received_data_message.want_asset_keys.shrink(1024 * 256);
             received_data_message.give_asset_key_locations.shrink(1024 * 256);
             received_data_message.push_asset_key_locations.shrink(1024 * 256);
+            received_data_message.push_asset_pointers.shrink(1024 * 256);
+        }
+
+        for event in found_events {
+            self.asset_key_location_found_fn.execute(&event);
         }
 
         Ok(())
@@ -286,17 +477,40 @@ impl TaskReceiver {
 }
 
 bitflags! {
-    #[derive(Debug, PartialEq, Eq )]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq )]
       struct NodeFinderVersion: u32 {
         const V1 = 1;
+        // ProfileMessage に対応機能を、DataMessage に push_asset_pointers を、NodeProfile に flags を載せる
+        const V2 = 1 << 1;
     }
 }
 
+// 対応しているプロトコルのバージョン (ビット毎)
+pub const NODE_FINDER_PROTOCOL_VERSION: u32 = NodeFinderVersion::all().bits();
+
+bitflags! {
+    // セッション毎に挙動を変えられるよう、ハンドシェイクで互いの対応機能を伝える
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct NodeCapabilities: u32 {
+        const COMPRESSION = 1;
+        const QUIC = 1 << 1;
+        const RELAY = 1 << 2;
+        const FILE_EXCHANGE_V2 = 1 << 3;
+    }
+}
+
+// 自身が対応している機能。いずれもまだ実装されていない
+pub const SUPPORTED_CAPABILITIES: NodeCapabilities = NodeCapabilities::empty();
+
 #[derive(Debug, PartialEq, Eq)]
 struct HelloMessage {
     pub version: NodeFinderVersion,
 }
 
+impl MessageLimit for HelloMessage {
+    const MAX_LENGTH: usize = 1024;
+}
+
 impl RocketMessage for HelloMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
         writer.put_u32(value.version.bits());
@@ -308,7 +522,8 @@ impl RocketMessage for HelloMessage {
     where
         Self: Sized,
     {
-        let version = NodeFinderVersion::from_bits(reader.get_u32()?).ok_or_else(|| anyhow::anyhow!("invalid version"))?;
+        // 新しいバージョンを知らなくても、共通のバージョンで接続できるよう未知のビットは無視する
+        let version = NodeFinderVersion::from_bits_truncate(reader.get_u32()?);
 
         Ok(Self { version })
     }
@@ -317,11 +532,17 @@ impl RocketMessage for HelloMessage {
 #[derive(Debug, PartialEq, Eq)]
 struct ProfileMessage {
     pub node_profile: NodeProfile,
+    pub capabilities: NodeCapabilities,
+}
+
+impl MessageLimit for ProfileMessage {
+    const MAX_LENGTH: usize = 256 * 1024;
 }
 
 impl RocketMessage for ProfileMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
         NodeProfile::pack(writer, &value.node_profile, depth + 1)?;
+        writer.put_u32(value.capabilities.bits());
 
         Ok(())
     }
@@ -330,18 +551,52 @@ impl RocketMessage for ProfileMessage {
     where
         Self: Sized,
     {
+        limits::check_depth(depth)?;
+
         let node_profile = NodeProfile::unpack(reader, depth + 1)?;
+        // 新しい版の機能を知らなくても接続できるよう、未知のビットは無視する
+        let capabilities = NodeCapabilities::from_bits_truncate(reader.get_u32()?);
+
+        Ok(Self { node_profile, capabilities })
+    }
+}
+
+// 対応機能を持たない、V1 での ProfileMessage。NodeProfile も V1 の形式で送る
+#[derive(Debug, PartialEq, Eq)]
+struct ProfileMessageV1 {
+    pub node_profile: NodeProfile,
+}
+
+impl MessageLimit for ProfileMessageV1 {
+    const MAX_LENGTH: usize = 256 * 1024;
+}
+
+impl RocketMessage for ProfileMessageV1 {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        NodeProfile::pack_v1(writer, &value.node_profile, depth + 1)?;
+
+        Ok(())
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        limits::check_depth(depth)?;
+
+        let node_profile = NodeProfile::unpack_v1(reader, depth + 1)?;
 
         Ok(Self { node_profile })
     }
 }
 
 #[derive(Debug, PartialEq, Eq)]
-struct DataMessage {
+pub struct DataMessage {
     pub push_node_profiles: Vec<NodeProfile>,
     pub want_asset_keys: Vec<AssetKey>,
     pub give_asset_key_locations: HashMap<AssetKey, Vec<NodeProfile>>,
     pub push_asset_key_locations: HashMap<AssetKey, Vec<NodeProfile>>,
+    pub push_asset_pointers: Vec<AssetPointer>,
 }
 
 impl DataMessage {
@@ -351,6 +606,7 @@ impl DataMessage {
             want_asset_keys: vec![],
             give_asset_key_locations: HashMap::new(),
             push_asset_key_locations: HashMap::new(),
+            push_asset_pointers: vec![],
         }
     }
 }
@@ -361,11 +617,16 @@ impl Default for DataMessage {
     }
 }
 
-impl RocketMessage for DataMessage {
-    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+impl MessageLimit for DataMessage {
+    const MAX_LENGTH: usize = limits::MAX_FRAME_LENGTH;
+}
+
+impl DataMessage {
+    // V1 では push_asset_pointers を持たず、NodeProfile も flags を持たない形式で送る
+    fn pack_version(writer: &mut RocketMessageWriter, value: &Self, depth: u32, version: NodeFinderVersion) -> anyhow::Result<()> {
         writer.put_u32(value.push_node_profiles.len().try_into()?);
         for v in &value.push_node_profiles {
-            NodeProfile::pack(writer, v, depth + 1)?;
+            Self::pack_node_profile(writer, v, depth + 1, version)?;
         }
 
         writer.put_u32(value.want_asset_keys.len().try_into()?);
@@ -378,7 +639,7 @@ impl RocketMessage for DataMessage {
             AssetKey::pack(writer, key, depth + 1)?;
             writer.put_u32(vs.len().try_into()?);
             for v in vs {
-                NodeProfile::pack(writer, v, depth + 1)?;
+                Self::pack_node_profile(writer, v, depth + 1, version)?;
             }
         }
 
@@ -387,69 +648,72 @@ impl RocketMessage for DataMessage {
             AssetKey::pack(writer, key, depth + 1)?;
             writer.put_u32(vs.len().try_into()?);
             for v in vs {
-                NodeProfile::pack(writer, v, depth + 1)?;
+                Self::pack_node_profile(writer, v, depth + 1, version)?;
+            }
+        }
+
+        if version == NodeFinderVersion::V2 {
+            writer.put_u32(value.push_asset_pointers.len().try_into()?);
+            for v in &value.push_asset_pointers {
+                AssetPointer::pack(writer, v, depth + 1)?;
             }
         }
 
         Ok(())
     }
 
-    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
-    where
-        Self: Sized,
-    {
-        let len = reader.get_u32()?.try_into()?;
-        if len > 128 {
-            anyhow::bail!("len too large");
-        }
+    fn unpack_version(reader: &mut RocketMessageReader, depth: u32, version: NodeFinderVersion) -> anyhow::Result<Self> {
+        limits::check_depth(depth)?;
+
+        let len = limits::check_len(reader.get_u32()?, limits::MAX_NODE_PROFILE_COUNT)?;
         let mut push_node_profiles = Vec::with_capacity(len);
         for _ in 0..len {
-            push_node_profiles.push(NodeProfile::unpack(reader, depth + 1)?);
+            push_node_profiles.push(Self::unpack_node_profile(reader, depth + 1, version)?);
         }
 
-        let len = reader.get_u32()?.try_into()?;
-        if len > 128 {
-            anyhow::bail!("len too large");
-        }
+        let len = limits::check_len(reader.get_u32()?, limits::MAX_ASSET_KEY_COUNT)?;
         let mut want_asset_keys = Vec::with_capacity(len);
         for _ in 0..len {
             want_asset_keys.push(AssetKey::unpack(reader, depth + 1)?);
         }
 
-        let len = reader.get_u32()?.try_into()?;
-        if len > 128 {
-            anyhow::bail!("len too large");
-        }
+        let len = limits::check_len(reader.get_u32()?, limits::MAX_ASSET_KEY_COUNT)?;
         let mut give_asset_key_locations: HashMap<AssetKey, Vec<NodeProfile>> = HashMap::new();
         for _ in 0..len {
             let key = AssetKey::unpack(reader, depth + 1)?;
-            let len = reader.get_u32()?.try_into()?;
-            if len > 128 {
-                anyhow::bail!("len too large");
-            }
+            let len = limits::check_len(reader.get_u32()?, limits::MAX_NODE_PROFILE_COUNT)?;
             let mut vs = Vec::with_capacity(len);
             for _ in 0..len {
-                vs.push(NodeProfile::unpack(reader, depth + 1)?);
+                vs.push(Self::unpack_node_profile(reader, depth + 1, version)?);
+            }
+            // 同一キーの重複は正規形ではないため拒否する
+            if give_asset_key_locations.insert(key, vs).is_some() {
+                anyhow::bail!("duplicate asset key");
             }
-            give_asset_key_locations.entry(key).or_default().extend(vs);
         }
 
-        let len = reader.get_u32()?.try_into()?;
-        if len > 128 {
-            anyhow::bail!("len too large");
-        }
+        let len = limits::check_len(reader.get_u32()?, limits::MAX_ASSET_KEY_COUNT)?;
         let mut push_asset_key_locations: HashMap<AssetKey, Vec<NodeProfile>> = HashMap::new();
         for _ in 0..len {
             let key = AssetKey::unpack(reader, depth + 1)?;
-            let len = reader.get_u32()?.try_into()?;
-            if len > 128 {
-                anyhow::bail!("len too large");
-            }
+            let len = limits::check_len(reader.get_u32()?, limits::MAX_NODE_PROFILE_COUNT)?;
             let mut vs = Vec::with_capacity(len);
             for _ in 0..len {
-                vs.push(NodeProfile::unpack(reader, depth + 1)?);
+                vs.push(Self::unpack_node_profile(reader, depth + 1, version)?);
+            }
+            // 同一キーの重複は正規形ではないため拒否する
+            if push_asset_key_locations.insert(key, vs).is_some() {
+                anyhow::bail!("duplicate asset key");
+            }
+        }
+
+        let mut push_asset_pointers = Vec::new();
+        if version == NodeFinderVersion::V2 {
+            let len = limits::check_len(reader.get_u32()?, limits::MAX_ASSET_POINTER_COUNT)?;
+            push_asset_pointers.reserve(len);
+            for _ in 0..len {
+                push_asset_pointers.push(AssetPointer::unpack(reader, depth + 1)?);
             }
-            push_asset_key_locations.entry(key).or_default().extend(vs);
         }
 
         Ok(Self {
@@ -457,6 +721,382 @@ impl RocketMessage for DataMessage {
             want_asset_keys,
             give_asset_key_locations,
             push_asset_key_locations,
+            push_asset_pointers,
         })
     }
+
+    fn pack_node_profile(writer: &mut RocketMessageWriter, value: &NodeProfile, depth: u32, version: NodeFinderVersion) -> anyhow::Result<()> {
+        if version == NodeFinderVersion::V2 {
+            NodeProfile::pack(writer, value, depth)
+        } else {
+            NodeProfile::pack_v1(writer, value, depth)
+        }
+    }
+
+    fn unpack_node_profile(reader: &mut RocketMessageReader, depth: u32, version: NodeFinderVersion) -> anyhow::Result<NodeProfile> {
+        if version == NodeFinderVersion::V2 {
+            NodeProfile::unpack(reader, depth)
+        } else {
+            NodeProfile::unpack_v1(reader, depth)
+        }
+    }
+}
+
+impl RocketMessage for DataMessage {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        Self::pack_version(writer, value, depth, NodeFinderVersion::V2)
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::unpack_version(reader, depth, NodeFinderVersion::V2)
+    }
+}
+
+// V1 での DataMessage
+#[derive(Debug, PartialEq, Eq)]
+struct DataMessageV1(DataMessage);
+
+impl MessageLimit for DataMessageV1 {
+    const MAX_LENGTH: usize = limits::MAX_FRAME_LENGTH;
+}
+
+impl RocketMessage for DataMessageV1 {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        DataMessage::pack_version(writer, &value.0, depth, NodeFinderVersion::V1)
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self(DataMessage::unpack_version(reader, depth, NodeFinderVersion::V1)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use testresult::TestResult;
+    use tokio_util::bytes::Bytes;
+
+    use omnius_core_omnikit::model::{OmniAddr, OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
+    use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
+
+    use crate::{
+        limits,
+        model::{AssetKey, AssetPointer, NodeProfile},
+    };
+
+    use super::{DataMessage, DataMessageV1, HelloMessage, NodeCapabilities, NodeFinderVersion, ProfileMessage, ProfileMessageV1};
+
+    #[test]
+    pub fn data_message_roundtrip_test() -> TestResult {
+        let node_profile = NodeProfile {
+            id: vec![1, 2, 3],
+            addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+            reachable: false,
+        };
+        let asset_key = AssetKey {
+            typ: "test".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+        let signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "test")?;
+        let message = DataMessage {
+            push_node_profiles: vec![node_profile.clone()],
+            want_asset_keys: vec![asset_key.clone()],
+            give_asset_key_locations: HashMap::from([(asset_key.clone(), vec![node_profile.clone()])]),
+            push_asset_key_locations: HashMap::new(),
+            push_asset_pointers: vec![AssetPointer::sign("test".to_string(), asset_key.clone(), 1, &signer)?],
+        };
+
+        let mut b = Bytes::from(message.export()?.to_vec());
+        assert_eq!(DataMessage::import(&mut b)?, message);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn data_message_v1_test() -> TestResult {
+        let asset_key = AssetKey {
+            typ: "test".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+        let signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "test")?;
+        let message = DataMessage {
+            want_asset_keys: vec![asset_key.clone()],
+            push_asset_pointers: vec![AssetPointer::sign("test".to_string(), asset_key.clone(), 1, &signer)?],
+            ..Default::default()
+        };
+
+        // V1 の相手には push_asset_pointers を送らない
+        let mut b = Bytes::from(DataMessageV1(message).export()?.to_vec());
+        let message = DataMessageV1::import(&mut b)?.0;
+        assert_eq!(message.want_asset_keys, vec![asset_key]);
+        assert!(message.push_asset_pointers.is_empty());
+
+        Ok(())
+    }
+
+    // flags を追加する前の NodeProfile が並んでいても、後ろのフィールドまで正しく読める
+    #[test]
+    pub fn data_message_v1_legacy_node_profile_test() -> TestResult {
+        let node_profiles = vec![
+            NodeProfile {
+                id: vec![1, 2, 3],
+                addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+                reachable: false,
+            },
+            NodeProfile {
+                id: vec![4, 5, 6],
+                addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60001)")],
+                reachable: false,
+            },
+        ];
+        let asset_key = AssetKey {
+            typ: "test".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+        let legacy = LegacyDataMessage {
+            push_node_profiles: node_profiles.clone(),
+            want_asset_keys: vec![asset_key.clone()],
+            give_asset_key_locations: vec![(asset_key.clone(), node_profiles.clone())],
+        };
+
+        let mut b = Bytes::from(legacy.export()?.to_vec());
+        let message = DataMessageV1::import(&mut b)?.0;
+        assert_eq!(message.push_node_profiles, node_profiles);
+        assert_eq!(message.want_asset_keys, vec![asset_key.clone()]);
+        assert_eq!(message.give_asset_key_locations, HashMap::from([(asset_key, node_profiles)]));
+        assert!(message.push_asset_key_locations.is_empty());
+
+        Ok(())
+    }
+
+    // flags を追加する前の NodeProfile を並べた、V1 の DataMessage
+    struct LegacyDataMessage {
+        push_node_profiles: Vec<NodeProfile>,
+        want_asset_keys: Vec<AssetKey>,
+        give_asset_key_locations: Vec<(AssetKey, Vec<NodeProfile>)>,
+    }
+
+    impl LegacyDataMessage {
+        fn pack_node_profile(writer: &mut RocketMessageWriter, value: &NodeProfile) -> anyhow::Result<()> {
+            writer.put_bytes(&value.id);
+            writer.put_u32(value.addrs.len().try_into()?);
+            for v in &value.addrs {
+                writer.put_str(v.as_str());
+            }
+            Ok(())
+        }
+    }
+
+    impl RocketMessage for LegacyDataMessage {
+        fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+            writer.put_u32(value.push_node_profiles.len().try_into()?);
+            for v in &value.push_node_profiles {
+                Self::pack_node_profile(writer, v)?;
+            }
+
+            writer.put_u32(value.want_asset_keys.len().try_into()?);
+            for v in &value.want_asset_keys {
+                AssetKey::pack(writer, v, depth + 1)?;
+            }
+
+            writer.put_u32(value.give_asset_key_locations.len().try_into()?);
+            for (key, vs) in &value.give_asset_key_locations {
+                AssetKey::pack(writer, key, depth + 1)?;
+                writer.put_u32(vs.len().try_into()?);
+                for v in vs {
+                    Self::pack_node_profile(writer, v)?;
+                }
+            }
+
+            writer.put_u32(0);
+
+            Ok(())
+        }
+
+        fn unpack(_reader: &mut RocketMessageReader, _depth: u32) -> anyhow::Result<Self>
+        where
+            Self: Sized,
+        {
+            anyhow::bail!("not supported")
+        }
+    }
+
+    #[test]
+    pub fn profile_message_capabilities_test() -> TestResult {
+        let message = ProfileMessage {
+            node_profile: NodeProfile {
+                id: vec![1, 2, 3],
+                addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+                reachable: true,
+            },
+            capabilities: NodeCapabilities::COMPRESSION | NodeCapabilities::RELAY,
+        };
+
+        let mut b = Bytes::from(message.export()?.to_vec());
+        assert_eq!(ProfileMessage::import(&mut b)?, message);
+
+        // 未知のビットは無視して読み込む
+        let message = ProfileMessage {
+            capabilities: NodeCapabilities::from_bits_retain(0x8000_0000 | NodeCapabilities::QUIC.bits()),
+            ..message
+        };
+        let mut b = Bytes::from(message.export()?.to_vec());
+        assert_eq!(ProfileMessage::import(&mut b)?.capabilities, NodeCapabilities::QUIC);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn hello_message_test() -> TestResult {
+        // 知らないバージョンが含まれていても、共通のバージョンで接続できる
+        let message = HelloMessage {
+            version: NodeFinderVersion::from_bits_retain(0x8000_0000 | NodeFinderVersion::V1.bits()),
+        };
+        let mut b = Bytes::from(message.export()?.to_vec());
+        assert_eq!(HelloMessage::import(&mut b)?.version, NodeFinderVersion::V1);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn profile_message_v1_test() -> TestResult {
+        let message = ProfileMessageV1 {
+            node_profile: NodeProfile {
+                id: vec![1, 2, 3],
+                addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+                reachable: false,
+            },
+        };
+
+        // V1 の相手には対応機能を送らない
+        let b = message.export()?;
+        let v2 = ProfileMessage {
+            node_profile: message.node_profile.clone(),
+            capabilities: NodeCapabilities::empty(),
+        };
+        assert!(b.len() < v2.export()?.len());
+
+        let mut b = Bytes::from(b.to_vec());
+        assert_eq!(ProfileMessageV1::import(&mut b)?, message);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn data_message_reject_test() -> TestResult {
+        let message = DataMessage {
+            want_asset_keys: (0..(limits::MAX_ASSET_KEY_COUNT + 1))
+                .map(|n| AssetKey {
+                    typ: "test".to_string(),
+                    hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, n.to_string().as_bytes()),
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut b = Bytes::from(message.export()?.to_vec());
+        assert!(DataMessage::import(&mut b).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn data_message_malformed_test() -> TestResult {
+        let node_profile = NodeProfile {
+            id: vec![1, 2, 3],
+            addrs: vec![OmniAddr::new("tcp(ip4(127.0.0.1),60000)")],
+            reachable: false,
+        };
+        let asset_key = AssetKey {
+            typ: "test".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+
+        // 途中で切れているもの
+        let message = DataMessage {
+            push_node_profiles: vec![node_profile.clone()],
+            want_asset_keys: vec![asset_key.clone()],
+            ..Default::default()
+        };
+        let b = message.export()?;
+        for len in 0..b.len() {
+            assert!(DataMessage::import(&mut Bytes::from(b[..len].to_vec())).is_err(), "len: {}", len);
+        }
+
+        // 要素数が上限を越えているもの
+        let message = DataMessage {
+            push_node_profiles: vec![node_profile.clone(); limits::MAX_NODE_PROFILE_COUNT + 1],
+            ..Default::default()
+        };
+        assert!(DataMessage::import(&mut Bytes::from(message.export()?.to_vec())).is_err());
+
+        let message = DataMessage {
+            give_asset_key_locations: HashMap::from([(asset_key.clone(), vec![node_profile.clone(); limits::MAX_NODE_PROFILE_COUNT + 1])]),
+            ..Default::default()
+        };
+        assert!(DataMessage::import(&mut Bytes::from(message.export()?.to_vec())).is_err());
+
+        // 同一キーが重複しているもの
+        let message = DuplicateKeyMessage { asset_key, node_profile };
+        assert!(DataMessage::import(&mut Bytes::from(message.export()?.to_vec())).is_err());
+
+        Ok(())
+    }
+
+    // give_asset_key_locations に同じキーを 2 回書き込む、正規形でない DataMessage
+    struct DuplicateKeyMessage {
+        asset_key: AssetKey,
+        node_profile: NodeProfile,
+    }
+
+    impl RocketMessage for DuplicateKeyMessage {
+        fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+            writer.put_u32(0);
+            writer.put_u32(0);
+
+            writer.put_u32(2);
+            for _ in 0..2 {
+                AssetKey::pack(writer, &value.asset_key, depth + 1)?;
+                writer.put_u32(1);
+                NodeProfile::pack(writer, &value.node_profile, depth + 1)?;
+            }
+
+            writer.put_u32(0);
+            writer.put_u32(0);
+
+            Ok(())
+        }
+
+        fn unpack(_reader: &mut RocketMessageReader, _depth: u32) -> anyhow::Result<Self>
+        where
+            Self: Sized,
+        {
+            anyhow::bail!("not supported")
+        }
+    }
+
+    // 任意のバイト列を与えてもパニックせずにエラーを返すことを確認する
+    #[test]
+    pub fn unpack_fuzz_test() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        for _ in 0..1024 {
+            let mut buf = vec![0; (rng.next_u32() % 512) as usize];
+            rng.fill_bytes(&mut buf);
+
+            let _ = HelloMessage::import(&mut Bytes::from(buf.clone()));
+            let _ = ProfileMessage::import(&mut Bytes::from(buf.clone()));
+            let _ = ProfileMessageV1::import(&mut Bytes::from(buf.clone()));
+            let _ = DataMessageV1::import(&mut Bytes::from(buf.clone()));
+            let _ = DataMessage::import(&mut Bytes::from(buf));
+        }
+    }
 }
@@ -4,29 +4,29 @@ use std::{
 };
 
 use async_trait::async_trait;
-use futures::FutureExt;
+use futures::{StreamExt as _, TryStreamExt as _};
 use parking_lot::Mutex;
 use rand::seq::SliceRandom as _;
-use tokio::{
-    sync::{Mutex as TokioMutex, RwLock as TokioRwLock},
-    task::JoinHandle,
-};
+use tokio::{sync::Mutex as TokioMutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
 use tracing::warn;
 
 use omnius_core_base::{sleeper::Sleeper, terminable::Terminable};
 
 use crate::{
-    model::{AssetKey, NodeProfile},
-    service::util::{FnExecutor, Kadex},
+    limits,
+    model::{AssetKey, AssetPointer, NodeProfile},
+    service::util::{shutdown_task, sleep_or_cancelled, FnExecutor, Kadex, TASK_SHUTDOWN_GRACE_PERIOD},
 };
 
-use super::{NodeProfileFetcher, NodeProfileRepo, SendingDataMessage, SessionStatus};
+use super::{NodeFinderOption, NodeProfileFetcher, NodeProfileRepo, SendingDataMessage, SessionRegistry, SessionStatus};
 
 #[derive(Clone)]
 pub struct TaskComputer {
     inner: Inner,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
     join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
 }
 
 impl TaskComputer {
@@ -34,10 +34,12 @@ impl TaskComputer {
         my_node_profile: Arc<Mutex<NodeProfile>>,
         node_profile_repo: Arc<NodeProfileRepo>,
         node_profile_fetcher: Arc<dyn NodeProfileFetcher + Send + Sync>,
-        sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+        sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
         get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
         get_push_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+        get_push_asset_pointers_fn: FnExecutor<Vec<AssetPointer>, ()>,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
+        option: NodeFinderOption,
     ) -> Self {
         let inner = Inner {
             my_node_profile,
@@ -46,23 +48,29 @@ impl TaskComputer {
             sessions,
             get_want_asset_keys_fn,
             get_push_asset_keys_fn,
+            get_push_asset_pointers_fn,
+            option,
         };
         Self {
             inner,
             sleeper,
             join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
         }
     }
 
     pub async fn run(&self) {
         let sleeper = self.sleeper.clone();
         let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
         let join_handle = tokio::spawn(async move {
             if let Err(e) = inner.set_initial_node_profile().await {
                 warn!(error_message = e.to_string(), "set initial node profile failed");
             }
             loop {
-                sleeper.sleep(std::time::Duration::from_secs(60)).await;
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(60), &cancellation_token).await {
+                    return;
+                }
                 let res = inner.compute().await;
                 if let Err(e) = res {
                     warn!(error_message = e.to_string(), "compute failed");
@@ -78,8 +86,7 @@ impl Terminable for TaskComputer {
     type Error = anyhow::Error;
     async fn terminate(&self) -> anyhow::Result<()> {
         if let Some(join_handle) = self.join_handle.lock().await.take() {
-            join_handle.abort();
-            let _ = join_handle.fuse().await;
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
         }
 
         Ok(())
@@ -91,9 +98,11 @@ struct Inner {
     my_node_profile: Arc<Mutex<NodeProfile>>,
     node_profile_repo: Arc<NodeProfileRepo>,
     node_profile_fetcher: Arc<dyn NodeProfileFetcher + Send + Sync>,
-    sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
     get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
     get_push_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+    get_push_asset_pointers_fn: FnExecutor<Vec<AssetPointer>, ()>,
+    option: NodeFinderOption,
 }
 
 impl Inner {
@@ -114,38 +123,48 @@ impl Inner {
     #[allow(clippy::type_complexity)]
     async fn compute_sending_data_message(&self) -> anyhow::Result<()> {
         let my_node_profile = Arc::new(self.my_node_profile.lock().clone());
-        let cloud_node_profile: Vec<Arc<NodeProfile>> = self.node_profile_repo.get_node_profiles().await?.into_iter().map(Arc::new).collect();
+        // 受信側の上限を超えないよう、自身の分を除いた件数だけ読み込む
+        let cloud_node_profile: Vec<Arc<NodeProfile>> = self
+            .node_profile_repo
+            .stream_node_profiles()
+            .take(limits::MAX_NODE_PROFILE_COUNT - 1)
+            .map_ok(Arc::new)
+            .try_collect()
+            .await?;
 
         let my_get_want_asset_keys: HashSet<Arc<AssetKey>> = self.get_want_asset_keys_fn.execute(&()).into_iter().flatten().map(Arc::new).collect();
         let my_get_push_asset_keys: HashSet<Arc<AssetKey>> = self.get_push_asset_keys_fn.execute(&()).into_iter().flatten().map(Arc::new).collect();
+        let my_get_push_asset_pointers: Vec<Arc<AssetPointer>> =
+            self.get_push_asset_pointers_fn.execute(&()).into_iter().flatten().map(Arc::new).collect();
 
         let mut received_data_map: HashMap<Vec<u8>, ReceivedTempDataMessage> = HashMap::new();
-        {
-            let sessions = self.sessions.read().await;
-            for (id, status) in sessions.iter() {
-                let data = status.received_data_message.lock();
-
-                let mut want_asset_keys: Vec<Arc<AssetKey>> = data.want_asset_keys.iter().cloned().collect();
-                let mut give_asset_key_locations: Vec<(Arc<AssetKey>, Vec<Arc<NodeProfile>>)> =
-                    data.give_asset_key_locations.iter().map(|(k, v)| (k.clone(), v.to_vec())).collect();
-                let mut push_asset_key_locations: Vec<(Arc<AssetKey>, Vec<Arc<NodeProfile>>)> =
-                    data.push_asset_key_locations.iter().map(|(k, v)| (k.clone(), v.to_vec())).collect();
-
-                let mut rng = rand::thread_rng();
-                want_asset_keys.shuffle(&mut rng);
-                give_asset_key_locations.shuffle(&mut rng);
-                push_asset_key_locations.shuffle(&mut rng);
-
-                let tmp = ReceivedTempDataMessage {
-                    want_asset_keys,
-                    give_asset_key_locations,
-                    push_asset_key_locations,
-                };
-                received_data_map.insert(id.clone(), tmp);
-            }
+        for (id, status) in self.sessions.snapshot() {
+            let data = status.received_data_message.lock();
+
+            let mut want_asset_keys: Vec<Arc<AssetKey>> = data.want_asset_keys.iter().cloned().collect();
+            let mut give_asset_key_locations: Vec<(Arc<AssetKey>, Vec<Arc<NodeProfile>>)> =
+                data.give_asset_key_locations.iter().map(|(k, v)| (k.clone(), v.to_vec())).collect();
+            let mut push_asset_key_locations: Vec<(Arc<AssetKey>, Vec<Arc<NodeProfile>>)> =
+                data.push_asset_key_locations.iter().map(|(k, v)| (k.clone(), v.to_vec())).collect();
+            let push_asset_pointers: Vec<Arc<AssetPointer>> = data.push_asset_pointers.iter().map(|(_, v)| v.clone()).collect();
+
+            let mut rng = rand::thread_rng();
+            want_asset_keys.shuffle(&mut rng);
+            give_asset_key_locations.shuffle(&mut rng);
+            push_asset_key_locations.shuffle(&mut rng);
+
+            let tmp = ReceivedTempDataMessage {
+                want_asset_keys,
+                give_asset_key_locations,
+                push_asset_key_locations,
+                push_asset_pointers,
+            };
+            received_data_map.insert(id, tmp);
         }
 
         let ids: Vec<&[u8]> = received_data_map.keys().map(|n| n.as_slice()).collect();
+        let lookup_width = self.option.lookup_width.max(1);
+        let replication_factor = self.option.replication_factor.max(1);
 
         // 全ノードに配布する情報
         let mut push_node_profiles: HashSet<Arc<NodeProfile>> = HashSet::new();
@@ -198,7 +217,7 @@ impl Inner {
         // Kadexの距離が近いノードにwant_asset_keyを配布する
         let mut sending_want_asset_key_map: HashMap<&[u8], Vec<Arc<AssetKey>>> = HashMap::new();
         for target_key in want_asset_keys.iter() {
-            for id in Kadex::find(&my_node_profile.id, &target_key.hash.value, &ids, 1) {
+            for id in Kadex::find(&my_node_profile.id, &target_key.hash.value, &ids, lookup_width) {
                 sending_want_asset_key_map.entry(id).or_default().push(target_key.clone());
             }
         }
@@ -219,7 +238,7 @@ impl Inner {
         // Kadexの距離が近いノードにpush_asset_key_locationsを配布する
         let mut sending_push_asset_key_location_map: HashMap<&[u8], HashMap<Arc<AssetKey>, &HashSet<Arc<NodeProfile>>>> = HashMap::new();
         for (target_key, node_profiles) in push_asset_key_locations.iter() {
-            for id in Kadex::find(&my_node_profile.id, &target_key.hash.value, &ids, 1) {
+            for id in Kadex::find(&my_node_profile.id, &target_key.hash.value, &ids, replication_factor) {
                 sending_push_asset_key_location_map
                     .entry(id)
                     .or_default()
@@ -227,7 +246,36 @@ impl Inner {
             }
         }
 
+        // 同じキーのポインタは sequence の最も大きいものだけを残す
+        let mut push_asset_pointers: HashMap<AssetKey, Arc<AssetPointer>> = HashMap::new();
+        let received_asset_pointers = received_data_map.values().flat_map(|n| n.push_asset_pointers.iter());
+        for pointer in my_get_push_asset_pointers.iter().chain(received_asset_pointers) {
+            let key = pointer.key();
+            match push_asset_pointers.get(&key) {
+                Some(current) if current.sequence >= pointer.sequence => {}
+                _ => {
+                    push_asset_pointers.insert(key, pointer.clone());
+                }
+            }
+        }
+
+        // Kadexの距離が近いノードと、キーをwantしているノードにポインタを配布する
+        let mut sending_push_asset_pointer_map: HashMap<&[u8], HashMap<&AssetKey, Arc<AssetPointer>>> = HashMap::new();
+        for (target_key, pointer) in push_asset_pointers.iter() {
+            for id in Kadex::find(&my_node_profile.id, &target_key.hash.value, &ids, replication_factor) {
+                sending_push_asset_pointer_map.entry(id).or_default().insert(target_key, pointer.clone());
+            }
+        }
+        for (id, data) in received_data_map.iter() {
+            for target_key in data.want_asset_keys.iter() {
+                if let Some((target_key, pointer)) = push_asset_pointers.get_key_value(target_key.as_ref()) {
+                    sending_push_asset_pointer_map.entry(id).or_default().insert(target_key, pointer.clone());
+                }
+            }
+        }
+
         // Session毎にデータを実体化する
+        // 受信側は limits の上限を越えるメッセージを拒否するため、同じ上限で切り詰める
         let mut sending_data_map: HashMap<Vec<u8>, SendingDataMessage> = HashMap::new();
 
         let push_node_profiles: Vec<NodeProfile> = push_node_profiles.into_iter().map(|n| n.as_ref().clone()).collect();
@@ -237,22 +285,40 @@ impl Inner {
                 .get(id.as_slice())
                 .unwrap_or(&Vec::new())
                 .iter()
-                .take(1024 * 256)
+                .take(limits::MAX_ASSET_KEY_COUNT)
                 .map(|n| n.as_ref().clone())
                 .collect();
             let give_asset_key_locations = sending_give_asset_key_location_map
                 .get(id.as_slice())
                 .unwrap_or(&HashMap::new())
                 .iter()
-                .take(1024 * 256)
-                .map(|(k, v)| (k.as_ref().clone(), v.iter().map(|n| n.as_ref().clone()).collect()))
+                .take(limits::MAX_ASSET_KEY_COUNT)
+                .map(|(k, v)| {
+                    (
+                        k.as_ref().clone(),
+                        v.iter().take(limits::MAX_NODE_PROFILE_COUNT).map(|n| n.as_ref().clone()).collect(),
+                    )
+                })
                 .collect();
             let push_asset_key_locations = sending_push_asset_key_location_map
                 .get(id.as_slice())
                 .unwrap_or(&HashMap::new())
                 .iter()
-                .take(1024 * 256)
-                .map(|(k, v)| (k.as_ref().clone(), v.iter().map(|n| n.as_ref().clone()).collect()))
+                .take(limits::MAX_ASSET_KEY_COUNT)
+                .map(|(k, v)| {
+                    (
+                        k.as_ref().clone(),
+                        v.iter().take(limits::MAX_NODE_PROFILE_COUNT).map(|n| n.as_ref().clone()).collect(),
+                    )
+                })
+                .collect();
+
+            let push_asset_pointers = sending_push_asset_pointer_map
+                .get(id.as_slice())
+                .unwrap_or(&HashMap::new())
+                .values()
+                .take(limits::MAX_ASSET_POINTER_COUNT)
+                .map(|n| n.as_ref().clone())
                 .collect();
 
             let data_message = SendingDataMessage {
@@ -260,17 +326,15 @@ impl Inner {
                 want_asset_keys,
                 give_asset_key_locations,
                 push_asset_key_locations,
+                push_asset_pointers,
             };
             sending_data_map.insert(id.clone(), data_message);
         }
 
         // Session毎に送信用データを格納する
-        {
-            let mut sessions = self.sessions.write().await;
-            for (id, status) in sessions.iter_mut() {
-                if let Some(data_message) = sending_data_map.remove(id) {
-                    *status.sending_data_message.lock() = data_message;
-                }
+        for (id, data_message) in sending_data_map {
+            if let Some(status) = self.sessions.get(&id) {
+                *status.sending_data_message.lock() = data_message;
             }
         }
 
@@ -282,4 +346,5 @@ struct ReceivedTempDataMessage {
     pub want_asset_keys: Vec<Arc<AssetKey>>,
     pub give_asset_key_locations: Vec<(Arc<AssetKey>, Vec<Arc<NodeProfile>>)>,
     pub push_asset_key_locations: Vec<(Arc<AssetKey>, Vec<Arc<NodeProfile>>)>,
+    pub push_asset_pointers: Vec<Arc<AssetPointer>>,
 }
@@ -1,15 +1,22 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use async_trait::async_trait;
-use futures::FutureExt;
+use futures::TryStreamExt as _;
 use parking_lot::Mutex;
-use rand::{seq::SliceRandom, SeedableRng};
+use rand::{Rng as _, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use tokio::{
-    sync::{mpsc, Mutex as TokioMutex, RwLock as TokioRwLock},
+    sync::{mpsc, Mutex as TokioMutex},
     task::JoinHandle,
 };
-use tracing::warn;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
 
 use omnius_core_base::{sleeper::Sleeper, terminable::Terminable};
 
@@ -20,26 +27,31 @@ use crate::{
             model::{Session, SessionType},
             SessionConnector,
         },
-        util::VolatileHashSet,
+        util::{shutdown_task, sleep_or_cancelled, VolatileHashSet, TASK_SHUTDOWN_GRACE_PERIOD},
     },
 };
 
-use super::{HandshakeType, NodeFinderOption, NodeProfileRepo, SessionStatus};
+use super::{ConnectionPacer, HandshakeType, NodeFinderOption, NodeProfileRepo, SessionRegistry, SessionStatus};
 
 #[derive(Clone)]
 pub struct TaskConnector {
     inner: Inner,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
     join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
 }
 
 impl TaskConnector {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+        sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
         session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
         session_connector: Arc<SessionConnector>,
         connected_node_profiles: Arc<Mutex<VolatileHashSet<NodeProfile>>>,
         node_profile_repo: Arc<NodeProfileRepo>,
+        connection_pacer: Arc<ConnectionPacer>,
+        draining: Arc<AtomicBool>,
+        priority_node_profiles: Arc<Mutex<VecDeque<NodeProfile>>>,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
         option: NodeFinderOption,
     ) -> Self {
@@ -49,24 +61,34 @@ impl TaskConnector {
             session_connector,
             connected_node_profiles,
             node_profile_repo,
+            connection_pacer,
+            draining,
+            priority_node_profiles,
             option,
         };
         Self {
             inner,
             sleeper,
             join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
         }
     }
 
     pub async fn run(&self) {
         let sleeper = self.sleeper.clone();
         let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
         let join_handle = tokio::spawn(async move {
             loop {
-                sleeper.sleep(std::time::Duration::from_secs(1)).await;
+                let delay = inner
+                    .connection_pacer
+                    .next_delay(inner.connected_session_count(), inner.option.max_connected_session_count);
+                if !sleep_or_cancelled(sleeper.as_ref(), delay, &cancellation_token).await {
+                    return;
+                }
                 let res = inner.connect().await;
                 if let Err(e) = res {
-                    warn!(error_message = e.to_string(), "connect failed");
+                    warn!(error_message = format!("{:#}", e), "connect failed");
                 }
             }
         });
@@ -79,8 +101,7 @@ impl Terminable for TaskConnector {
     type Error = anyhow::Error;
     async fn terminate(&self) -> anyhow::Result<()> {
         if let Some(join_handle) = self.join_handle.lock().await.take() {
-            join_handle.abort();
-            let _ = join_handle.fuse().await;
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
         }
 
         Ok(())
@@ -89,40 +110,43 @@ impl Terminable for TaskConnector {
 
 #[derive(Clone)]
 struct Inner {
-    sessions: Arc<TokioRwLock<HashMap<Vec<u8>, Arc<SessionStatus>>>>,
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
     session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
     session_connector: Arc<SessionConnector>,
     connected_node_profiles: Arc<Mutex<VolatileHashSet<NodeProfile>>>,
     node_profile_repo: Arc<NodeProfileRepo>,
+    connection_pacer: Arc<ConnectionPacer>,
+    draining: Arc<AtomicBool>,
+    priority_node_profiles: Arc<Mutex<VecDeque<NodeProfile>>>,
     option: NodeFinderOption,
 }
 
 impl Inner {
+    fn connected_session_count(&self) -> usize {
+        self.sessions.count(|status| status.handshake_type == HandshakeType::Connected)
+    }
+
     async fn connect(&self) -> anyhow::Result<()> {
-        let session_count = self
-            .sessions
-            .read()
-            .await
-            .iter()
-            .filter(|(_, status)| status.handshake_type == HandshakeType::Connected)
-            .count();
+        if self.draining.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let session_count = self.connected_session_count();
         if session_count >= self.option.max_connected_session_count {
             return Ok(());
         }
 
         self.connected_node_profiles.lock().refresh();
 
-        let mut rng = ChaCha20Rng::from_entropy();
-        let node_profiles = self.node_profile_repo.get_node_profiles().await?;
-        let node_profile = node_profiles.choose(&mut rng).ok_or(anyhow::anyhow!("Not found node_profile"))?;
-
-        if self
-            .sessions
-            .read()
-            .await
-            .iter()
-            .any(|(_, status)| status.node_profile.id == node_profile.id)
-        {
+        // 前回接続できていたノードを先に試し、尽きたら保存済みのノードから選ぶ
+        let priority_node_profile = self.priority_node_profiles.lock().pop_front();
+        let node_profile = match priority_node_profile {
+            Some(node_profile) => node_profile,
+            None => self.select_node_profile().await?,
+        };
+        let node_profile = &node_profile;
+
+        if self.sessions.contains(&node_profile.id) {
             anyhow::bail!("Already connected");
         }
 
@@ -130,13 +154,38 @@ impl Inner {
             anyhow::bail!("connected_node_profiles contains");
         }
 
+        let mut connected = false;
         for addr in node_profile.addrs.iter() {
-            if let Ok(session) = self.session_connector.connect(addr, &SessionType::NodeFinder).await {
-                self.session_sender.lock().await.send((HandshakeType::Connected, session)).await?;
-                self.connected_node_profiles.lock().insert(node_profile.clone());
+            match self.session_connector.connect(addr, &SessionType::NodeFinder).await {
+                Ok(session) => {
+                    self.session_sender.lock().await.send((HandshakeType::Connected, session)).await?;
+                    self.connected_node_profiles.lock().insert(node_profile.clone());
+                    connected = true;
+                }
+                // 原因まで辿れるよう、エラーの連鎖ごと出力する
+                Err(e) => debug!(%addr, error_message = format!("{:#}", e), "session connect failed"),
             }
         }
+        self.connection_pacer.record(connected);
 
         Ok(())
     }
+
+    // 全件を読み込まずに 1 件を選ぶため、リザーバサンプリングを行う
+    // 直接接続できると伝えているノードを優先し、居なければそれ以外から選ぶ
+    async fn select_node_profile(&self) -> anyhow::Result<NodeProfile> {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut node_profiles = self.node_profile_repo.stream_node_profiles();
+        let mut selected: [Option<NodeProfile>; 2] = [None, None];
+        let mut counts: [u64; 2] = [0, 0];
+        while let Some(node_profile) = node_profiles.try_next().await? {
+            let i = if node_profile.reachable { 0 } else { 1 };
+            counts[i] += 1;
+            if rng.gen_range(0..counts[i]) == 0 {
+                selected[i] = Some(node_profile);
+            }
+        }
+        let [reachable, unreachable] = selected;
+        reachable.or(unreachable).ok_or(anyhow::anyhow!("Not found node_profile"))
+    }
 }
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::{sync::Mutex as TokioMutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
+
+use crate::service::util::{shutdown_task, sleep_or_cancelled, TASK_SHUTDOWN_GRACE_PERIOD};
+
+use super::{NodeFinderOption, SessionCloseReason, SessionRegistry, SessionStatus};
+
+// 受信が途絶えたまま残っているセッションを閉じる
+#[derive(Clone)]
+pub struct TaskReaper {
+    inner: Inner,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
+}
+
+impl TaskReaper {
+    pub fn new(
+        sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
+        clock: Arc<dyn Clock<Utc> + Send + Sync>,
+        sleeper: Arc<dyn Sleeper + Send + Sync>,
+        option: NodeFinderOption,
+    ) -> Self {
+        let inner = Inner { sessions, clock, option };
+        Self {
+            inner,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    pub async fn run(&self) {
+        let sleeper = self.sleeper.clone();
+        let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(30), &cancellation_token).await {
+                    return;
+                }
+                inner.reap();
+            }
+        });
+        *self.join_handle.lock().await = Some(join_handle);
+    }
+}
+
+#[async_trait]
+impl Terminable for TaskReaper {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        if let Some(join_handle) = self.join_handle.lock().await.take() {
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Inner {
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
+    clock: Arc<dyn Clock<Utc> + Send + Sync>,
+    option: NodeFinderOption,
+}
+
+impl Inner {
+    fn reap(&self) {
+        let Ok(idle_timeout) = chrono::Duration::from_std(self.option.session_idle_timeout) else {
+            return;
+        };
+
+        // セッション表からの削除は、送受信のタスクが止まった後に通信タスク側で行う
+        let now = self.clock.now();
+        for (_, status) in self.sessions.snapshot() {
+            if status.idle_duration(now) > idle_timeout {
+                info!(node_profile = status.node_profile.to_string(), "Session idle, closing");
+                status.close(SessionCloseReason::Idle);
+            }
+        }
+    }
+}
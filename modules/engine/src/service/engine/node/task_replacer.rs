@@ -0,0 +1,265 @@
+use std::{
+    cmp::Ordering as CmpOrdering,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{StreamExt as _, TryStreamExt as _};
+use parking_lot::Mutex;
+use tokio::{
+    sync::{mpsc, Mutex as TokioMutex},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use omnius_core_base::{sleeper::Sleeper, terminable::Terminable};
+
+use crate::{
+    model::{AssetKey, NodeProfile},
+    service::{
+        session::{
+            model::{Session, SessionType},
+            SessionConnector,
+        },
+        util::{shutdown_task, sleep_or_cancelled, FnExecutor, Kadex, VolatileHashSet, TASK_SHUTDOWN_GRACE_PERIOD},
+    },
+};
+
+use super::{HandshakeType, NodeFinderOption, NodeProfileRepo, SessionCloseReason, SessionRegistry, SessionStatus};
+
+// 候補として読み込むノード情報の上限
+const MAX_CANDIDATE_COUNT: usize = 1024;
+
+// 入れ替え先とのハンドシェイクを待つ間隔と回数
+const ESTABLISH_WAIT_INTERVAL: Duration = Duration::from_secs(1);
+const ESTABLISH_WAIT_COUNT: usize = 30;
+
+// 接続数が上限に達していても、want しているキーにより近いノードが見つかれば、最も遠いセッションと入れ替える
+// 入れ替え先に繋がってから閉じるため、繋がらない候補のために接続を失うことはない
+#[derive(Clone)]
+pub struct TaskReplacer {
+    inner: Inner,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
+}
+
+impl TaskReplacer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
+        node_profile_repo: Arc<NodeProfileRepo>,
+        session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
+        session_connector: Arc<SessionConnector>,
+        connected_node_profiles: Arc<Mutex<VolatileHashSet<NodeProfile>>>,
+        get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+        draining: Arc<AtomicBool>,
+        sleeper: Arc<dyn Sleeper + Send + Sync>,
+        option: NodeFinderOption,
+    ) -> Self {
+        let inner = Inner {
+            sessions,
+            node_profile_repo,
+            session_sender,
+            session_connector,
+            connected_node_profiles,
+            get_want_asset_keys_fn,
+            draining,
+            sleeper: sleeper.clone(),
+            option,
+        };
+        Self {
+            inner,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    pub async fn run(&self) {
+        let sleeper = self.sleeper.clone();
+        let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(60), &cancellation_token).await {
+                    return;
+                }
+                if let Err(e) = inner.replace().await {
+                    warn!(error_message = e.to_string(), "replace failed");
+                }
+            }
+        });
+        *self.join_handle.lock().await = Some(join_handle);
+    }
+}
+
+#[async_trait]
+impl Terminable for TaskReplacer {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        if let Some(join_handle) = self.join_handle.lock().await.take() {
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Inner {
+    sessions: Arc<SessionRegistry<Arc<SessionStatus>>>,
+    node_profile_repo: Arc<NodeProfileRepo>,
+    session_sender: Arc<TokioMutex<mpsc::Sender<(HandshakeType, Session)>>>,
+    session_connector: Arc<SessionConnector>,
+    connected_node_profiles: Arc<Mutex<VolatileHashSet<NodeProfile>>>,
+    get_want_asset_keys_fn: FnExecutor<Vec<AssetKey>, ()>,
+    draining: Arc<AtomicBool>,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    option: NodeFinderOption,
+}
+
+impl Inner {
+    async fn replace(&self) -> anyhow::Result<()> {
+        if self.draining.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let connected: Vec<Arc<SessionStatus>> = self
+            .sessions
+            .snapshot()
+            .into_iter()
+            .filter(|(_, status)| status.handshake_type == HandshakeType::Connected)
+            .map(|(_, status)| status)
+            .collect();
+        if connected.len() < self.option.max_connected_session_count {
+            return Ok(());
+        }
+
+        let want_asset_keys: Vec<AssetKey> = self.get_want_asset_keys_fn.execute(&()).into_iter().flatten().collect();
+        if want_asset_keys.is_empty() {
+            return Ok(());
+        }
+        let targets: Vec<&[u8]> = want_asset_keys.iter().map(|n| n.hash.value.as_slice()).collect();
+
+        // 接続中のものと、最近接続を試みたものは候補から外す
+        self.connected_node_profiles.lock().refresh();
+        let candidates: Vec<NodeProfile> = self
+            .node_profile_repo
+            .stream_node_profiles()
+            .try_filter(|n| {
+                let skip = self.sessions.contains(&n.id) || self.connected_node_profiles.lock().contains(n);
+                futures::future::ready(!skip)
+            })
+            .take(MAX_CANDIDATE_COUNT)
+            .try_collect()
+            .await?;
+
+        let connected_ids: Vec<&[u8]> = connected.iter().map(|n| n.node_profile.id.as_slice()).collect();
+        let candidate_ids: Vec<&[u8]> = candidates.iter().map(|n| n.id.as_slice()).collect();
+        let Some((worst, best)) = select_replacement(&connected_ids, &candidate_ids, &targets) else {
+            return Ok(());
+        };
+
+        let status = &connected[worst];
+        let candidate = &candidates[best];
+
+        // 繋がらなかった場合も、すぐには同じ候補を試さない
+        self.connected_node_profiles.lock().insert(candidate.clone());
+        if !self.connect(candidate).await? {
+            debug!(replacement = candidate.to_string(), "replacement not established");
+            return Ok(());
+        }
+        if self.draining.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        info!(
+            node_profile = status.node_profile.to_string(),
+            replacement = candidate.to_string(),
+            "Session replaced by a closer node"
+        );
+        status.close(SessionCloseReason::Replaced);
+
+        Ok(())
+    }
+
+    // 入れ替え先のセッションが確立した場合に true を返す
+    async fn connect(&self, node_profile: &NodeProfile) -> anyhow::Result<bool> {
+        let mut session: Option<Session> = None;
+        for addr in node_profile.addrs.iter() {
+            match self.session_connector.connect(addr, &SessionType::NodeFinder).await {
+                Ok(v) => {
+                    session = Some(v);
+                    break;
+                }
+                Err(e) => debug!(%addr, error_message = format!("{:#}", e), "session connect failed"),
+            }
+        }
+        let Some(session) = session else {
+            return Ok(false);
+        };
+        self.session_sender.lock().await.send((HandshakeType::Connected, session)).await?;
+
+        // TaskCommunicator がハンドシェイクを終えて登録するまで待つ
+        for _ in 0..ESTABLISH_WAIT_COUNT {
+            if self.sessions.contains(&node_profile.id) {
+                return Ok(true);
+            }
+            self.sleeper.sleep(ESTABLISH_WAIT_INTERVAL).await;
+        }
+
+        Ok(false)
+    }
+}
+
+// want しているキーのうち、最も近いものとの距離
+fn closest_diff(id: &[u8], targets: &[&[u8]]) -> Option<Vec<u8>> {
+    targets
+        .iter()
+        .map(|target| target.iter().zip(id).map(|(x, y)| x ^ y).collect::<Vec<u8>>())
+        .min_by(|x, y| Kadex::compare(x, y))
+}
+
+// 接続中で最も遠いものより近い候補があれば、(接続中の添字, 候補の添字) を返す
+fn select_replacement(connected_ids: &[&[u8]], candidate_ids: &[&[u8]], targets: &[&[u8]]) -> Option<(usize, usize)> {
+    let (worst, worst_diff) = connected_ids
+        .iter()
+        .enumerate()
+        .filter_map(|(i, id)| Some((i, closest_diff(id, targets)?)))
+        .max_by(|x, y| Kadex::compare(&x.1, &y.1))?;
+    let (best, best_diff) = candidate_ids
+        .iter()
+        .enumerate()
+        .filter_map(|(i, id)| Some((i, closest_diff(id, targets)?)))
+        .min_by(|x, y| Kadex::compare(&x.1, &y.1))?;
+
+    (Kadex::compare(&best_diff, &worst_diff) == CmpOrdering::Less).then_some((worst, best))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::select_replacement;
+
+    #[test]
+    pub fn select_replacement_test() {
+        let targets: Vec<&[u8]> = vec![&[0, 0]];
+
+        // 最も遠い接続中のものを、最も近い候補と入れ替える
+        let connected: Vec<&[u8]> = vec![&[0, 1], &[0, 8], &[0, 2]];
+        let candidates: Vec<&[u8]> = vec![&[0, 4], &[0, 3], &[0, 9]];
+        assert_eq!(select_replacement(&connected, &candidates, &targets), Some((1, 1)));
+
+        // 候補の方が遠ければ入れ替えない
+        let candidates: Vec<&[u8]> = vec![&[0, 9], &[0, 8]];
+        assert_eq!(select_replacement(&connected, &candidates, &targets), None);
+
+        assert_eq!(select_replacement(&connected, &[], &targets), None);
+        assert_eq!(select_replacement(&connected, &candidates, &[]), None);
+    }
+}
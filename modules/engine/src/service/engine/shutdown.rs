@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex as TokioMutex;
+use tracing::{info, warn};
+
+use omnius_core_base::terminable::Terminable;
+
+// 依存される側ほど後に停止する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownStage {
+    Exchanger,
+    Finder,
+    Sessions,
+    Accepters,
+    Storage,
+}
+
+type Component = Arc<dyn Terminable<Error = anyhow::Error> + Send + Sync>;
+
+// エンジンを構成するサブシステムを、段階毎に依存の順で停止する
+// 途中で失敗しても残りの停止は続け、最初のエラーを返す
+#[derive(Default)]
+pub struct ShutdownSequence {
+    components: TokioMutex<Vec<(ShutdownStage, &'static str, Component)>>,
+}
+
+impl ShutdownSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn register(&self, stage: ShutdownStage, name: &'static str, component: Component) {
+        self.components.lock().await.push((stage, name, component));
+    }
+}
+
+#[async_trait]
+impl Terminable for ShutdownSequence {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        let mut components: Vec<(ShutdownStage, &'static str, Component)> = self.components.lock().await.drain(..).collect();
+        // 同じ段階のものは登録の逆順に停止する
+        components.reverse();
+        components.sort_by_key(|(stage, _, _)| *stage);
+
+        let mut first_error: Option<anyhow::Error> = None;
+        for (stage, name, component) in components {
+            info!(?stage, name, "shutting down");
+            if let Err(e) = component.terminate().await {
+                warn!(?stage, name, error_message = e.to_string(), "shutdown failed");
+                first_error.get_or_insert(e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use testresult::TestResult;
+
+    use omnius_core_base::terminable::Terminable;
+
+    use super::{ShutdownSequence, ShutdownStage};
+
+    struct Recorder {
+        name: &'static str,
+        fail: bool,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl Terminable for Recorder {
+        type Error = anyhow::Error;
+        async fn terminate(&self) -> anyhow::Result<()> {
+            self.log.lock().push(self.name);
+            if self.fail {
+                anyhow::bail!("{} failed", self.name);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    pub async fn order_test() -> TestResult {
+        let log = Arc::new(Mutex::new(vec![]));
+        let sequence = ShutdownSequence::new();
+
+        for (stage, name, fail) in [
+            (ShutdownStage::Storage, "storage", false),
+            (ShutdownStage::Accepters, "accepter", true),
+            (ShutdownStage::Exchanger, "exchanger", false),
+            (ShutdownStage::Finder, "finder", false),
+        ] {
+            let recorder = Recorder {
+                name,
+                fail,
+                log: log.clone(),
+            };
+            sequence.register(stage, name, Arc::new(recorder)).await;
+        }
+
+        // 失敗した段階があっても残りは停止される
+        assert!(sequence.terminate().await.is_err());
+        assert_eq!(*log.lock(), vec!["exchanger", "finder", "accepter", "storage"]);
+
+        Ok(())
+    }
+}
@@ -2,35 +2,60 @@ mod accepter;
 mod connector;
 pub mod message;
 pub mod model;
+mod peer_limiter;
 
 pub use accepter::*;
 pub use connector::*;
+pub use peer_limiter::*;
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
 
     use parking_lot::Mutex;
     use testresult::TestResult;
 
-    use omnius_core_base::{random_bytes::RandomBytesProviderImpl, sleeper::FakeSleeper, terminable::Terminable as _};
+    use omnius_core_base::{
+        random_bytes::RandomBytesProviderImpl,
+        sleeper::{FakeSleeper, SleeperImpl},
+        terminable::Terminable as _,
+    };
     use omnius_core_omnikit::model::{OmniAddr, OmniSignType, OmniSigner};
     use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
-    use crate::service::{
-        connection::{ConnectionTcpAccepterImpl, ConnectionTcpConnectorImpl, FramedRecvExt as _, FramedSendExt as _, TcpProxyOption, TcpProxyType},
-        session::{model::SessionType, SessionAccepter, SessionConnector},
+    use crate::{
+        limits::{MessageLimit, MAX_FRAME_LENGTH},
+        service::{
+            connection::{
+                BandwidthLimiter, ConnectionTcpAccepterImpl, ConnectionTcpConnectorImpl, FramedRecvExt as _, FramedSendExt as _, TcpProxyOption,
+                TcpProxyType,
+            },
+            session::{model::SessionType, SessionAccepter, SessionAccepterOption, SessionConnector, SessionConnectorOption},
+        },
     };
 
     #[tokio::test]
     #[ignore]
     async fn simple_test() -> TestResult {
-        let tcp_accepter = Arc::new(ConnectionTcpAccepterImpl::new(&OmniAddr::create_tcp("127.0.0.1".parse()?, 60000), false).await?);
+        let tcp_accepter = Arc::new(
+            ConnectionTcpAccepterImpl::new(
+                &OmniAddr::create_tcp("127.0.0.1".parse()?, 60000),
+                false,
+                Arc::new(BandwidthLimiter::default()),
+            )
+            .await?,
+        );
         let tcp_connector = Arc::new(
-            ConnectionTcpConnectorImpl::new(TcpProxyOption {
-                typ: TcpProxyType::None,
-                addr: None,
-            })
+            ConnectionTcpConnectorImpl::new(
+                TcpProxyOption {
+                    typ: TcpProxyType::None,
+                    addr: None,
+                    credential: None,
+                },
+                Arc::new(BandwidthLimiter::default()),
+            )
             .await?,
         );
 
@@ -38,8 +63,15 @@ mod tests {
         let random_bytes_provider = Arc::new(Mutex::new(RandomBytesProviderImpl::new()));
         let sleeper = Arc::new(FakeSleeper);
 
-        let session_accepter = SessionAccepter::new(tcp_accepter.clone(), signer.clone(), random_bytes_provider.clone(), sleeper.clone()).await;
-        let session_connector = SessionConnector::new(tcp_connector, signer, random_bytes_provider);
+        let session_accepter = SessionAccepter::new(
+            tcp_accepter.clone(),
+            signer.clone(),
+            random_bytes_provider.clone(),
+            sleeper.clone(),
+            SessionAccepterOption::default(),
+        )
+        .await;
+        let session_connector = SessionConnector::new(tcp_connector, signer, random_bytes_provider, SessionConnectorOption::default());
 
         let client = Arc::new(
             session_connector
@@ -67,11 +99,70 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn network_key_test() -> TestResult {
+        let addr = OmniAddr::create_tcp("127.0.0.1".parse()?, 60020);
+        let tcp_accepter = Arc::new(ConnectionTcpAccepterImpl::new(&addr, false, Arc::new(BandwidthLimiter::default())).await?);
+        let tcp_connector = Arc::new(
+            ConnectionTcpConnectorImpl::new(
+                TcpProxyOption {
+                    typ: TcpProxyType::None,
+                    addr: None,
+                    credential: None,
+                },
+                Arc::new(BandwidthLimiter::default()),
+            )
+            .await?,
+        );
+        let random_bytes_provider = Arc::new(Mutex::new(RandomBytesProviderImpl::new()));
+
+        let session_accepter = SessionAccepter::new(
+            tcp_accepter.clone(),
+            Arc::new(OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "server")?),
+            random_bytes_provider.clone(),
+            Arc::new(SleeperImpl),
+            SessionAccepterOption {
+                network_key: Some(b"network1".to_vec()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let client_signer = Arc::new(OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "client")?);
+        let new_session_connector = |network_key: &[u8]| {
+            SessionConnector::new(
+                tcp_connector.clone(),
+                client_signer.clone(),
+                random_bytes_provider.clone(),
+                SessionConnectorOption {
+                    network_key: Some(network_key.to_vec()),
+                    handshake_timeout: Duration::from_secs(5),
+                    ..Default::default()
+                },
+            )
+        };
+
+        // ネットワーク鍵が異なるノードとはハンドシェイクに失敗する
+        assert!(new_session_connector(b"network2").connect(&addr, &SessionType::NodeFinder).await.is_err());
+
+        let _client = new_session_connector(b"network1").connect(&addr, &SessionType::NodeFinder).await?;
+        let _server = session_accepter.accept(&SessionType::NodeFinder).await?;
+
+        session_accepter.terminate().await?;
+        tcp_accepter.terminate().await?;
+
+        Ok(())
+    }
+
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct TestMessage {
         pub value: String,
     }
 
+    impl MessageLimit for TestMessage {
+        const MAX_LENGTH: usize = MAX_FRAME_LENGTH;
+    }
+
     impl RocketMessage for TestMessage {
         fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
             writer.put_str(&value.value);
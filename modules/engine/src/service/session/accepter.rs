@@ -1,25 +1,36 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use futures::{future::join_all, FutureExt};
+use futures::future::join_all;
 use parking_lot::Mutex;
 use tokio::{
     sync::{mpsc, Mutex as TokioMutex},
     task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
 use tracing::warn;
 
 use omnius_core_base::{random_bytes::RandomBytesProvider, sleeper::Sleeper, terminable::Terminable};
 use omnius_core_omnikit::model::{OmniAddr, OmniSigner};
 
 use crate::service::{
-    connection::{ConnectionTcpAccepter, FramedRecvExt as _, FramedSendExt as _},
+    connection::{ConnectionTcpAccepter, FramedRecvExt as _, FramedSendExt as _, FramedStream},
     session::message::{HelloMessage, SessionVersion, V1ChallengeMessage, V1RequestMessage, V1SignatureMessage},
+    util::{shutdown_task, sleep_or_cancelled, TASK_SHUTDOWN_GRACE_PERIOD},
 };
 
 use super::{
     message::{V1RequestType, V1ResultMessage, V1ResultType},
     model::{Session, SessionHandshakeType, SessionType},
+    PeerLimiter, PeerSlot, DEFAULT_HANDSHAKE_TIMEOUT,
 };
 
 pub struct SessionAccepter {
@@ -30,6 +41,33 @@ pub struct SessionAccepter {
     receivers: Arc<TokioMutex<HashMap<SessionType, mpsc::Receiver<Session>>>>,
     senders: Arc<TokioMutex<HashMap<SessionType, mpsc::Sender<Session>>>>,
     task_acceptors: Arc<TokioMutex<Vec<TaskAccepter>>>,
+    peer_limiter: Arc<PeerLimiter>,
+    paused: Arc<AtomicBool>,
+    option: SessionAccepterOption,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionAccepterOption {
+    pub network_key: Option<Vec<u8>>,
+    pub handshake_timeout: Duration,
+    // 同じ IP アドレスから同時に受け入れるセッション数
+    pub max_sessions_per_ip: usize,
+    // 同じ /24 (IPv6 は /48) から同時に受け入れるセッション数
+    pub max_sessions_per_subnet: usize,
+    // ループバックやプライベートアドレスからのセッションは上記の制限を受けない
+    pub exempt_lan: bool,
+}
+
+impl Default for SessionAccepterOption {
+    fn default() -> Self {
+        Self {
+            network_key: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_sessions_per_ip: 2,
+            max_sessions_per_subnet: 4,
+            exempt_lan: true,
+        }
+    }
 }
 
 impl SessionAccepter {
@@ -38,6 +76,7 @@ impl SessionAccepter {
         signer: Arc<OmniSigner>,
         random_bytes_provider: Arc<Mutex<dyn RandomBytesProvider + Send + Sync>>,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
+        option: SessionAccepterOption,
     ) -> Self {
         let senders = Arc::new(TokioMutex::new(HashMap::<SessionType, mpsc::Sender<Session>>::new()));
         let receivers = Arc::new(TokioMutex::new(HashMap::<SessionType, mpsc::Receiver<Session>>::new()));
@@ -56,6 +95,13 @@ impl SessionAccepter {
             receivers,
             senders,
             task_acceptors: Arc::new(TokioMutex::new(Vec::new())),
+            peer_limiter: Arc::new(PeerLimiter::new(
+                option.max_sessions_per_ip,
+                option.max_sessions_per_subnet,
+                option.exempt_lan,
+            )),
+            paused: Arc::new(AtomicBool::new(false)),
+            option,
         };
         result.run().await;
 
@@ -69,7 +115,10 @@ impl SessionAccepter {
                 self.tcp_connector.clone(),
                 self.signer.clone(),
                 self.random_bytes_provider.clone(),
+                self.peer_limiter.clone(),
+                self.paused.clone(),
                 self.sleeper.clone(),
+                self.option.clone(),
             );
             task.run().await;
             self.task_acceptors.lock().await.push(task);
@@ -82,6 +131,15 @@ impl SessionAccepter {
 
         receiver.recv().await.ok_or_else(|| anyhow::anyhow!("Receiver closed"))
     }
+
+    // resume を呼ぶまで、届いた接続はハンドシェイクせずに切断する
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
 }
 
 #[async_trait]
@@ -101,6 +159,7 @@ struct TaskAccepter {
     inner: Inner,
     sleeper: Arc<dyn Sleeper + Send + Sync>,
     join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
 }
 
 impl TaskAccepter {
@@ -109,30 +168,44 @@ impl TaskAccepter {
         tcp_connector: Arc<dyn ConnectionTcpAccepter + Send + Sync>,
         signer: Arc<OmniSigner>,
         random_bytes_provider: Arc<Mutex<dyn RandomBytesProvider + Send + Sync>>,
+        peer_limiter: Arc<PeerLimiter>,
+        paused: Arc<AtomicBool>,
         sleeper: Arc<dyn Sleeper + Send + Sync>,
+        option: SessionAccepterOption,
     ) -> Self {
         let inner = Inner {
             senders,
             tcp_connector,
             signer,
             random_bytes_provider,
+            peer_limiter,
+            paused,
+            option,
         };
         Self {
             inner,
             sleeper,
             join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
         }
     }
 
     pub async fn run(&self) {
         let sleeper = self.sleeper.clone();
         let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
         let join_handle = tokio::spawn(async move {
             loop {
-                sleeper.sleep(std::time::Duration::from_secs(1)).await;
-                let res = inner.accept().await;
+                if !sleep_or_cancelled(sleeper.as_ref(), std::time::Duration::from_secs(1), &cancellation_token).await {
+                    return;
+                }
+                // 受け入れ待ちの間はまだ何も書き込んでいないので、ここで中断してよい
+                let res = tokio::select! {
+                    _ = cancellation_token.cancelled() => return,
+                    res = inner.accept() => res,
+                };
                 if let Err(e) = res {
-                    warn!(error_message = e.to_string(), "accept failed");
+                    warn!(error_message = format!("{:#}", e), "accept failed");
                 }
             }
         });
@@ -145,8 +218,7 @@ impl Terminable for TaskAccepter {
     type Error = anyhow::Error;
     async fn terminate(&self) -> anyhow::Result<()> {
         if let Some(join_handle) = self.join_handle.lock().await.take() {
-            join_handle.abort();
-            let _ = join_handle.fuse().await;
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
         }
 
         Ok(())
@@ -159,12 +231,33 @@ struct Inner {
     tcp_connector: Arc<dyn ConnectionTcpAccepter + Send + Sync>,
     signer: Arc<OmniSigner>,
     random_bytes_provider: Arc<Mutex<dyn RandomBytesProvider + Send + Sync>>,
+    peer_limiter: Arc<PeerLimiter>,
+    paused: Arc<AtomicBool>,
+    option: SessionAccepterOption,
 }
 
 impl Inner {
     async fn accept(&self) -> anyhow::Result<()> {
         let (stream, addr) = self.tcp_connector.accept().await?;
 
+        // 止めている間はハンドシェイクを始めず、そのまま切断する
+        if self.paused.load(Ordering::SeqCst) {
+            drop(stream);
+            return Ok(());
+        }
+
+        // ハンドシェイク中のものも数え、上限を超える相手とはそのまま切断する
+        let Some(peer_slot) = self.peer_limiter.try_acquire(addr.ip()) else {
+            anyhow::bail!("Too many sessions from the same host or network: {}", addr);
+        };
+
+        // Hello の後に止まる相手に受け入れタスクを占有されないよう、ハンドシェイク全体に期限を設ける
+        tokio::time::timeout(self.option.handshake_timeout, self.handshake(stream, addr, peer_slot))
+            .await
+            .map_err(|_| anyhow::anyhow!("Handshake timed out: {}", addr))?
+    }
+
+    async fn handshake(&self, stream: FramedStream, addr: SocketAddr, peer_slot: PeerSlot) -> anyhow::Result<()> {
         let send_hello_message = HelloMessage { version: SessionVersion::V1 };
         stream.sender.lock().await.send_message(&send_hello_message).await?;
         let received_hello_message: HelloMessage = stream.receiver.lock().await.recv_message().await?;
@@ -182,12 +275,19 @@ impl Inner {
             stream.sender.lock().await.send_message(&send_challenge_message).await?;
             let receive_challenge_message: V1ChallengeMessage = stream.receiver.lock().await.recv_message().await?;
 
-            let send_signature = self.signer.sign(&receive_challenge_message.nonce)?;
+            let network_key = self.option.network_key.as_deref();
+            let send_signature = self
+                .signer
+                .sign(&V1ChallengeMessage::gen_signing_payload(&receive_challenge_message.nonce, network_key))?;
             let send_signature_message = V1SignatureMessage { cert: send_signature };
             stream.sender.lock().await.send_message(&send_signature_message).await?;
             let received_signature_message: V1SignatureMessage = stream.receiver.lock().await.recv_message().await?;
 
-            if received_signature_message.cert.verify(send_nonce.as_slice()).is_err() {
+            if received_signature_message
+                .cert
+                .verify(&V1ChallengeMessage::gen_signing_payload(&send_nonce, network_key))
+                .is_err()
+            {
                 anyhow::bail!("Invalid signature")
             }
 
@@ -208,6 +308,7 @@ impl Inner {
                     handshake_type: SessionHandshakeType::Accepted,
                     cert: received_signature_message.cert,
                     stream,
+                    peer_slot: Some(Arc::new(peer_slot)),
                 };
                 permit.send(session);
             } else {
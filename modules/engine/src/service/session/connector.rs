@@ -1,23 +1,44 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use anyhow::Context as _;
 use omnius_core_base::random_bytes::RandomBytesProvider;
-use omnius_core_omnikit::model::{OmniAddr, OmniSigner};
+use omnius_core_omnikit::model::{OmniAddr, OmniCert, OmniSigner};
 use parking_lot::Mutex;
 
 use crate::service::{
-    connection::{ConnectionTcpConnector, FramedRecvExt as _, FramedSendExt as _},
+    connection::{ConnectionTcpConnector, FramedRecvExt as _, FramedSendExt as _, FramedStream},
     session::message::{V1ChallengeMessage, V1SignatureMessage},
 };
 
 use super::{
     message::{HelloMessage, SessionVersion, V1RequestMessage, V1RequestType, V1ResultMessage, V1ResultType},
     model::{Session, SessionHandshakeType, SessionType},
+    DEFAULT_HANDSHAKE_TIMEOUT,
 };
 
 pub struct SessionConnector {
     tcp_connector: Arc<dyn ConnectionTcpConnector + Send + Sync>,
     signer: Arc<OmniSigner>,
     random_bytes_provider: Arc<Mutex<dyn RandomBytesProvider + Send + Sync>>,
+    option: SessionConnectorOption,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionConnectorOption {
+    // 接続先アドレス毎に期待する署名者の公開鍵
+    pub pinned_public_keys: HashMap<OmniAddr, Vec<u8>>,
+    pub network_key: Option<Vec<u8>>,
+    pub handshake_timeout: Duration,
+}
+
+impl Default for SessionConnectorOption {
+    fn default() -> Self {
+        Self {
+            pinned_public_keys: HashMap::new(),
+            network_key: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
 }
 
 impl SessionConnector {
@@ -25,17 +46,30 @@ impl SessionConnector {
         tcp_connector: Arc<dyn ConnectionTcpConnector + Send + Sync>,
         signer: Arc<OmniSigner>,
         random_bytes_provider: Arc<Mutex<dyn RandomBytesProvider + Send + Sync>>,
+        option: SessionConnectorOption,
     ) -> Self {
         Self {
             tcp_connector,
             signer,
             random_bytes_provider,
+            option,
         }
     }
 
     pub async fn connect(&self, addr: &OmniAddr, typ: &SessionType) -> anyhow::Result<Session> {
-        let stream = self.tcp_connector.connect(addr).await?;
+        let stream = self
+            .tcp_connector
+            .connect(addr)
+            .await
+            .with_context(|| format!("failed to open connection: {}", addr))?;
 
+        // 応答の途中で止まる相手に接続を占有されないよう、ハンドシェイク全体に期限を設ける
+        tokio::time::timeout(self.option.handshake_timeout, self.handshake(stream, addr, typ))
+            .await
+            .map_err(|_| anyhow::anyhow!("Handshake timed out: {}", addr))?
+    }
+
+    async fn handshake(&self, stream: FramedStream, addr: &OmniAddr, typ: &SessionType) -> anyhow::Result<Session> {
         let send_hello_message = HelloMessage { version: SessionVersion::V1 };
         stream.sender.lock().await.send_message(&send_hello_message).await?;
         let received_hello_message: HelloMessage = stream.receiver.lock().await.recv_message().await?;
@@ -53,14 +87,22 @@ impl SessionConnector {
             stream.sender.lock().await.send_message(&send_challenge_message).await?;
             let receive_challenge_message: V1ChallengeMessage = stream.receiver.lock().await.recv_message().await?;
 
-            let send_signature = self.signer.sign(&receive_challenge_message.nonce)?;
+            let network_key = self.option.network_key.as_deref();
+            let send_signature = self
+                .signer
+                .sign(&V1ChallengeMessage::gen_signing_payload(&receive_challenge_message.nonce, network_key))?;
             let send_signature_message = V1SignatureMessage { cert: send_signature };
             stream.sender.lock().await.send_message(&send_signature_message).await?;
             let received_signature_message: V1SignatureMessage = stream.receiver.lock().await.recv_message().await?;
 
-            if received_signature_message.cert.verify(send_nonce.as_slice()).is_err() {
+            if received_signature_message
+                .cert
+                .verify(&V1ChallengeMessage::gen_signing_payload(&send_nonce, network_key))
+                .is_err()
+            {
                 anyhow::bail!("Invalid signature")
             }
+            self.verify_pinned_cert(addr, &received_signature_message.cert)?;
 
             let send_session_request_message = V1RequestMessage {
                 request_type: match typ {
@@ -80,6 +122,7 @@ impl SessionConnector {
                 handshake_type: SessionHandshakeType::Connected,
                 cert: received_signature_message.cert,
                 stream,
+                peer_slot: None,
             };
 
             Ok(session)
@@ -87,4 +130,125 @@ impl SessionConnector {
             anyhow::bail!("Unsupported session version: {:?}", version)
         }
     }
+
+    fn verify_pinned_cert(&self, addr: &OmniAddr, cert: &OmniCert) -> anyhow::Result<()> {
+        let Some(public_key) = self.option.pinned_public_keys.get(addr) else {
+            return Ok(());
+        };
+        if cert.public_key != *public_key {
+            anyhow::bail!("Pinned certificate mismatch: {}", addr)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use parking_lot::Mutex;
+    use testresult::TestResult;
+
+    use omnius_core_base::random_bytes::RandomBytesProviderImpl;
+    use omnius_core_omnikit::model::{OmniAddr, OmniSignType, OmniSigner};
+
+    use crate::service::{
+        connection::{BandwidthLimiter, ConnectionTcpConnectorImpl, TcpProxyOption, TcpProxyType},
+        session::{message::V1ChallengeMessage, model::SessionType},
+    };
+
+    use super::{SessionConnector, SessionConnectorOption};
+
+    #[tokio::test]
+    pub async fn pinned_cert_test() -> TestResult {
+        let tcp_connector = Arc::new(
+            ConnectionTcpConnectorImpl::new(
+                TcpProxyOption {
+                    typ: TcpProxyType::None,
+                    addr: None,
+                    credential: None,
+                },
+                Arc::new(BandwidthLimiter::default()),
+            )
+            .await?,
+        );
+        let signer = Arc::new(OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "test")?);
+        let random_bytes_provider = Arc::new(Mutex::new(RandomBytesProviderImpl::new()));
+
+        let pinned_signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "pinned")?;
+        let other_signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "other")?;
+
+        let pinned_addr = OmniAddr::create_tcp("127.0.0.1".parse()?, 60000);
+        let unpinned_addr = OmniAddr::create_tcp("127.0.0.1".parse()?, 60001);
+
+        let option = SessionConnectorOption {
+            pinned_public_keys: HashMap::from([(pinned_addr.clone(), pinned_signer.sign(b"test")?.public_key)]),
+            ..Default::default()
+        };
+        let session_connector = SessionConnector::new(tcp_connector, signer, random_bytes_provider, option);
+
+        assert!(session_connector.verify_pinned_cert(&pinned_addr, &pinned_signer.sign(b"nonce")?).is_ok());
+        assert!(session_connector.verify_pinned_cert(&pinned_addr, &other_signer.sign(b"nonce")?).is_err());
+        assert!(session_connector
+            .verify_pinned_cert(&unpinned_addr, &other_signer.sign(b"nonce")?)
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn handshake_timeout_test() -> TestResult {
+        // 接続は受け入れるが、何も応答しない相手
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let local_addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let tcp_connector = Arc::new(
+            ConnectionTcpConnectorImpl::new(
+                TcpProxyOption {
+                    typ: TcpProxyType::None,
+                    addr: None,
+                    credential: None,
+                },
+                Arc::new(BandwidthLimiter::default()),
+            )
+            .await?,
+        );
+        let signer = Arc::new(OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "test")?);
+        let random_bytes_provider = Arc::new(Mutex::new(RandomBytesProviderImpl::new()));
+        let option = SessionConnectorOption {
+            handshake_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let session_connector = SessionConnector::new(tcp_connector, signer, random_bytes_provider, option);
+
+        let start = tokio::time::Instant::now();
+        let addr = OmniAddr::create_tcp(local_addr.ip(), local_addr.port());
+        assert!(session_connector.connect(&addr, &SessionType::NodeFinder).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        server.abort();
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn network_key_test() -> TestResult {
+        let signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "test")?;
+        let nonce = [0_u8; 32];
+
+        let cert = signer.sign(&V1ChallengeMessage::gen_signing_payload(&nonce, Some(b"network1".as_slice())))?;
+        assert!(cert
+            .verify(&V1ChallengeMessage::gen_signing_payload(&nonce, Some(b"network1".as_slice())))
+            .is_ok());
+        assert!(cert
+            .verify(&V1ChallengeMessage::gen_signing_payload(&nonce, Some(b"network2".as_slice())))
+            .is_err());
+        assert!(cert.verify(&V1ChallengeMessage::gen_signing_payload(&nonce, None)).is_err());
+
+        Ok(())
+    }
 }
@@ -2,9 +2,11 @@ use bitflags::bitflags;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
-use omnius_core_omnikit::model::OmniCert;
+use omnius_core_omnikit::model::{OmniCert, OmniHash, OmniHashAlgorithmType};
 use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
+use crate::limits::{self, MessageLimit};
+
 bitflags! {
     #[derive(Debug, PartialEq, Eq)]
     pub struct SessionVersion: u32 {
@@ -17,6 +19,10 @@ pub struct HelloMessage {
     pub version: SessionVersion,
 }
 
+impl MessageLimit for HelloMessage {
+    const MAX_LENGTH: usize = 1024;
+}
+
 impl RocketMessage for HelloMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
         writer.put_u32(value.version.bits());
@@ -39,6 +45,21 @@ pub struct V1ChallengeMessage {
     pub nonce: [u8; 32],
 }
 
+impl V1ChallengeMessage {
+    // ネットワーク鍵を署名対象に混ぜ、異なるプライベートネットワークのノード同士では署名検証が失敗するようにする
+    pub fn gen_signing_payload(nonce: &[u8; 32], network_key: Option<&[u8]>) -> Vec<u8> {
+        let mut res = nonce.to_vec();
+        if let Some(network_key) = network_key {
+            res.extend_from_slice(&OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, network_key).value);
+        }
+        res
+    }
+}
+
+impl MessageLimit for V1ChallengeMessage {
+    const MAX_LENGTH: usize = 1024;
+}
+
 impl RocketMessage for V1ChallengeMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
         writer.put_bytes(value.nonce.as_slice());
@@ -61,6 +82,10 @@ pub struct V1SignatureMessage {
     pub cert: OmniCert,
 }
 
+impl MessageLimit for V1SignatureMessage {
+    const MAX_LENGTH: usize = 8 * 1024;
+}
+
 impl RocketMessage for V1SignatureMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
         OmniCert::pack(writer, &value.cert, depth + 1)?;
@@ -72,6 +97,8 @@ impl RocketMessage for V1SignatureMessage {
     where
         Self: Sized,
     {
+        limits::check_depth(depth)?;
+
         let cert = OmniCert::unpack(reader, depth + 1)?;
 
         Ok(Self { cert })
@@ -89,6 +116,10 @@ pub struct V1RequestMessage {
     pub request_type: V1RequestType,
 }
 
+impl MessageLimit for V1RequestMessage {
+    const MAX_LENGTH: usize = 1024;
+}
+
 impl RocketMessage for V1RequestMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
         writer.put_u32(value.request_type.to_u32().ok_or_else(|| anyhow::anyhow!("invalid request_type"))?);
@@ -118,6 +149,10 @@ pub struct V1ResultMessage {
     pub result_type: V1ResultType,
 }
 
+impl MessageLimit for V1ResultMessage {
+    const MAX_LENGTH: usize = 1024;
+}
+
 impl RocketMessage for V1ResultMessage {
     fn pack(writer: &mut RocketMessageWriter, value: &Self, _depth: u32) -> anyhow::Result<()> {
         writer.put_u32(value.result_type.to_u32().ok_or_else(|| anyhow::anyhow!("invalid result_type"))?);
@@ -1,7 +1,11 @@
+use std::sync::Arc;
+
 use omnius_core_omnikit::model::{OmniAddr, OmniCert};
 
 use crate::service::connection::FramedStream;
 
+use super::PeerSlot;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum SessionType {
     NodeFinder,
@@ -20,4 +24,6 @@ pub struct Session {
     pub handshake_type: SessionHandshakeType,
     pub cert: OmniCert,
     pub stream: FramedStream,
+    // 受け入れたセッションでは、全ての複製が破棄されるまで PeerLimiter の枠を占有する
+    pub peer_slot: Option<Arc<PeerSlot>>,
 }
@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use omnius_core_base::net::Reachable as _;
+
+// 1 つのホストやネットワークが受け入れ枠を占有しないよう、同時に受け入れるセッション数を制限する
+// IPv4 は /24、IPv6 は /48 を同じネットワークとみなす
+// 同じホストや LAN 内で複数のノードを動かせるよう、exempt_lan の場合はループバックやプライベートアドレスを制限しない
+pub struct PeerLimiter {
+    max_sessions_per_ip: usize,
+    max_sessions_per_subnet: usize,
+    exempt_lan: bool,
+    counts: Mutex<HashMap<PeerKey, usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PeerKey {
+    Ip(IpAddr),
+    Subnet(IpAddr),
+}
+
+impl PeerLimiter {
+    pub fn new(max_sessions_per_ip: usize, max_sessions_per_subnet: usize, exempt_lan: bool) -> Self {
+        Self {
+            max_sessions_per_ip,
+            max_sessions_per_subnet,
+            exempt_lan,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 上限に達している場合は None を返す。返された PeerSlot を破棄すると枠が空く
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<PeerSlot> {
+        let ip = canonical_ip(ip);
+        if self.exempt_lan && is_lan(&ip) {
+            return Some(PeerSlot {
+                limiter: self.clone(),
+                keys: vec![],
+            });
+        }
+        let keys = vec![PeerKey::Ip(ip), PeerKey::Subnet(subnet_of(ip))];
+
+        let mut counts = self.counts.lock();
+        let ip_count = counts.get(&keys[0]).copied().unwrap_or(0);
+        let subnet_count = counts.get(&keys[1]).copied().unwrap_or(0);
+        if ip_count >= self.max_sessions_per_ip || subnet_count >= self.max_sessions_per_subnet {
+            return None;
+        }
+        for key in keys.iter() {
+            *counts.entry(*key).or_default() += 1;
+        }
+
+        Some(PeerSlot { limiter: self.clone(), keys })
+    }
+
+    pub fn count(&self, ip: IpAddr) -> usize {
+        self.counts.lock().get(&PeerKey::Ip(canonical_ip(ip))).copied().unwrap_or(0)
+    }
+
+    fn release(&self, keys: &[PeerKey]) {
+        let mut counts = self.counts.lock();
+        for key in keys.iter() {
+            if let Some(count) = counts.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(key);
+                }
+            }
+        }
+    }
+}
+
+pub struct PeerSlot {
+    limiter: Arc<PeerLimiter>,
+    keys: Vec<PeerKey>,
+}
+
+impl Drop for PeerSlot {
+    fn drop(&mut self) {
+        self.limiter.release(&self.keys);
+    }
+}
+
+// IPv4 射影アドレスで接続されても、同じホストとして数える
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip6) => ip6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+fn is_lan(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !ip.is_reachable(),
+        IpAddr::V6(ip) => !ip.is_reachable(),
+    }
+}
+
+fn subnet_of(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip4) => {
+            let [a, b, c, _] = ip4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip6) => {
+            let s = ip6.segments();
+            IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::PeerLimiter;
+
+    #[test]
+    pub fn simple_test() {
+        let limiter = Arc::new(PeerLimiter::new(2, 3, false));
+
+        // 同じアドレスからは 2 つまで
+        let s1 = limiter.try_acquire("192.0.2.1".parse().unwrap());
+        let s2 = limiter.try_acquire("::ffff:192.0.2.1".parse().unwrap());
+        assert!(s1.is_some() && s2.is_some());
+        assert!(limiter.try_acquire("192.0.2.1".parse().unwrap()).is_none());
+        assert_eq!(limiter.count("192.0.2.1".parse().unwrap()), 2);
+
+        // 同じ /24 からは 3 つまで
+        let s3 = limiter.try_acquire("192.0.2.2".parse().unwrap());
+        assert!(s3.is_some());
+        assert!(limiter.try_acquire("192.0.2.3".parse().unwrap()).is_none());
+        assert!(limiter.try_acquire("198.51.100.1".parse().unwrap()).is_some());
+
+        // 破棄すると枠が空く
+        drop(s1);
+        assert_eq!(limiter.count("192.0.2.1".parse().unwrap()), 1);
+        assert!(limiter.try_acquire("192.0.2.3".parse().unwrap()).is_some());
+
+        // IPv6 は /48 でまとめる
+        let _s4 = limiter.try_acquire("2001:db8:1::1".parse().unwrap());
+        let _s5 = limiter.try_acquire("2001:db8:1:ffff::1".parse().unwrap());
+        let _s6 = limiter.try_acquire("2001:db8:1:1::1".parse().unwrap());
+        assert!(limiter.try_acquire("2001:db8:1:2::1".parse().unwrap()).is_none());
+        assert!(limiter.try_acquire("2001:db8:2::1".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    pub fn exempt_lan_test() {
+        let limiter = Arc::new(PeerLimiter::new(1, 1, true));
+
+        // ループバックやプライベートアドレスは数えない
+        let slots: Vec<_> = ["127.0.0.1", "127.0.0.1", "192.168.0.1", "192.168.0.2", "::1", "::1"]
+            .into_iter()
+            .map(|n| limiter.try_acquire(n.parse().unwrap()))
+            .collect();
+        assert!(slots.iter().all(|n| n.is_some()));
+        assert_eq!(limiter.count("127.0.0.1".parse().unwrap()), 0);
+
+        let limiter = Arc::new(PeerLimiter::new(1, 1, false));
+        let _s1 = limiter.try_acquire("127.0.0.1".parse().unwrap());
+        assert!(limiter.try_acquire("127.0.0.1".parse().unwrap()).is_none());
+    }
+}
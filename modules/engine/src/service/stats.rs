@@ -0,0 +1,5 @@
+mod stats_repo;
+mod task_stats_recorder;
+
+pub use stats_repo::*;
+pub use task_stats_recorder::*;
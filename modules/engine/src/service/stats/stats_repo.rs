@@ -0,0 +1,216 @@
+use std::{path::Path, sync::Arc};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::migrate::MigrateDatabase;
+use sqlx::{sqlite::SqlitePool, Sqlite};
+
+use crate::service::util::{MigrationRequest, SqliteBackup, SqliteMigrator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsResolution {
+    Raw,
+    Hourly,
+}
+
+impl StatsResolution {
+    fn code(&self) -> i64 {
+        match self {
+            StatsResolution::Raw => 0,
+            StatsResolution::Hourly => 1,
+        }
+    }
+
+    fn truncate(&self, time: &DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            StatsResolution::Raw => time.naive_utc(),
+            StatsResolution::Hourly => {
+                let secs = time.timestamp();
+                DateTime::from_timestamp(secs - secs.rem_euclid(3600), 0).unwrap_or(*time).naive_utc()
+            }
+        }
+    }
+}
+
+// 転送量は前回の標本からの差分、セッション数とストレージ使用量はその時点の値
+// 1 時間毎にまとめたものでは、転送量は合計、それ以外は最大値になる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsSample {
+    pub time: DateTime<Utc>,
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub session_count: u64,
+    pub storage_used_bytes: u64,
+}
+
+pub struct StatsRepo {
+    db: Arc<SqlitePool>,
+}
+
+impl StatsRepo {
+    pub async fn new(dir_path: &str) -> anyhow::Result<Self> {
+        let path = Path::new(dir_path).join("sqlite.db");
+        let path = path.to_str().ok_or(anyhow::anyhow!("Invalid path"))?;
+        let url = format!("sqlite:{}", path);
+
+        if !Sqlite::database_exists(url.as_str()).await.unwrap_or(false) {
+            Sqlite::create_database(url.as_str()).await?;
+        }
+
+        let db = Arc::new(SqlitePool::connect(&url).await?);
+        let res = Self { db };
+
+        res.migrate().await?;
+
+        Ok(res)
+    }
+
+    async fn migrate(&self) -> anyhow::Result<()> {
+        let migrator = SqliteMigrator::new(self.db.clone());
+
+        let requests = vec![MigrationRequest {
+            name: "2024-10-01_init".to_string(),
+            queries: r#"
+CREATE TABLE IF NOT EXISTS stats_samples (
+    resolution INTEGER NOT NULL,
+    time TIMESTAMP NOT NULL,
+    uploaded_bytes INTEGER NOT NULL,
+    downloaded_bytes INTEGER NOT NULL,
+    session_count INTEGER NOT NULL,
+    storage_used_bytes INTEGER NOT NULL,
+    PRIMARY KEY (resolution, time)
+);
+"#
+            .to_string(),
+        }];
+
+        migrator.migrate(requests).await?;
+
+        Ok(())
+    }
+
+    pub async fn backup_to(&self, path: &Path) -> anyhow::Result<()> {
+        SqliteBackup::backup(self.db.as_ref(), path).await
+    }
+
+    // 標本を記録し、同時に 1 時間毎の集計へ加える
+    pub async fn insert(&self, sample: &StatsSample) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        for resolution in [StatsResolution::Raw, StatsResolution::Hourly] {
+            sqlx::query(
+                r#"
+INSERT INTO stats_samples (resolution, time, uploaded_bytes, downloaded_bytes, session_count, storage_used_bytes)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+ON CONFLICT(resolution, time) DO UPDATE SET
+    uploaded_bytes = uploaded_bytes + excluded.uploaded_bytes,
+    downloaded_bytes = downloaded_bytes + excluded.downloaded_bytes,
+    session_count = MAX(session_count, excluded.session_count),
+    storage_used_bytes = MAX(storage_used_bytes, excluded.storage_used_bytes)
+"#,
+            )
+            .bind(resolution.code())
+            .bind(resolution.truncate(&sample.time))
+            .bind(i64::try_from(sample.uploaded_bytes)?)
+            .bind(i64::try_from(sample.downloaded_bytes)?)
+            .bind(i64::try_from(sample.session_count)?)
+            .bind(i64::try_from(sample.storage_used_bytes)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // from 以上 to 未満の範囲を、古い順に返す
+    pub async fn get_samples(&self, resolution: StatsResolution, from: &DateTime<Utc>, to: &DateTime<Utc>) -> anyhow::Result<Vec<StatsSample>> {
+        let rows: Vec<(NaiveDateTime, i64, i64, i64, i64)> = sqlx::query_as(
+            r#"
+SELECT time, uploaded_bytes, downloaded_bytes, session_count, storage_used_bytes FROM stats_samples
+WHERE resolution = ?1 AND time >= ?2 AND time < ?3
+ORDER BY time ASC
+"#,
+        )
+        .bind(resolution.code())
+        .bind(from.naive_utc())
+        .bind(to.naive_utc())
+        .fetch_all(self.db.as_ref())
+        .await?;
+
+        let res = rows
+            .into_iter()
+            .map(
+                |(time, uploaded_bytes, downloaded_bytes, session_count, storage_used_bytes)| StatsSample {
+                    time: time.and_utc(),
+                    uploaded_bytes: uploaded_bytes as u64,
+                    downloaded_bytes: downloaded_bytes as u64,
+                    session_count: session_count as u64,
+                    storage_used_bytes: storage_used_bytes as u64,
+                },
+            )
+            .collect();
+        Ok(res)
+    }
+
+    // before より古いものを削除する
+    pub async fn prune(&self, resolution: StatsResolution, before: &DateTime<Utc>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+DELETE FROM stats_samples
+WHERE resolution = ?1 AND time < ?2
+"#,
+        )
+        .bind(resolution.code())
+        .bind(before.naive_utc())
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use testresult::TestResult;
+
+    use super::{StatsRepo, StatsResolution, StatsSample};
+
+    #[tokio::test]
+    pub async fn simple_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let repo = StatsRepo::new(path).await?;
+
+        let start: DateTime<Utc> = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z")?.into();
+        for i in 0..90 {
+            repo.insert(&StatsSample {
+                time: start + Duration::minutes(i),
+                uploaded_bytes: 10,
+                downloaded_bytes: 20,
+                session_count: (i % 7) as u64,
+                storage_used_bytes: 1000 + i as u64,
+            })
+            .await?;
+        }
+
+        let end = start + Duration::days(1);
+        let raw = repo.get_samples(StatsResolution::Raw, &start, &end).await?;
+        assert_eq!(raw.len(), 90);
+        assert_eq!(raw[0].time, start);
+
+        // 1 時間毎の集計では、転送量は合計、それ以外は最大値になる
+        let hourly = repo.get_samples(StatsResolution::Hourly, &start, &end).await?;
+        assert_eq!(hourly.len(), 2);
+        assert_eq!(hourly[0].uploaded_bytes, 600);
+        assert_eq!(hourly[0].session_count, 6);
+        assert_eq!(hourly[0].storage_used_bytes, 1059);
+        assert_eq!(hourly[1].time, start + Duration::hours(1));
+        assert_eq!(hourly[1].downloaded_bytes, 600);
+
+        repo.prune(StatsResolution::Raw, &(start + Duration::hours(1))).await?;
+        assert_eq!(repo.get_samples(StatsResolution::Raw, &start, &end).await?.len(), 30);
+        assert_eq!(repo.get_samples(StatsResolution::Hourly, &start, &end).await?.len(), 2);
+
+        Ok(())
+    }
+}
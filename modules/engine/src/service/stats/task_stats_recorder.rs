@@ -0,0 +1,211 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use parking_lot::Mutex;
+use tokio::{sync::Mutex as TokioMutex, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use omnius_core_base::{clock::Clock, sleeper::Sleeper, terminable::Terminable};
+
+use crate::service::{
+    connection::{BandwidthLimiter, TrafficTotals},
+    util::{shutdown_task, sleep_or_cancelled, FnExecutor, FnHub, FnRegistrar, TASK_SHUTDOWN_GRACE_PERIOD},
+};
+
+use super::{StatsRepo, StatsResolution, StatsSample};
+
+#[derive(Debug, Clone)]
+pub struct StatsRecorderOption {
+    pub sample_interval: Duration,
+    pub raw_retention: Duration,
+    pub hourly_retention: Duration,
+}
+
+impl Default for StatsRecorderOption {
+    fn default() -> Self {
+        Self {
+            sample_interval: Duration::from_secs(60),
+            raw_retention: Duration::from_secs(60 * 60 * 24),
+            hourly_retention: Duration::from_secs(60 * 60 * 24 * 90),
+        }
+    }
+}
+
+// 転送量・セッション数・ストレージ使用量を定期的に記録し、保持期間を過ぎたものを消す
+// セッション数とストレージ使用量は、登録された関数の合計を使う
+#[derive(Clone)]
+pub struct TaskStatsRecorder {
+    inner: Inner,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
+    get_session_count_fn: Arc<FnHub<usize, ()>>,
+    get_storage_used_bytes_fn: Arc<FnHub<u64, ()>>,
+}
+
+impl TaskStatsRecorder {
+    pub fn new(
+        stats_repo: Arc<StatsRepo>,
+        bandwidth_limiter: Arc<BandwidthLimiter>,
+        clock: Arc<dyn Clock<Utc> + Send + Sync>,
+        sleeper: Arc<dyn Sleeper + Send + Sync>,
+        option: StatsRecorderOption,
+    ) -> Self {
+        let get_session_count_fn = Arc::new(FnHub::new());
+        let get_storage_used_bytes_fn = Arc::new(FnHub::new());
+        let inner = Inner {
+            stats_repo,
+            last_traffic: Arc::new(Mutex::new(bandwidth_limiter.traffic())),
+            bandwidth_limiter,
+            get_session_count_fn: get_session_count_fn.executor(),
+            get_storage_used_bytes_fn: get_storage_used_bytes_fn.executor(),
+            clock,
+            option,
+        };
+        Self {
+            inner,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+            get_session_count_fn,
+            get_storage_used_bytes_fn,
+        }
+    }
+
+    pub async fn run(&self) {
+        let sleeper = self.sleeper.clone();
+        let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                if !sleep_or_cancelled(sleeper.as_ref(), inner.option.sample_interval, &cancellation_token).await {
+                    return;
+                }
+                if let Err(e) = inner.record().await {
+                    warn!(error_message = e.to_string(), "stats record failed");
+                }
+            }
+        });
+        *self.join_handle.lock().await = Some(join_handle);
+    }
+
+    // 返された FnHandle を保持している間、標本を取る度に呼ばれる
+    pub fn on_get_session_count(&self) -> FnRegistrar<usize, ()> {
+        self.get_session_count_fn.registrar()
+    }
+
+    pub fn on_get_storage_used_bytes(&self) -> FnRegistrar<u64, ()> {
+        self.get_storage_used_bytes_fn.registrar()
+    }
+}
+
+#[async_trait]
+impl Terminable for TaskStatsRecorder {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        if let Some(join_handle) = self.join_handle.lock().await.take() {
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Inner {
+    stats_repo: Arc<StatsRepo>,
+    bandwidth_limiter: Arc<BandwidthLimiter>,
+    last_traffic: Arc<Mutex<TrafficTotals>>,
+    get_session_count_fn: FnExecutor<usize, ()>,
+    get_storage_used_bytes_fn: FnExecutor<u64, ()>,
+    clock: Arc<dyn Clock<Utc> + Send + Sync>,
+    option: StatsRecorderOption,
+}
+
+impl Inner {
+    async fn record(&self) -> anyhow::Result<()> {
+        let now = self.clock.now();
+
+        let traffic = self.bandwidth_limiter.traffic();
+        let last_traffic = std::mem::replace(&mut *self.last_traffic.lock(), traffic);
+
+        let sample = StatsSample {
+            time: now,
+            uploaded_bytes: traffic.uploaded_bytes.saturating_sub(last_traffic.uploaded_bytes),
+            downloaded_bytes: traffic.downloaded_bytes.saturating_sub(last_traffic.downloaded_bytes),
+            session_count: self.get_session_count_fn.execute(&()).into_iter().sum::<usize>() as u64,
+            storage_used_bytes: self.get_storage_used_bytes_fn.execute(&()).into_iter().sum(),
+        };
+        self.stats_repo.insert(&sample).await?;
+
+        for (resolution, retention) in [
+            (StatsResolution::Raw, self.option.raw_retention),
+            (StatsResolution::Hourly, self.option.hourly_retention),
+        ] {
+            let before = now - chrono::Duration::from_std(retention)?;
+            self.stats_repo.prune(resolution, &before).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration, Utc};
+    use testresult::TestResult;
+    use tokio::io::AsyncWriteExt as _;
+
+    use crate::service::{
+        connection::BandwidthLimiter,
+        stats::{StatsRepo, StatsResolution},
+        util::{ManualClock, ManualSleeper},
+    };
+
+    use super::{StatsRecorderOption, TaskStatsRecorder};
+
+    #[tokio::test]
+    pub async fn record_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let stats_repo = Arc::new(StatsRepo::new(dir.path().as_os_str().to_str().unwrap()).await?);
+        let bandwidth_limiter = Arc::new(BandwidthLimiter::default());
+        let start: DateTime<Utc> = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z")?.into();
+        let clock = Arc::new(ManualClock::new(start));
+
+        let recorder = TaskStatsRecorder::new(
+            stats_repo.clone(),
+            bandwidth_limiter.clone(),
+            clock.clone(),
+            Arc::new(ManualSleeper::new(clock.clone())),
+            StatsRecorderOption::default(),
+        );
+        let _session_count = recorder.on_get_session_count().register(|_| 3);
+        let _storage_used_bytes = recorder.on_get_storage_used_bytes().register(|_| 1024);
+
+        let (client, _server) = tokio::io::duplex(64 * 1024);
+        let mut client = bandwidth_limiter.wrap(client, None);
+        client.write_all(&[0; 100]).await?;
+
+        recorder.inner.record().await?;
+        clock.advance(Duration::minutes(1));
+        recorder.inner.record().await?;
+
+        // 転送量は前回からの差分として記録される
+        let samples = stats_repo.get_samples(StatsResolution::Raw, &start, &(start + Duration::days(1))).await?;
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].uploaded_bytes, 100);
+        assert_eq!(samples[1].uploaded_bytes, 0);
+        let hourly = stats_repo
+            .get_samples(StatsResolution::Hourly, &start, &(start + Duration::days(1)))
+            .await?;
+        assert_eq!(hourly[0].uploaded_bytes, 100);
+        assert_eq!(hourly[0].session_count, 3);
+        assert_eq!(hourly[0].storage_used_bytes, 1024);
+
+        Ok(())
+    }
+}
@@ -1,3 +1,9 @@
-mod blob;
+mod blob;
+mod cache;
+mod disk_space;
+mod executor;
 
-pub use blob::*;
+pub use blob::*;
+pub use cache::*;
+pub use disk_space::*;
+pub use executor::*;
@@ -1,45 +1,721 @@
 // https://rocksdb.org/blog/2021/05/26/integrated-blob-db.html
 
-use std::path::Path;
+use std::{
+    ffi::OsString,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt as _,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt as _};
+use tokio_util::{bytes::Bytes, io::StreamReader};
+use tracing::warn;
+
+use super::{BlockCache, BlockCacheStatus, StorageExecutor, StorageExecutorStatus};
+
+const CHUNKS_CF_NAME: &str = "chunks";
+const METAS_CF_NAME: &str = "metas";
+const ROOT_HASH_INDEX_CF_NAME: &str = "metas_by_root_hash";
+const LAST_ACCESSED_INDEX_CF_NAME: &str = "metas_by_last_accessed";
+const CHUNK_SIZE: usize = 1024 * 1024;
+const SCAN_BATCH_SIZE: usize = 1024;
+const SHRINK_BATCH_SIZE: usize = 1024;
 
 #[allow(dead_code)]
 pub struct BlobStorage {
-    rocksdb: rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>,
+    rocksdb: Arc<rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>>,
+    meta_lock: Mutex<()>,
+    executor: Arc<StorageExecutor>,
+    value_cache: Arc<BlockCache>,
+    secondary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlobStorageOption {
+    pub write_buffer_size: usize,
+    pub max_background_jobs: i32,
+    pub blob_gc_age_cutoff: f64,
+    pub blob_gc_force_threshold: f64,
+    pub block_cache_size: usize,
+    pub thread_count: usize,
+    pub value_cache_size: usize,
+    // 指定した場合は、他のプロセスが書き込むストレージを読み取り専用で開く
+    // ここには読み取り側が自身の情報ログを置くディレクトリを指定する
+    pub secondary_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobMeta {
+    // このブロックを参照している root_hash。要素数が参照数になる
+    pub root_hashes: Vec<Vec<u8>>,
+    pub last_accessed_at: DateTime<Utc>,
+}
+
+impl BlobMeta {
+    pub fn ref_count(&self) -> u32 {
+        self.root_hashes.len() as u32
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShrinkProgress {
+    pub scanned_count: u64,
+    pub deleted_count: u64,
+}
+
+impl Default for BlobStorageOption {
+    fn default() -> Self {
+        Self {
+            write_buffer_size: 64 * 1024 * 1024,
+            max_background_jobs: 2,
+            blob_gc_age_cutoff: 0.25,
+            blob_gc_force_threshold: 1.0,
+            block_cache_size: 32 * 1024 * 1024,
+            thread_count: 4,
+            value_cache_size: 64 * 1024 * 1024,
+            secondary_path: None,
+        }
+    }
 }
 
 #[allow(dead_code)]
 impl BlobStorage {
-    pub fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+    pub fn new<P: AsRef<Path>>(path: P, option: BlobStorageOption) -> anyhow::Result<Self> {
+        let cache = rocksdb::Cache::new_lru_cache(option.block_cache_size);
+        let mut block_opts = rocksdb::BlockBasedOptions::default();
+        block_opts.set_block_cache(&cache);
+
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
+        opts.set_write_buffer_size(option.write_buffer_size);
+        opts.set_max_background_jobs(option.max_background_jobs);
+        opts.set_block_based_table_factory(&block_opts);
         opts.set_blob_compression_type(rocksdb::DBCompressionType::None);
         opts.set_enable_blob_files(true);
         opts.set_enable_blob_gc(true);
-        let db = rocksdb::DBWithThreadMode::<rocksdb::MultiThreaded>::open(&opts, path)?;
-        Ok(Self { rocksdb: db })
+        opts.set_blob_gc_age_cutoff(option.blob_gc_age_cutoff);
+        opts.set_blob_gc_force_threshold(option.blob_gc_force_threshold);
+        let cfs = [CHUNKS_CF_NAME, METAS_CF_NAME, ROOT_HASH_INDEX_CF_NAME, LAST_ACCESSED_INDEX_CF_NAME]
+            .into_iter()
+            .map(|name| rocksdb::ColumnFamilyDescriptor::new(name, opts.clone()))
+            .collect::<Vec<_>>();
+        let db = match option.secondary_path.as_ref() {
+            Some(secondary_path) => {
+                // 読み取り側は書き込み側のファイルを全て開いたままにする必要がある
+                opts.set_max_open_files(-1);
+                rocksdb::DBWithThreadMode::<rocksdb::MultiThreaded>::open_cf_descriptors_as_secondary(
+                    &opts,
+                    path.as_ref(),
+                    secondary_path.as_path(),
+                    cfs,
+                )?
+            }
+            None => rocksdb::DBWithThreadMode::<rocksdb::MultiThreaded>::open_cf_descriptors(&opts, path, cfs)?,
+        };
+        Ok(Self {
+            rocksdb: Arc::new(db),
+            meta_lock: Mutex::new(()),
+            executor: Arc::new(StorageExecutor::new(option.thread_count)?),
+            value_cache: Arc::new(BlockCache::new(option.value_cache_size)),
+            secondary: option.secondary_path.is_some(),
+        })
+    }
+
+    pub fn is_secondary(&self) -> bool {
+        self.secondary
+    }
+
+    // 読み取り専用で開いている場合に、書き込み側の変更を取り込む
+    // 書き込み側で削除や上書きがあった可能性があるので、キャッシュは捨てる
+    pub fn catch_up(&self) -> anyhow::Result<()> {
+        if !self.secondary {
+            return Ok(());
+        }
+        self.rocksdb.try_catch_up_with_primary()?;
+        self.value_cache.remove_prefix(&[]);
+        Ok(())
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
         self.rocksdb.put(key, value)?;
+        self.value_cache.remove(key);
         Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
-        let value = self.rocksdb.get(key)?;
+    // RocksDB から受け取った Vec をそのまま Bytes にするので、呼び出し側はコピーせずにメッセージへ渡せる
+    pub fn get(&self, key: &[u8]) -> anyhow::Result<Option<Bytes>> {
+        if let Some(value) = self.value_cache.get(key) {
+            return Ok(Some(value));
+        }
+        let generation = self.value_cache.generation();
+        let value = self.rocksdb.get(key)?.map(Bytes::from);
+        if let Some(value) = value.as_ref() {
+            self.value_cache.insert(key, value.clone(), generation);
+        }
+        Ok(value)
+    }
+
+    // 専用のスレッドプールで実行する
+    pub async fn get_async(&self, key: &[u8]) -> anyhow::Result<Option<Bytes>> {
+        if let Some(value) = self.value_cache.get(key) {
+            return Ok(Some(value));
+        }
+        let generation = self.value_cache.generation();
+        let rocksdb = self.rocksdb.clone();
+        let k = key.to_vec();
+        let value = self.executor.run(move || rocksdb.get(k)).await??.map(Bytes::from);
+        if let Some(value) = value.as_ref() {
+            self.value_cache.insert(key, value.clone(), generation);
+        }
         Ok(value)
     }
 
+    pub async fn put_async(&self, key: &[u8], value: Bytes) -> anyhow::Result<()> {
+        let rocksdb = self.rocksdb.clone();
+        let k = key.to_vec();
+        self.executor.run(move || rocksdb.put(k, value)).await??;
+        self.value_cache.remove(key);
+        Ok(())
+    }
+
+    pub fn executor_status(&self) -> StorageExecutorStatus {
+        self.executor.status()
+    }
+
+    pub fn cache_status(&self) -> BlockCacheStatus {
+        self.value_cache.status()
+    }
+
+    // キャッシュに無いものだけを、専用のスレッドプールでまとめて読む
+    pub async fn multi_get(&self, keys: &[&[u8]]) -> anyhow::Result<Vec<Option<Bytes>>> {
+        let mut res: Vec<Option<Bytes>> = keys.iter().map(|key| self.value_cache.get(key)).collect();
+        let missing: Vec<usize> = (0..keys.len()).filter(|&i| res[i].is_none()).collect();
+        if missing.is_empty() {
+            return Ok(res);
+        }
+
+        let generation = self.value_cache.generation();
+        let rocksdb = self.rocksdb.clone();
+        let missing_keys: Vec<Vec<u8>> = missing.iter().map(|&i| keys[i].to_vec()).collect();
+        let values = self
+            .executor
+            .run(move || rocksdb.multi_get(missing_keys).into_iter().collect::<Result<Vec<_>, _>>())
+            .await??;
+
+        for (i, value) in missing.into_iter().zip(values) {
+            let value = value.map(Bytes::from);
+            if let Some(value) = value.as_ref() {
+                self.value_cache.insert(keys[i], value.clone(), generation);
+            }
+            res[i] = value;
+        }
+        Ok(res)
+    }
+
+    // 1 つの WriteBatch にまとめ、専用のスレッドプールで書き込む
+    pub async fn multi_put(&self, items: &[(&[u8], &[u8])]) -> anyhow::Result<()> {
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, value) in items {
+            batch.put(key, value);
+        }
+        let rocksdb = self.rocksdb.clone();
+        self.executor.run(move || rocksdb.write(batch)).await??;
+        for (key, _) in items {
+            self.value_cache.remove(key);
+        }
+        Ok(())
+    }
+
     pub fn delete(&self, key: &[u8]) -> anyhow::Result<()> {
-        self.rocksdb.delete(key)?;
+        let _guard = self.meta_lock.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.delete(key);
+        self.delete_meta_in_batch(&mut batch, key)?;
+        self.rocksdb.write(batch)?;
+        self.value_cache.remove(key);
+        Ok(())
+    }
+
+    pub fn put_meta(&self, key: &[u8], meta: &BlobMeta) -> anyhow::Result<()> {
+        let _guard = self.meta_lock.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+        self.put_meta_in_batch(&mut batch, key, meta)?;
+        self.rocksdb.write(batch)?;
+        Ok(())
+    }
+
+    pub fn get_meta(&self, key: &[u8]) -> anyhow::Result<Option<BlobMeta>> {
+        let Some(value) = self.rocksdb.get_cf(&self.cf(METAS_CF_NAME)?, key)? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    pub fn delete_meta(&self, key: &[u8]) -> anyhow::Result<()> {
+        let _guard = self.meta_lock.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+        self.delete_meta_in_batch(&mut batch, key)?;
+        self.rocksdb.write(batch)?;
         Ok(())
     }
 
-    pub fn keys(&self) -> anyhow::Result<BlobStorageKeyIterator> {
-        let mut iter = self.rocksdb.raw_iterator();
+    pub fn touch(&self, key: &[u8], now: DateTime<Utc>) -> anyhow::Result<()> {
+        let _guard = self.meta_lock.lock();
+        let Some(mut meta) = self.get_meta(key)? else {
+            return Ok(());
+        };
+        meta.last_accessed_at = now;
+
+        let mut batch = rocksdb::WriteBatch::default();
+        self.put_meta_in_batch(&mut batch, key, &meta)?;
+        self.rocksdb.write(batch)?;
+        Ok(())
+    }
+
+    // 同じ root_hash からの参照は 1 つと数えるので、公開をやり直しても参照数は増えない
+    pub fn add_ref(&self, key: &[u8], root_hash: &[u8], now: DateTime<Utc>) -> anyhow::Result<u32> {
+        let _guard = self.meta_lock.lock();
+        let mut meta = self.get_meta(key)?.unwrap_or_else(|| BlobMeta {
+            root_hashes: Vec::new(),
+            last_accessed_at: now,
+        });
+        if !meta.root_hashes.iter().any(|n| n.as_slice() == root_hash) {
+            meta.root_hashes.push(root_hash.to_vec());
+        }
+
+        let mut batch = rocksdb::WriteBatch::default();
+        self.put_meta_in_batch(&mut batch, key, &meta)?;
+        self.rocksdb.write(batch)?;
+        Ok(meta.ref_count())
+    }
+
+    // 参照が無くなった時点で値とメタデータを同じバッチで削除する
+    pub fn remove_ref(&self, key: &[u8], root_hash: &[u8]) -> anyhow::Result<u32> {
+        let _guard = self.meta_lock.lock();
+        let Some(mut meta) = self.get_meta(key)? else {
+            return Ok(0);
+        };
+        meta.root_hashes.retain(|n| n.as_slice() != root_hash);
+
+        let mut batch = rocksdb::WriteBatch::default();
+        if meta.root_hashes.is_empty() {
+            batch.delete(key);
+            self.delete_meta_in_batch(&mut batch, key)?;
+        } else {
+            self.put_meta_in_batch(&mut batch, key, &meta)?;
+        }
+        self.rocksdb.write(batch)?;
+        if meta.root_hashes.is_empty() {
+            self.value_cache.remove(key);
+        }
+        Ok(meta.ref_count())
+    }
+
+    pub fn keys_by_root_hash(&self, root_hash: &[u8]) -> anyhow::Result<Vec<Box<[u8]>>> {
+        let prefix = Self::gen_root_hash_index_key(root_hash, &[]);
+
+        let cf = self.cf(ROOT_HASH_INDEX_CF_NAME)?;
+        let mut iter = self.rocksdb.raw_iterator_cf(&cf);
+        iter.seek(&prefix);
+
+        let mut res = Vec::new();
+        while let Some(index_key) = iter.key() {
+            let Some(key) = index_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            res.push(Box::from(key));
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(res)
+    }
+
+    pub fn least_recently_used_keys(&self, limit: usize) -> anyhow::Result<Vec<Box<[u8]>>> {
+        let cf = self.cf(LAST_ACCESSED_INDEX_CF_NAME)?;
+        let mut iter = self.rocksdb.raw_iterator_cf(&cf);
         iter.seek_to_first();
-        let iter = BlobStorageKeyIterator::new(iter);
-        Ok(iter)
+
+        let mut res = Vec::new();
+        while let Some(index_key) = iter.key() {
+            if res.len() >= limit {
+                break;
+            }
+            res.push(Box::from(&index_key[8..]));
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(res)
+    }
+
+    fn put_meta_in_batch(&self, batch: &mut rocksdb::WriteBatch, key: &[u8], meta: &BlobMeta) -> anyhow::Result<()> {
+        self.delete_meta_in_batch(batch, key)?;
+        batch.put_cf(&self.cf(METAS_CF_NAME)?, key, serde_json::to_vec(meta)?);
+        for root_hash in meta.root_hashes.iter() {
+            batch.put_cf(&self.cf(ROOT_HASH_INDEX_CF_NAME)?, Self::gen_root_hash_index_key(root_hash, key), b"");
+        }
+        batch.put_cf(
+            &self.cf(LAST_ACCESSED_INDEX_CF_NAME)?,
+            Self::gen_last_accessed_index_key(&meta.last_accessed_at, key),
+            b"",
+        );
+        Ok(())
+    }
+
+    // メタデータと索引は同じバッチで消し、不整合な索引を残さない
+    fn delete_meta_in_batch(&self, batch: &mut rocksdb::WriteBatch, key: &[u8]) -> anyhow::Result<()> {
+        let Some(meta) = self.get_meta(key)? else {
+            return Ok(());
+        };
+        batch.delete_cf(&self.cf(METAS_CF_NAME)?, key);
+        for root_hash in meta.root_hashes.iter() {
+            batch.delete_cf(&self.cf(ROOT_HASH_INDEX_CF_NAME)?, Self::gen_root_hash_index_key(root_hash, key));
+        }
+        batch.delete_cf(
+            &self.cf(LAST_ACCESSED_INDEX_CF_NAME)?,
+            Self::gen_last_accessed_index_key(&meta.last_accessed_at, key),
+        );
+        Ok(())
+    }
+
+    // <root_hash_len: u32 BE><root_hash><key>
+    fn gen_root_hash_index_key(root_hash: &[u8], key: &[u8]) -> Vec<u8> {
+        let mut res = Vec::with_capacity(4 + root_hash.len() + key.len());
+        res.extend_from_slice(&(root_hash.len() as u32).to_be_bytes());
+        res.extend_from_slice(root_hash);
+        res.extend_from_slice(key);
+        res
+    }
+
+    // <last_accessed_at: u64 BE (unix millis)><key>
+    fn gen_last_accessed_index_key(last_accessed_at: &DateTime<Utc>, key: &[u8]) -> Vec<u8> {
+        let mut res = Vec::with_capacity(8 + key.len());
+        res.extend_from_slice(&(last_accessed_at.timestamp_millis().max(0) as u64).to_be_bytes());
+        res.extend_from_slice(key);
+        res
+    }
+
+    // チャンクの書き込みは実行スレッドに回し、呼び出し側のスレッドで RocksDB を待たない
+    pub async fn put_value_stream<R>(&self, key: &[u8], reader: &mut R) -> anyhow::Result<u64>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.delete_value_stream(key).await?;
+
+        let mut count: u32 = 0;
+        let mut size: u64 = 0;
+        loop {
+            let mut buf = vec![0; CHUNK_SIZE];
+            let n = Self::read_chunk(reader, &mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            buf.truncate(n);
+            Self::put_chunk_async(&self.executor, &self.rocksdb, Self::gen_chunk_key(key, Some(count)), buf).await?;
+            count = count.checked_add(1).ok_or_else(|| anyhow::anyhow!("too many chunks"))?;
+            size += n as u64;
+        }
+
+        // ヘッダは全チャンクの書き込み後に置く (ヘッダが無ければ未完成とみなす)
+        let mut header = Vec::with_capacity(12);
+        header.extend_from_slice(&count.to_be_bytes());
+        header.extend_from_slice(&size.to_be_bytes());
+        Self::put_chunk_async(&self.executor, &self.rocksdb, Self::gen_chunk_key(key, None), header).await?;
+
+        Ok(size)
+    }
+
+    pub async fn get_value_stream(&self, key: &[u8]) -> anyhow::Result<Option<impl AsyncRead + Send + Unpin + 'static>> {
+        let Some(header) = Self::get_chunk_async(&self.executor, &self.rocksdb, Self::gen_chunk_key(key, None)).await? else {
+            return Ok(None);
+        };
+        let count = u32::from_be_bytes(header.get(..4).ok_or_else(|| anyhow::anyhow!("invalid header"))?.try_into()?);
+
+        // チャンクの読み込みも実行スレッドに回し、poll 中に RocksDB を待たない
+        let executor = self.executor.clone();
+        let rocksdb = self.rocksdb.clone();
+        let key = key.to_vec();
+        let chunks = stream::iter(0..count).then(move |index| {
+            let executor = executor.clone();
+            let rocksdb = rocksdb.clone();
+            let chunk_key = Self::gen_chunk_key(&key, Some(index));
+            async move {
+                match Self::get_chunk_async(&executor, &rocksdb, chunk_key).await {
+                    Ok(Some(value)) => Ok(value),
+                    Ok(None) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunk not found")),
+                    Err(e) => Err(io::Error::other(e)),
+                }
+            }
+        });
+
+        Ok(Some(StreamReader::new(Box::pin(chunks))))
+    }
+
+    pub async fn delete_value_stream(&self, key: &[u8]) -> anyhow::Result<()> {
+        let rocksdb = self.rocksdb.clone();
+        let from = Self::gen_chunk_key(key, None);
+        let to = Self::gen_chunk_key(key, Some(u32::MAX));
+        self.executor
+            .run(move || -> anyhow::Result<()> {
+                let cf = rocksdb
+                    .cf_handle(CHUNKS_CF_NAME)
+                    .ok_or_else(|| anyhow::anyhow!("column family not found: {}", CHUNKS_CF_NAME))?;
+                let mut batch = rocksdb::WriteBatch::default();
+                batch.delete_range_cf(&cf, &from, &to);
+                batch.delete_cf(&cf, &to);
+                rocksdb.write(batch)?;
+                Ok(())
+            })
+            .await??;
+        Ok(())
+    }
+
+    async fn put_chunk_async(
+        executor: &StorageExecutor,
+        rocksdb: &Arc<rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>>,
+        chunk_key: Vec<u8>,
+        value: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let rocksdb = rocksdb.clone();
+        executor
+            .run(move || -> anyhow::Result<()> {
+                let cf = rocksdb
+                    .cf_handle(CHUNKS_CF_NAME)
+                    .ok_or_else(|| anyhow::anyhow!("column family not found: {}", CHUNKS_CF_NAME))?;
+                rocksdb.put_cf(&cf, chunk_key, value)?;
+                Ok(())
+            })
+            .await??;
+        Ok(())
+    }
+
+    async fn get_chunk_async(
+        executor: &StorageExecutor,
+        rocksdb: &Arc<rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>>,
+        chunk_key: Vec<u8>,
+    ) -> anyhow::Result<Option<Bytes>> {
+        let rocksdb = rocksdb.clone();
+        let value = executor
+            .run(move || -> anyhow::Result<Option<Vec<u8>>> {
+                let cf = rocksdb
+                    .cf_handle(CHUNKS_CF_NAME)
+                    .ok_or_else(|| anyhow::anyhow!("column family not found: {}", CHUNKS_CF_NAME))?;
+                Ok(rocksdb.get_cf(&cf, chunk_key)?)
+            })
+            .await??;
+        Ok(value.map(Bytes::from))
+    }
+
+    fn cf(&self, name: &str) -> anyhow::Result<Arc<rocksdb::BoundColumnFamily<'_>>> {
+        self.rocksdb
+            .cf_handle(name)
+            .ok_or_else(|| anyhow::anyhow!("column family not found: {}", name))
+    }
+
+    // <key_len: u32 BE><key>[<index: u32 BE>]
+    fn gen_chunk_key(key: &[u8], index: Option<u32>) -> Vec<u8> {
+        let mut res = Vec::with_capacity(key.len() + 8);
+        res.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        res.extend_from_slice(key);
+        if let Some(index) = index {
+            res.extend_from_slice(&index.to_be_bytes());
+        }
+        res
+    }
+
+    async fn read_chunk<R>(reader: &mut R, buf: &mut [u8]) -> anyhow::Result<usize>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let n = reader.read(&mut buf[pos..]).await?;
+            if n == 0 {
+                break;
+            }
+            pos += n;
+        }
+        Ok(pos)
+    }
+
+    pub fn keys(&self, prefix: Option<&[u8]>) -> impl Stream<Item = anyhow::Result<Box<[u8]>>> + Send + Unpin {
+        self.scan(prefix, false).map(|n| n.map(|(key, _)| key))
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> impl Stream<Item = anyhow::Result<(Box<[u8]>, Bytes)>> + Send + Unpin {
+        self.scan(Some(prefix), true)
+            .map(|n| n.map(|(key, value)| (key, value.unwrap_or_default())))
+    }
+
+    pub fn delete_prefix(&self, prefix: &[u8]) -> anyhow::Result<()> {
+        let _guard = self.meta_lock.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+
+        // メタデータと索引も値と同じバッチで消し、参照の残骸を残さない
+        let meta_keys = {
+            let cf = self.cf(METAS_CF_NAME)?;
+            let mut iter = self.rocksdb.raw_iterator_cf(&cf);
+            iter.seek(prefix);
+            let mut res: Vec<Box<[u8]>> = Vec::new();
+            while let Some(key) = iter.key() {
+                if !key.starts_with(prefix) {
+                    break;
+                }
+                res.push(Box::from(key));
+                iter.next();
+            }
+            iter.status()?;
+            res
+        };
+        for key in meta_keys.iter() {
+            self.delete_meta_in_batch(&mut batch, key)?;
+        }
+
+        if let Some(upper_bound) = Self::gen_prefix_upper_bound(prefix) {
+            batch.delete_range(prefix, upper_bound.as_slice());
+        } else {
+            // 上限キーを作れない (0xFF のみの) prefix は末尾まで個別に削除する
+            let mut iter = self.rocksdb.raw_iterator();
+            iter.seek(prefix);
+            while let Some(key) = iter.key() {
+                batch.delete(key);
+                iter.next();
+            }
+            iter.status()?;
+        }
+        self.rocksdb.write(batch)?;
+        self.value_cache.remove_prefix(prefix);
+        Ok(())
+    }
+
+    pub async fn shrink<F, Fut, P>(&self, mut exclude: F, mut on_progress: P) -> anyhow::Result<ShrinkProgress>
+    where
+        F: FnMut(&[u8]) -> Fut,
+        Fut: Future<Output = anyhow::Result<bool>>,
+        P: FnMut(&ShrinkProgress),
+    {
+        let mut progress = ShrinkProgress::default();
+        let mut pending_keys: Vec<Box<[u8]>> = Vec::with_capacity(SHRINK_BATCH_SIZE);
+
+        let mut keys = self.keys(None);
+        while let Some(key) = keys.next().await {
+            let key = key?;
+            progress.scanned_count += 1;
+
+            if !exclude(&key).await? {
+                pending_keys.push(key);
+                progress.deleted_count += 1;
+            }
+
+            if pending_keys.len() >= SHRINK_BATCH_SIZE {
+                self.delete_batch(&pending_keys)?;
+                pending_keys.clear();
+                on_progress(&progress);
+
+                // 巨大なキー空間でも他の処理を妨げないよう、バッチ毎に実行権を譲る
+                tokio::task::yield_now().await;
+            }
+        }
+
+        if !pending_keys.is_empty() {
+            self.delete_batch(&pending_keys)?;
+        }
+        on_progress(&progress);
+
+        Ok(progress)
+    }
+
+    fn delete_batch(&self, keys: &[Box<[u8]>]) -> anyhow::Result<()> {
+        let _guard = self.meta_lock.lock();
+        let mut batch = rocksdb::WriteBatch::default();
+        for key in keys {
+            batch.delete(key);
+            self.delete_meta_in_batch(&mut batch, key)?;
+        }
+        self.rocksdb.write(batch)?;
+        for key in keys {
+            self.value_cache.remove(key);
+        }
+        Ok(())
+    }
+
+    // 一度に読む件数を区切って実行スレッドを返し、受信側が止まっても他の処理を塞がない
+    fn scan(&self, prefix: Option<&[u8]>, with_value: bool) -> BoxStream<'static, anyhow::Result<(Box<[u8]>, Option<Bytes>)>> {
+        let rocksdb = self.rocksdb.clone();
+        let executor = self.executor.clone();
+        let prefix = prefix.map(|n| n.to_vec());
+        let start = prefix.clone().unwrap_or_default();
+
+        stream::unfold(Some(start), move |cursor| {
+            let rocksdb = rocksdb.clone();
+            let executor = executor.clone();
+            let prefix = prefix.clone();
+            async move {
+                let cursor = cursor?;
+                let res = executor
+                    .run(move || Self::scan_batch(&rocksdb, prefix.as_deref(), &cursor, with_value))
+                    .await
+                    .and_then(|n| n);
+                match res {
+                    Ok((items, next)) => Some((items.into_iter().map(Ok).collect::<Vec<_>>(), next)),
+                    Err(e) => Some((vec![Err(e)], None)),
+                }
+            }
+        })
+        .flat_map(stream::iter)
+        .boxed()
+    }
+
+    // cursor 以降を最大 SCAN_BATCH_SIZE 件読み、続きがあれば次の開始キーを返す
+    #[allow(clippy::type_complexity)]
+    fn scan_batch(
+        rocksdb: &rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>,
+        prefix: Option<&[u8]>,
+        cursor: &[u8],
+        with_value: bool,
+    ) -> anyhow::Result<(Vec<(Box<[u8]>, Option<Bytes>)>, Option<Vec<u8>>)> {
+        let mut iter = rocksdb.raw_iterator();
+        iter.seek(cursor);
+
+        let mut items = Vec::with_capacity(SCAN_BATCH_SIZE);
+        while let Some(key) = iter.key() {
+            if let Some(prefix) = prefix {
+                if !key.starts_with(prefix) {
+                    break;
+                }
+            }
+            if items.len() >= SCAN_BATCH_SIZE {
+                return Ok((items, Some(key.to_vec())));
+            }
+
+            let value = if with_value { iter.value().map(Bytes::copy_from_slice) } else { None };
+            items.push((Box::from(key), value));
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok((items, None))
+    }
+
+    fn gen_prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+        let mut res = prefix.to_vec();
+        while let Some(last) = res.pop() {
+            if last != u8::MAX {
+                res.push(last + 1);
+                return Some(res);
+            }
+        }
+        None
     }
 
     pub fn flush(&self) -> anyhow::Result<()> {
@@ -47,6 +723,59 @@ impl BlobStorage {
         Ok(())
     }
 
+    pub fn backup_to<P: AsRef<Path>>(&self, dir_path: P) -> anyhow::Result<()> {
+        let checkpoint = rocksdb::checkpoint::Checkpoint::new(self.rocksdb.as_ref())?;
+        checkpoint.create_checkpoint(dir_path)?;
+        Ok(())
+    }
+
+    pub fn restore_from<P: AsRef<Path>, Q: AsRef<Path>>(backup_dir_path: P, path: Q) -> anyhow::Result<()> {
+        let backup_dir_path = backup_dir_path.as_ref();
+        let path = path.as_ref();
+
+        if !backup_dir_path.join("CURRENT").exists() {
+            anyhow::bail!("invalid checkpoint: {}", backup_dir_path.display());
+        }
+
+        // 隣のディレクトリに写して開けることを確かめてから置き換え、途中で失敗しても既存のものを壊さない
+        let restoring_path = gen_sibling_path(path, "restoring");
+        if restoring_path.exists() {
+            std::fs::remove_dir_all(&restoring_path)?;
+        }
+        let res = copy_dir(backup_dir_path, &restoring_path).and_then(|_| Self::verify(&restoring_path));
+        if let Err(e) = res {
+            let _ = std::fs::remove_dir_all(&restoring_path);
+            return Err(e);
+        }
+
+        let old_path = gen_sibling_path(path, "old");
+        if old_path.exists() {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+        if path.exists() {
+            std::fs::rename(path, &old_path)?;
+        }
+        if let Err(e) = std::fs::rename(&restoring_path, path) {
+            if old_path.exists() {
+                let _ = std::fs::rename(&old_path, path);
+            }
+            return Err(e.into());
+        }
+        if old_path.exists() {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+
+        Ok(())
+    }
+
+    // 書き込まずに開き、全ての列ファミリーを読めることを確かめる
+    fn verify<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
+        let opts = rocksdb::Options::default();
+        let cfs = rocksdb::DB::list_cf(&opts, path.as_ref())?;
+        rocksdb::DBWithThreadMode::<rocksdb::MultiThreaded>::open_cf_for_read_only(&opts, path.as_ref(), cfs, false)?;
+        Ok(())
+    }
+
     pub fn destroy<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
         let opts = rocksdb::Options::default();
         rocksdb::DB::destroy(&opts, path)?;
@@ -54,40 +783,46 @@ impl BlobStorage {
     }
 }
 
-pub struct BlobStorageKeyIterator<'a> {
-    iter: rocksdb::DBRawIteratorWithThreadMode<'a, rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>>,
+fn gen_sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut s: OsString = path.as_os_str().to_owned();
+    s.push(".");
+    s.push(suffix);
+    PathBuf::from(s)
 }
 
-impl<'a> BlobStorageKeyIterator<'a> {
-    fn new(iter: rocksdb::DBRawIteratorWithThreadMode<'a, rocksdb::DBWithThreadMode<rocksdb::MultiThreaded>>) -> Self {
-        Self { iter }
-    }
-}
-
-impl Iterator for BlobStorageKeyIterator<'_> {
-    type Item = Box<[u8]>;
-
-    fn next(&mut self) -> Option<Box<[u8]>> {
-        let key = self.iter.key();
-        if let Some(key) = key {
-            let key: Box<[u8]> = Box::from(key);
-            self.iter.next();
-            Some(key)
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let to_path = to.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &to_path)?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), &to_path)?;
         } else {
-            None
+            warn!(path = %entry.path().display(), "skipped a checkpoint entry that is not a file or directory");
         }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::BlobStorage;
+    use std::sync::Arc;
 
-    #[test]
-    pub fn simple_test() {
+    use chrono::{DateTime, Duration, Utc};
+    use futures::{StreamExt as _, TryStreamExt as _};
+    use tokio::io::AsyncReadExt as _;
+    use tokio_util::bytes::Bytes;
+
+    use super::{BlobMeta, BlobStorage, BlobStorageOption, ShrinkProgress};
+
+    #[tokio::test]
+    pub async fn simple_test() {
         let dir = tempfile::tempdir().unwrap();
         let path = dir.path().as_os_str().to_str().unwrap();
-        let storage = BlobStorage::new(path).unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
 
         let key1: Vec<u8> = vec![0x00, 0x00];
         let key2: Vec<u8> = vec![0x00, 0x01];
@@ -99,9 +834,362 @@ mod tests {
         assert_ne!(storage.get(key1.as_ref()).unwrap().unwrap(), value2);
         assert!(storage.get(key2.as_ref()).unwrap().is_none());
         storage.flush().unwrap();
-        assert_eq!(storage.keys().unwrap().map(|n| n.to_vec()).collect::<Vec<_>>(), vec![key1.clone()]);
+        assert_eq!(
+            storage.keys(None).map_ok(|n| n.to_vec()).try_collect::<Vec<_>>().await.unwrap(),
+            vec![key1.clone()]
+        );
         assert!(storage.delete(key1.as_ref()).is_ok());
-        assert_eq!(storage.keys().unwrap().count(), 0);
+        assert_eq!(storage.keys(None).count().await, 0);
         assert!(storage.get(key1.as_ref()).unwrap().is_none());
     }
+
+    #[tokio::test]
+    pub async fn async_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = BlobStorage::new(dir.path(), BlobStorageOption::default()).unwrap();
+
+        storage.put_async(b"key", Bytes::from_static(b"value")).await.unwrap();
+        assert_eq!(storage.get_async(b"key").await.unwrap(), Some(Bytes::from_static(b"value")));
+        assert_eq!(storage.get_async(b"missing").await.unwrap(), None);
+        assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"value")));
+        assert_eq!(storage.executor_status().queued_count, 0);
+    }
+
+    #[test]
+    pub fn cache_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = BlobStorage::new(dir.path(), BlobStorageOption::default()).unwrap();
+
+        storage.put(b"key", b"value1").unwrap();
+        assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"value1")));
+        assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"value1")));
+        assert_eq!(storage.cache_status().hit_count, 1);
+
+        // 書き込みや削除の後に古い値を返さない
+        storage.put(b"key", b"value2").unwrap();
+        assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"value2")));
+        storage.delete(b"key").unwrap();
+        assert_eq!(storage.get(b"key").unwrap(), None);
+        assert_eq!(storage.cache_status().entry_count, 0);
+    }
+
+    #[tokio::test]
+    pub async fn multi_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        let key1: Vec<u8> = vec![0x00, 0x00];
+        let key2: Vec<u8> = vec![0x00, 0x01];
+        let key3: Vec<u8> = vec![0x00, 0x02];
+        let value1: Vec<u8> = vec![0x01, 0x00];
+        let value2: Vec<u8> = vec![0x01, 0x01];
+
+        storage
+            .multi_put(&[(key1.as_ref(), value1.as_ref()), (key2.as_ref(), value2.as_ref())])
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.multi_get(&[key1.as_ref(), key2.as_ref(), key3.as_ref()]).await.unwrap(),
+            vec![Some(Bytes::from(value1.clone())), Some(Bytes::from(value2.clone())), None]
+        );
+
+        // 読んだ値はキャッシュに入り、次からはキャッシュから返す
+        assert_eq!(storage.cache_status().entry_count, 2);
+        assert_eq!(
+            storage.multi_get(&[key1.as_ref(), key2.as_ref()]).await.unwrap(),
+            vec![Some(Bytes::from(value1)), Some(Bytes::from(value2))]
+        );
+        assert_eq!(storage.cache_status().hit_count, 2);
+    }
+
+    #[tokio::test]
+    pub async fn keys_prefix_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        for key in ["a/1", "a/2", "b/1"] {
+            storage.put(key.as_bytes(), &[0x00]).unwrap();
+        }
+
+        let keys = storage.keys(Some(b"a/")).map_ok(|n| n.to_vec()).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(keys, vec![b"a/1".to_vec(), b"a/2".to_vec()]);
+    }
+
+    #[tokio::test]
+    pub async fn prefix_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        for key in ["a/1", "a/2", "b/1"] {
+            storage.put(key.as_bytes(), key.as_bytes()).unwrap();
+        }
+        storage.put(&[0xFF, 0x00], &[0x00]).unwrap();
+
+        let items = storage
+            .scan_prefix(b"a/")
+            .map_ok(|(key, value)| (key.to_vec(), value.to_vec()))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(items, vec![(b"a/1".to_vec(), b"a/1".to_vec()), (b"a/2".to_vec(), b"a/2".to_vec())]);
+
+        storage.delete_prefix(b"a/").unwrap();
+        let keys = storage.keys(None).map_ok(|n| n.to_vec()).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(keys, vec![b"b/1".to_vec(), vec![0xFF, 0x00]]);
+
+        storage.delete_prefix(&[0xFF]).unwrap();
+        let keys = storage.keys(None).map_ok(|n| n.to_vec()).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(keys, vec![b"b/1".to_vec()]);
+    }
+
+    #[tokio::test]
+    pub async fn delete_prefix_meta_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        let now: DateTime<Utc> = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into();
+        for key in ["a/1", "a/2", "b/1"] {
+            storage.put(key.as_bytes(), key.as_bytes()).unwrap();
+            storage.add_ref(key.as_bytes(), b"root", now).unwrap();
+        }
+
+        storage.delete_prefix(b"a/").unwrap();
+
+        assert!(storage.get_meta(b"a/1").unwrap().is_none());
+        assert!(storage.get_meta(b"a/2").unwrap().is_none());
+        assert!(storage.get_meta(b"b/1").unwrap().is_some());
+        assert_eq!(storage.keys_by_root_hash(b"root").unwrap(), vec![Box::from(&b"b/1"[..])]);
+        assert_eq!(storage.least_recently_used_keys(10).unwrap(), vec![Box::from(&b"b/1"[..])]);
+    }
+
+    #[test]
+    pub fn backup_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("storage");
+        let backup_path = dir.path().join("backup");
+        let restore_path = dir.path().join("restore");
+
+        let key: Vec<u8> = vec![0x00, 0x00];
+        let value: Vec<u8> = vec![0x01, 0x00];
+
+        let storage = BlobStorage::new(&path, BlobStorageOption::default()).unwrap();
+        storage.put(key.as_ref(), value.as_ref()).unwrap();
+        storage.backup_to(&backup_path).unwrap();
+        storage.delete(key.as_ref()).unwrap();
+
+        BlobStorage::restore_from(&backup_path, &restore_path).unwrap();
+        {
+            let restored = BlobStorage::new(&restore_path, BlobStorageOption::default()).unwrap();
+            assert_eq!(restored.get(key.as_ref()).unwrap().unwrap(), value);
+        }
+        assert!(storage.get(key.as_ref()).unwrap().is_none());
+
+        // 壊れたチェックポイントからは復元せず、既存のものを残す
+        let broken_path = dir.path().join("broken");
+        std::fs::create_dir_all(&broken_path).unwrap();
+        std::fs::write(broken_path.join("CURRENT"), b"MANIFEST-999999\n").unwrap();
+        assert!(BlobStorage::restore_from(&broken_path, &restore_path).is_err());
+        assert!(!dir.path().join("restore.restoring").exists());
+        let restored = BlobStorage::new(&restore_path, BlobStorageOption::default()).unwrap();
+        assert_eq!(restored.get(key.as_ref()).unwrap().unwrap(), value);
+    }
+
+    #[tokio::test]
+    pub async fn stream_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        let key: Vec<u8> = vec![0x00, 0x00];
+        let value: Vec<u8> = (0..(1024 * 1024 * 3 + 123)).map(|n| (n % 251) as u8).collect();
+
+        assert!(storage.get_value_stream(key.as_ref()).await.unwrap().is_none());
+
+        let size = storage.put_value_stream(key.as_ref(), &mut value.as_slice()).await.unwrap();
+        assert_eq!(size, value.len() as u64);
+
+        let mut buf = Vec::new();
+        storage
+            .get_value_stream(key.as_ref())
+            .await
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, value);
+        assert_eq!(storage.keys(None).count().await, 0);
+
+        storage.delete_value_stream(key.as_ref()).await.unwrap();
+        assert!(storage.get_value_stream(key.as_ref()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    pub async fn shrink_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        for i in 0..3000u32 {
+            storage.put(format!("a/{:04}", i).as_bytes(), &[0x00]).unwrap();
+        }
+        storage.put(b"b/1", &[0x00]).unwrap();
+
+        let mut reports = Vec::new();
+        let progress = storage
+            .shrink(|key| std::future::ready(Ok(key.starts_with(b"b/"))), |n| reports.push(n.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            progress,
+            ShrinkProgress {
+                scanned_count: 3001,
+                deleted_count: 3000
+            }
+        );
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports.last(), Some(&progress));
+
+        let keys = storage.keys(None).map_ok(|n| n.to_vec()).try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(keys, vec![b"b/1".to_vec()]);
+    }
+
+    #[test]
+    pub fn meta_index_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        let t0: DateTime<Utc> = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into();
+        let t1 = t0 + Duration::seconds(1);
+        let t2 = t0 + Duration::seconds(2);
+
+        for (key, root_hash, last_accessed_at) in [("a/1", b"a", t1), ("a/2", b"a", t0), ("b/1", b"b", t2)] {
+            storage.put(key.as_bytes(), &[0x00]).unwrap();
+            let meta = BlobMeta {
+                root_hashes: vec![root_hash.to_vec()],
+                last_accessed_at,
+            };
+            storage.put_meta(key.as_bytes(), &meta).unwrap();
+        }
+
+        let to_vec = |keys: Vec<Box<[u8]>>| keys.into_iter().map(|n| n.to_vec()).collect::<Vec<_>>();
+
+        assert_eq!(to_vec(storage.keys_by_root_hash(b"a").unwrap()), vec![b"a/1".to_vec(), b"a/2".to_vec()]);
+        assert_eq!(
+            to_vec(storage.least_recently_used_keys(2).unwrap()),
+            vec![b"a/2".to_vec(), b"a/1".to_vec()]
+        );
+
+        storage.touch(b"a/2", t2 + Duration::seconds(1)).unwrap();
+        assert_eq!(
+            to_vec(storage.least_recently_used_keys(2).unwrap()),
+            vec![b"a/1".to_vec(), b"b/1".to_vec()]
+        );
+
+        storage.delete(b"a/1").unwrap();
+        assert!(storage.get_meta(b"a/1").unwrap().is_none());
+        assert_eq!(to_vec(storage.keys_by_root_hash(b"a").unwrap()), vec![b"a/2".to_vec()]);
+        assert_eq!(
+            to_vec(storage.least_recently_used_keys(3).unwrap()),
+            vec![b"b/1".to_vec(), b"a/2".to_vec()]
+        );
+    }
+
+    #[test]
+    pub fn ref_count_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = BlobStorage::new(path, BlobStorageOption::default()).unwrap();
+
+        let now: DateTime<Utc> = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into();
+        let key = b"C/block";
+
+        storage.put(key, &[0x00]).unwrap();
+        assert_eq!(storage.add_ref(key, b"root1", now).unwrap(), 1);
+        assert_eq!(storage.add_ref(key, b"root2", now).unwrap(), 2);
+        assert_eq!(storage.add_ref(key, b"root1", now).unwrap(), 2);
+
+        // 参照している全ての root_hash から引ける
+        assert_eq!(storage.keys_by_root_hash(b"root1").unwrap(), vec![Box::from(&key[..])]);
+        assert_eq!(storage.keys_by_root_hash(b"root2").unwrap(), vec![Box::from(&key[..])]);
+
+        assert_eq!(storage.remove_ref(key, b"root1").unwrap(), 1);
+        assert!(storage.get(key).unwrap().is_some());
+        assert!(storage.keys_by_root_hash(b"root1").unwrap().is_empty());
+        assert_eq!(storage.keys_by_root_hash(b"root2").unwrap().len(), 1);
+
+        assert_eq!(storage.remove_ref(key, b"root2").unwrap(), 0);
+        assert!(storage.get(key).unwrap().is_none());
+        assert!(storage.get_meta(key).unwrap().is_none());
+        assert!(storage.keys_by_root_hash(b"root2").unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn concurrent_ref_count_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().as_os_str().to_str().unwrap();
+        let storage = Arc::new(BlobStorage::new(path, BlobStorageOption::default()).unwrap());
+
+        let now: DateTime<Utc> = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into();
+        let key = b"C/block";
+        storage.put(key, &[0x00]).unwrap();
+        storage.add_ref(key, b"root", now).unwrap();
+
+        // touch と並行して参照を増やしても、更新が失われない
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for j in 0..50 {
+                        if i == 0 {
+                            storage.touch(key, now + Duration::seconds(j)).unwrap();
+                        } else {
+                            storage.add_ref(key, format!("root{}-{}", i, j).as_bytes(), now).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(storage.get_meta(key).unwrap().unwrap().ref_count(), 1 + 3 * 50);
+        assert_eq!(storage.least_recently_used_keys(10).unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn secondary_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("primary");
+        let primary = BlobStorage::new(&path, BlobStorageOption::default()).unwrap();
+        primary.put(b"a", b"1").unwrap();
+        primary.flush().unwrap();
+
+        let option = BlobStorageOption {
+            secondary_path: Some(dir.path().join("secondary")),
+            ..Default::default()
+        };
+        let secondary = BlobStorage::new(&path, option).unwrap();
+        assert!(secondary.is_secondary());
+        assert_eq!(secondary.get(b"a").unwrap().unwrap().as_ref(), b"1");
+
+        // 書き込み側の変更は取り込むまで見えない
+        primary.put(b"a", b"2").unwrap();
+        primary.put(b"b", b"3").unwrap();
+        primary.flush().unwrap();
+        assert!(secondary.get(b"b").unwrap().is_none());
+
+        secondary.catch_up().unwrap();
+        assert_eq!(secondary.get(b"a").unwrap().unwrap().as_ref(), b"2");
+        assert_eq!(secondary.get(b"b").unwrap().unwrap().as_ref(), b"3");
+
+        assert!(secondary.put(b"c", b"4").is_err());
+    }
 }
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap};
+
+use parking_lot::Mutex;
+use tokio_util::bytes::Bytes;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockCacheStatus {
+    pub entry_count: usize,
+    pub used_bytes: usize,
+    pub hit_count: u64,
+    pub miss_count: u64,
+}
+
+// 多数のピアへ同時にアップロードしているブロックを、毎回ディスクから読まないためのキャッシュ
+// 容量はバイト数で指定し、超えた分は最も古く参照されたものから捨てる
+pub struct BlockCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Vec<u8>, Entry>,
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    generation: u64,
+    status: BlockCacheStatus,
+}
+
+struct Entry {
+    value: Bytes,
+    tick: u64,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        let Some(entry) = inner.entries.get_mut(key) else {
+            inner.status.miss_count += 1;
+            return None;
+        };
+        let old_tick = entry.tick;
+        entry.tick = tick;
+        let value = entry.value.clone();
+
+        if let Some(key) = inner.order.remove(&old_tick) {
+            inner.order.insert(tick, key);
+        }
+        inner.status.hit_count += 1;
+        Some(value)
+    }
+
+    // 読み込みの前に取得しておき、insert に渡す
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    // 読み込み中に削除や上書きがあった場合は、古い値の可能性があるのでキャッシュしない
+    pub fn insert(&self, key: &[u8], value: Bytes, generation: u64) {
+        // 容量の 1/8 を超えるものは、他のブロックを大量に追い出すのでキャッシュしない
+        if value.len() > self.capacity / 8 {
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+        if inner.generation != generation {
+            return;
+        }
+        inner.remove(key);
+
+        while inner.status.used_bytes + value.len() > self.capacity {
+            let Some((_, key)) = inner.order.pop_first() else {
+                break;
+            };
+            inner.remove(&key);
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.status.used_bytes += value.len();
+        inner.status.entry_count += 1;
+        inner.order.insert(tick, key.to_vec());
+        inner.entries.insert(key.to_vec(), Entry { value, tick });
+    }
+
+    pub fn remove(&self, key: &[u8]) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        inner.remove(key);
+    }
+
+    pub fn remove_prefix(&self, prefix: &[u8]) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        let keys: Vec<Vec<u8>> = inner.entries.keys().filter(|n| n.starts_with(prefix)).cloned().collect();
+        for key in keys {
+            inner.remove(&key);
+        }
+    }
+
+    pub fn status(&self) -> BlockCacheStatus {
+        self.inner.lock().status.clone()
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, key: &[u8]) {
+        let Some(entry) = self.entries.remove(key) else {
+            return;
+        };
+        self.order.remove(&entry.tick);
+        self.status.used_bytes -= entry.value.len();
+        self.status.entry_count -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::bytes::Bytes;
+
+    use super::BlockCache;
+
+    #[test]
+    pub fn simple_test() {
+        let cache = BlockCache::new(80);
+
+        cache.insert(b"a", Bytes::from(vec![0; 10]), cache.generation());
+        cache.insert(b"b", Bytes::from(vec![1; 10]), cache.generation());
+        assert_eq!(cache.get(b"a"), Some(Bytes::from(vec![0; 10])));
+        assert_eq!(cache.get(b"c"), None);
+
+        // 容量を超えると、最も古く参照された b から捨てられる
+        for i in 0..7u8 {
+            cache.insert(&[b'x', i], Bytes::from(vec![i; 10]), cache.generation());
+        }
+        assert_eq!(cache.get(b"b"), None);
+        assert!(cache.get(b"a").is_some());
+
+        cache.remove(b"a");
+        assert_eq!(cache.get(b"a"), None);
+
+        cache.remove_prefix(b"x");
+        let status = cache.status();
+        assert_eq!(status.entry_count, 0);
+        assert_eq!(status.used_bytes, 0);
+        assert_eq!(status.hit_count, 2);
+        assert_eq!(status.miss_count, 3);
+
+        // 大きすぎるものはキャッシュしない
+        cache.insert(b"large", Bytes::from(vec![0; 11]), cache.generation());
+        assert_eq!(cache.get(b"large"), None);
+
+        // 読み込み中に削除されたものはキャッシュしない
+        let generation = cache.generation();
+        cache.remove(b"stale");
+        cache.insert(b"stale", Bytes::from(vec![0; 10]), generation);
+        assert_eq!(cache.get(b"stale"), None);
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::{
+    sync::{watch, Mutex as TokioMutex},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use omnius_core_base::{sleeper::Sleeper, terminable::Terminable};
+
+use crate::service::util::{shutdown_task, sleep_or_cancelled, FnHub, FnRegistrar, TASK_SHUTDOWN_GRACE_PERIOD};
+
+#[derive(Debug, Clone)]
+pub struct DiskSpaceWatchdogOption {
+    pub min_free_bytes: u64,
+    // プロセスの RSS がこれを超えたら書き込みを止める。None の場合は監視しない
+    pub max_rss_bytes: Option<u64>,
+    pub check_interval: Duration,
+}
+
+impl Default for DiskSpaceWatchdogOption {
+    fn default() -> Self {
+        Self {
+            min_free_bytes: 1024 * 1024 * 1024,
+            max_rss_bytes: None,
+            check_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiskSpaceStatus {
+    pub available_bytes: Option<u64>,
+    pub rss_bytes: Option<u64>,
+    pub low_disk: bool,
+    pub high_memory: bool,
+    pub paused: bool,
+    pub pause_count: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourcePressure {
+    LowDisk,
+    HighMemory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceEvent {
+    Detected(ResourcePressure),
+    Recovered(ResourcePressure),
+    Paused,
+    Resumed,
+}
+
+// ファイルの取り込みや他のノードから教わった情報の保存など、ディスクに書き込む処理が空き容量の回復を待つためのもの
+#[derive(Clone)]
+pub struct DiskSpaceGate {
+    receiver: watch::Receiver<bool>,
+}
+
+impl DiskSpaceGate {
+    pub fn is_paused(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub async fn wait_until_available(&mut self) -> anyhow::Result<()> {
+        self.receiver.wait_for(|paused| !*paused).await?;
+        Ok(())
+    }
+}
+
+// 状態ディレクトリのあるボリュームの空き容量とプロセスのメモリ使用量を監視し、
+// どちらかが閾値を越えている間は書き込みを止める
+#[derive(Clone)]
+pub struct TaskDiskSpaceWatchdog {
+    inner: Inner,
+    sleeper: Arc<dyn Sleeper + Send + Sync>,
+    join_handle: Arc<TokioMutex<Option<JoinHandle<()>>>>,
+    cancellation_token: CancellationToken,
+}
+
+impl TaskDiskSpaceWatchdog {
+    pub fn new<P: AsRef<Path>>(dir_path: P, sleeper: Arc<dyn Sleeper + Send + Sync>, option: DiskSpaceWatchdogOption) -> Self {
+        let (sender, _) = watch::channel(false);
+        let inner = Inner {
+            dir_path: dir_path.as_ref().to_path_buf(),
+            sender: Arc::new(sender),
+            status: Arc::new(Mutex::new(DiskSpaceStatus::default())),
+            event_fn: Arc::new(FnHub::new()),
+            option,
+        };
+        Self {
+            inner,
+            sleeper,
+            join_handle: Arc::new(TokioMutex::new(None)),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    pub async fn run(&self) {
+        let sleeper = self.sleeper.clone();
+        let inner = self.inner.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let join_handle = tokio::spawn(async move {
+            loop {
+                let res = inner.check();
+                if let Err(e) = res {
+                    warn!(error_message = e.to_string(), "disk space check failed");
+                }
+                if !sleep_or_cancelled(sleeper.as_ref(), inner.option.check_interval, &cancellation_token).await {
+                    return;
+                }
+            }
+        });
+        *self.join_handle.lock().await = Some(join_handle);
+    }
+
+    pub fn gate(&self) -> DiskSpaceGate {
+        DiskSpaceGate {
+            receiver: self.inner.sender.subscribe(),
+        }
+    }
+
+    pub fn status(&self) -> DiskSpaceStatus {
+        self.inner.status.lock().clone()
+    }
+
+    // 返された FnHandle を保持している間、状態が変わる度に呼ばれる
+    pub fn on_event(&self) -> FnRegistrar<(), ResourceEvent> {
+        self.inner.event_fn.registrar()
+    }
+}
+
+#[async_trait]
+impl Terminable for TaskDiskSpaceWatchdog {
+    type Error = anyhow::Error;
+    async fn terminate(&self) -> anyhow::Result<()> {
+        if let Some(join_handle) = self.join_handle.lock().await.take() {
+            shutdown_task(&self.cancellation_token, join_handle, TASK_SHUTDOWN_GRACE_PERIOD).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Inner {
+    dir_path: PathBuf,
+    sender: Arc<watch::Sender<bool>>,
+    status: Arc<Mutex<DiskSpaceStatus>>,
+    event_fn: Arc<FnHub<(), ResourceEvent>>,
+    option: DiskSpaceWatchdogOption,
+}
+
+impl Inner {
+    fn check(&self) -> anyhow::Result<()> {
+        let available_bytes = fs2::available_space(&self.dir_path)?;
+        let rss_bytes = if self.option.max_rss_bytes.is_some() { read_rss_bytes() } else { None };
+        self.update(available_bytes, rss_bytes);
+        Ok(())
+    }
+
+    fn update(&self, available_bytes: u64, rss_bytes: Option<u64>) {
+        let mut events: Vec<ResourceEvent> = Vec::new();
+
+        {
+            let mut status = self.status.lock();
+            status.available_bytes = Some(available_bytes);
+            status.rss_bytes = rss_bytes;
+
+            // 閾値付近で停止と再開を繰り返さないよう、再開は閾値から 1 割戻ってからにする
+            let min_free_bytes = self.option.min_free_bytes;
+            if !status.low_disk && available_bytes < min_free_bytes {
+                status.low_disk = true;
+                warn!(available_bytes, min_free_bytes, "disk space low");
+                events.push(ResourceEvent::Detected(ResourcePressure::LowDisk));
+            } else if status.low_disk && available_bytes >= min_free_bytes.saturating_add(min_free_bytes / 10) {
+                status.low_disk = false;
+                info!(available_bytes, "disk space recovered");
+                events.push(ResourceEvent::Recovered(ResourcePressure::LowDisk));
+            }
+
+            if let (Some(max_rss_bytes), Some(rss_bytes)) = (self.option.max_rss_bytes, rss_bytes) {
+                if !status.high_memory && rss_bytes > max_rss_bytes {
+                    status.high_memory = true;
+                    warn!(rss_bytes, max_rss_bytes, "memory usage high");
+                    events.push(ResourceEvent::Detected(ResourcePressure::HighMemory));
+                } else if status.high_memory && rss_bytes <= max_rss_bytes - max_rss_bytes / 10 {
+                    status.high_memory = false;
+                    info!(rss_bytes, "memory usage recovered");
+                    events.push(ResourceEvent::Recovered(ResourcePressure::HighMemory));
+                }
+            }
+
+            let paused = status.low_disk || status.high_memory;
+            if !status.paused && paused {
+                status.paused = true;
+                status.pause_count += 1;
+                warn!("pausing writes");
+                self.sender.send_replace(true);
+                events.push(ResourceEvent::Paused);
+            } else if status.paused && !paused {
+                status.paused = false;
+                info!("resuming writes");
+                self.sender.send_replace(false);
+                events.push(ResourceEvent::Resumed);
+            }
+        }
+
+        let executor = self.event_fn.executor();
+        for event in events {
+            executor.execute(&event);
+        }
+    }
+}
+
+// Linux 以外では取得できないため、メモリの監視は行わない
+fn read_rss_bytes() -> Option<u64> {
+    let text = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = text.lines().find(|n| n.starts_with("VmRSS:"))?;
+    let kib: u64 = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use parking_lot::Mutex;
+    use testresult::TestResult;
+    use tokio::sync::watch;
+
+    use crate::service::util::FnHub;
+
+    use super::{DiskSpaceGate, DiskSpaceStatus, DiskSpaceWatchdogOption, Inner, ResourceEvent, ResourcePressure};
+
+    #[tokio::test]
+    pub async fn pause_and_resume_test() -> TestResult {
+        let dir = tempfile::tempdir()?;
+        let (sender, _) = watch::channel(false);
+        let inner = Inner {
+            dir_path: dir.path().to_path_buf(),
+            sender: Arc::new(sender),
+            status: Arc::new(Mutex::new(DiskSpaceStatus::default())),
+            event_fn: Arc::new(FnHub::new()),
+            option: DiskSpaceWatchdogOption {
+                min_free_bytes: 1000,
+                max_rss_bytes: None,
+                check_interval: Duration::from_secs(1),
+            },
+        };
+        let mut gate = DiskSpaceGate {
+            receiver: inner.sender.subscribe(),
+        };
+
+        inner.check()?;
+        assert!(inner.status.lock().available_bytes.is_some());
+
+        inner.update(999, None);
+        assert!(gate.is_paused());
+
+        // 閾値を少し上回っただけでは再開しない
+        inner.update(1050, None);
+        assert!(gate.is_paused());
+
+        let waiter = tokio::spawn(async move { gate.wait_until_available().await });
+        inner.update(1100, None);
+        waiter.await??;
+
+        let status = inner.status.lock().clone();
+        assert!(!status.paused);
+        assert_eq!(status.pause_count, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    pub fn memory_test() -> TestResult {
+        let (sender, _) = watch::channel(false);
+        let inner = Inner {
+            dir_path: std::env::temp_dir(),
+            sender: Arc::new(sender),
+            status: Arc::new(Mutex::new(DiskSpaceStatus::default())),
+            event_fn: Arc::new(FnHub::new()),
+            option: DiskSpaceWatchdogOption {
+                min_free_bytes: 1000,
+                max_rss_bytes: Some(1000),
+                check_interval: Duration::from_secs(1),
+            },
+        };
+        let gate = DiskSpaceGate {
+            receiver: inner.sender.subscribe(),
+        };
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let _handle = {
+            let events = events.clone();
+            inner.event_fn.registrar().register(move |e: &ResourceEvent| events.lock().push(*e))
+        };
+
+        inner.update(2000, Some(1001));
+        assert!(gate.is_paused());
+
+        // ディスクも不足している間は、メモリが回復しても再開しない
+        inner.update(999, Some(1001));
+        inner.update(999, Some(900));
+        assert!(gate.is_paused());
+
+        inner.update(2000, Some(900));
+        assert!(!gate.is_paused());
+
+        assert_eq!(
+            *events.lock(),
+            vec![
+                ResourceEvent::Detected(ResourcePressure::HighMemory),
+                ResourceEvent::Paused,
+                ResourceEvent::Detected(ResourcePressure::LowDisk),
+                ResourceEvent::Recovered(ResourcePressure::HighMemory),
+                ResourceEvent::Recovered(ResourcePressure::LowDisk),
+                ResourceEvent::Resumed,
+            ]
+        );
+
+        Ok(())
+    }
+}
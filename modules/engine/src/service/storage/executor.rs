@@ -0,0 +1,149 @@
+use std::{
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Debug, Clone, Default)]
+pub struct StorageExecutorStatus {
+    pub queued_count: usize,
+    pub running_count: usize,
+    pub completed_count: u64,
+    pub max_wait: Duration,
+}
+
+// RocksDB の処理専用のスレッドプール
+// tokio の blocking pool を使うと、ファイル I/O やハッシュ計算と取り合いになるため分けている
+pub struct StorageExecutor {
+    sender: Mutex<Option<mpsc::Sender<(Instant, Job)>>>,
+    status: Arc<Mutex<StorageExecutorStatus>>,
+}
+
+impl StorageExecutor {
+    pub fn new(thread_count: usize) -> anyhow::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<(Instant, Job)>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let status = Arc::new(Mutex::new(StorageExecutorStatus::default()));
+
+        for i in 0..thread_count.max(1) {
+            let receiver = receiver.clone();
+            let status = status.clone();
+            std::thread::Builder::new().name(format!("axus-storage-{}", i)).spawn(move || loop {
+                let Ok((queued_at, job)) = receiver.lock().recv() else {
+                    return;
+                };
+
+                {
+                    let mut status = status.lock();
+                    status.queued_count -= 1;
+                    status.running_count += 1;
+                    status.max_wait = status.max_wait.max(queued_at.elapsed());
+                }
+
+                job();
+
+                let mut status = status.lock();
+                status.running_count -= 1;
+                status.completed_count += 1;
+            })?;
+        }
+
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            status,
+        })
+    }
+
+    pub fn spawn<F>(&self, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.lock();
+        let sender = sender.as_ref().ok_or_else(|| anyhow::anyhow!("storage executor is closed"))?;
+
+        self.status.lock().queued_count += 1;
+        if sender.send((Instant::now(), Box::new(f))).is_err() {
+            self.status.lock().queued_count -= 1;
+            anyhow::bail!("storage executor is closed");
+        }
+        Ok(())
+    }
+
+    pub async fn run<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.spawn(move || {
+            let _ = tx.send(f());
+        })?;
+        Ok(rx.await?)
+    }
+
+    pub fn status(&self) -> StorageExecutorStatus {
+        self.status.lock().clone()
+    }
+}
+
+impl Drop for StorageExecutor {
+    fn drop(&mut self) {
+        // 送信側を閉じると、キューに残った処理を終えてからスレッドが終了する
+        // 走査中の処理が呼び出し側を待っている可能性があるので、ここでは join しない
+        self.sender.lock().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use testresult::TestResult;
+
+    use super::StorageExecutor;
+
+    #[tokio::test]
+    pub async fn simple_test() -> TestResult {
+        let executor = Arc::new(StorageExecutor::new(2)?);
+
+        assert_eq!(executor.run(|| 1 + 1).await?, 2);
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let executor = executor.clone();
+                tokio::spawn(async move {
+                    executor
+                        .run(move || {
+                            std::thread::sleep(Duration::from_millis(10));
+                            i
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.await??, i);
+        }
+
+        // 結果を返した後に集計されるので、反映されるまで待つ
+        for _ in 0..100 {
+            if executor.status().completed_count == 9 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let status = executor.status();
+        assert_eq!(status.queued_count, 0);
+        assert_eq!(status.running_count, 0);
+        assert_eq!(status.completed_count, 9);
+        // 2 スレッドで 8 件を処理するので、後ろの処理は待たされる
+        assert!(status.max_wait >= Duration::from_millis(10));
+
+        Ok(())
+    }
+}
@@ -1,11 +1,17 @@
 mod collections;
+#[cfg(test)]
+mod fake_time;
 mod fn_hub;
 mod kadx;
 mod sqlite;
+mod task;
 mod uri;
 
 pub use collections::*;
+#[cfg(test)]
+pub use fake_time::*;
 pub use fn_hub::*;
 pub use kadx::*;
 pub use sqlite::*;
+pub use task::*;
 pub use uri::*;
@@ -64,6 +64,10 @@ where
             .extend(iter.into_iter().map(|(k, v)| (k, ValueEntry { value: v, created_time: now })));
     }
 
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|v| &v.value)
+    }
+
     pub fn contains_key(&self, key: &K) -> bool {
         self.map.contains_key(key)
     }
@@ -88,3 +92,33 @@ where
         self.map.iter().map(|(k, v)| (k, &v.value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration};
+
+    use crate::service::util::ManualClock;
+
+    use super::VolatileHashMap;
+
+    #[test]
+    pub fn expire_test() {
+        let clock = Arc::new(ManualClock::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let mut map = VolatileHashMap::new(Duration::seconds(60), clock.clone());
+
+        map.insert(1, "a");
+        clock.advance(Duration::seconds(30));
+        map.extend([(2, "b"), (3, "c")]);
+
+        clock.advance(Duration::seconds(30));
+        map.refresh();
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.len(), 2);
+
+        clock.advance(Duration::seconds(60));
+        map.refresh();
+        assert_eq!(map.len(), 0);
+    }
+}
@@ -76,3 +76,40 @@ where
         self.map.keys()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{DateTime, Duration};
+
+    use crate::service::util::ManualClock;
+
+    use super::VolatileHashSet;
+
+    #[test]
+    pub fn expire_test() {
+        let clock = Arc::new(ManualClock::new(DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into()));
+        let mut set = VolatileHashSet::new(Duration::seconds(60), clock.clone());
+
+        set.insert(1);
+        clock.advance(Duration::seconds(30));
+        set.insert(2);
+
+        clock.advance(Duration::seconds(30));
+        set.refresh();
+        assert!(!set.contains(&1));
+        assert!(set.contains(&2));
+
+        // 挿し直すと期限が延びる
+        set.insert(2);
+        clock.advance(Duration::seconds(59));
+        set.refresh();
+        assert!(set.contains(&2));
+
+        // 新しいものから残す
+        set.insert(3);
+        set.shrink(1);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![&3]);
+    }
+}
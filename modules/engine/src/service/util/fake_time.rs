@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+
+use omnius_core_base::{clock::Clock, sleeper::Sleeper};
+
+// テスト用の時計。advance した分だけ時刻が進む
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { now: Mutex::new(now) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
+
+impl Clock<Utc> for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock()
+    }
+}
+
+// 実際には待たずに、待つはずだった時間だけ時計を進める
+pub struct ManualSleeper {
+    clock: Arc<ManualClock>,
+}
+
+impl ManualSleeper {
+    pub fn new(clock: Arc<ManualClock>) -> Self {
+        Self { clock }
+    }
+}
+
+#[async_trait]
+impl Sleeper for ManualSleeper {
+    async fn sleep(&self, duration: std::time::Duration) {
+        self.clock.advance(Duration::from_std(duration).unwrap());
+        tokio::task::yield_now().await;
+    }
+}
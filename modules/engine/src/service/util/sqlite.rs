@@ -1,4 +1,4 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::HashSet, path::Path, sync::Arc};
 
 use chrono::NaiveDateTime;
 use sqlx::SqlitePool;
@@ -88,6 +88,43 @@ INSERT INTO _migrations (name, queries) VALUES ($1, $2)
     }
 }
 
+pub struct SqliteBackup;
+
+impl SqliteBackup {
+    pub async fn backup<P: AsRef<Path>>(db: &SqlitePool, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref().to_str().ok_or(anyhow::anyhow!("Invalid path"))?;
+
+        // VACUUM INTO は実行中のDBから一貫したスナップショットを作成する
+        sqlx::query(
+            r#"
+VACUUM INTO $1
+"#,
+        )
+        .bind(path)
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    // 開いていない DB ファイルを、WAL に残っている内容も含めて 1 つのファイルに写す
+    pub async fn backup_file<P: AsRef<Path>, Q: AsRef<Path>>(path: P, backup_path: Q) -> anyhow::Result<()> {
+        let path = path.as_ref().to_str().ok_or(anyhow::anyhow!("Invalid path"))?;
+        let url = format!("sqlite:{}", path);
+
+        let db = SqlitePool::connect(&url).await?;
+        let res = Self::backup(&db, backup_path).await;
+        db.close().await;
+
+        res
+    }
+
+    pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(backup_path: P, path: Q) -> anyhow::Result<()> {
+        std::fs::copy(backup_path, path)?;
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct MigrationRequest {
     pub name: String,
@@ -107,7 +144,7 @@ mod tests {
 
     use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
 
-    use super::SqliteMigrator;
+    use super::{SqliteBackup, SqliteMigrator};
 
     #[tokio::test]
     pub async fn success_test() {
@@ -143,6 +180,34 @@ CREATE TABLE test (
         migrator.migrate(requests).await.unwrap();
     }
 
+    #[tokio::test]
+    pub async fn backup_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().as_os_str().to_str().unwrap();
+
+        let path = Path::new(dir_path).join("sqlite.db");
+        let path = path.to_str().unwrap();
+        let url = format!("sqlite:{}", path);
+
+        if !Sqlite::database_exists(url.as_str()).await.unwrap_or(false) {
+            Sqlite::create_database(url.as_str()).await.unwrap();
+        }
+
+        let db = SqlitePool::connect(&url).await.unwrap();
+        sqlx::query("CREATE TABLE test (id INTEGER PRIMARY KEY)").execute(&db).await.unwrap();
+        sqlx::query("INSERT INTO test (id) VALUES (1)").execute(&db).await.unwrap();
+
+        let backup_path = Path::new(dir_path).join("backup.db");
+        SqliteBackup::backup(&db, &backup_path).await.unwrap();
+
+        let restore_path = Path::new(dir_path).join("restore.db");
+        SqliteBackup::restore(&backup_path, &restore_path).unwrap();
+
+        let restored = SqlitePool::connect(&format!("sqlite:{}", restore_path.to_str().unwrap())).await.unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM test").fetch_one(&restored).await.unwrap();
+        assert_eq!(count, 1);
+    }
+
     #[tokio::test]
     pub async fn error_test() {
         let dir = tempfile::tempdir().unwrap();
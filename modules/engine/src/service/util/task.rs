@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use omnius_core_base::sleeper::Sleeper;
+
+pub const TASK_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// 停止が要求された場合は false を返す。ループの先頭で呼び、安全な位置でのみ抜けるようにする
+pub async fn sleep_or_cancelled(sleeper: &(dyn Sleeper + Send + Sync), duration: Duration, cancellation_token: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = cancellation_token.cancelled() => false,
+        _ = sleeper.sleep(duration) => true,
+    }
+}
+
+// 停止を要求してタスクの終了を待つ。猶予期間を過ぎても終わらない場合に限り abort する
+pub async fn shutdown_task(cancellation_token: &CancellationToken, mut join_handle: JoinHandle<()>, grace_period: Duration) {
+    cancellation_token.cancel();
+
+    if tokio::time::timeout(grace_period, &mut join_handle).await.is_err() {
+        warn!(grace_period_secs = grace_period.as_secs(), "task did not stop in time, aborting");
+        join_handle.abort();
+        let _ = join_handle.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use chrono::DateTime;
+    use tokio_util::sync::CancellationToken;
+
+    use omnius_core_base::clock::Clock as _;
+
+    use crate::service::util::{ManualClock, ManualSleeper};
+
+    use super::{shutdown_task, sleep_or_cancelled};
+
+    #[tokio::test]
+    pub async fn shutdown_test() {
+        // 停止要求に応じるタスクは最後まで実行される
+        let token = CancellationToken::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let join_handle = {
+            let token = token.clone();
+            let finished = finished.clone();
+            tokio::spawn(async move {
+                token.cancelled().await;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                finished.store(true, Ordering::SeqCst);
+            })
+        };
+        shutdown_task(&token, join_handle, Duration::from_secs(5)).await;
+        assert!(finished.load(Ordering::SeqCst));
+
+        // 応じないタスクは猶予期間の後に abort される
+        let token = CancellationToken::new();
+        let join_handle = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+        let start = tokio::time::Instant::now();
+        shutdown_task(&token, join_handle, Duration::from_millis(50)).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    pub async fn sleep_or_cancelled_test() {
+        let start = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap().into();
+        let clock = Arc::new(ManualClock::new(start));
+        let sleeper = ManualSleeper::new(clock.clone());
+
+        // 実際には待たずに時計だけが進む
+        let token = CancellationToken::new();
+        assert!(sleep_or_cancelled(&sleeper, Duration::from_secs(3600), &token).await);
+        assert_eq!(clock.now() - start, chrono::Duration::hours(1));
+
+        token.cancel();
+        assert!(!sleep_or_cancelled(&sleeper, Duration::from_secs(3600), &token).await);
+    }
+}
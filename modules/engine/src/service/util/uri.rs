@@ -1,10 +1,10 @@
-use crate::model::NodeProfile;
+use crate::model::{AssetUri, FileManifest, NodeProfile};
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
 use crc::{Crc, CRC_32_ISCSI};
 use tokio_util::bytes::Bytes;
 
-use omnius_core_rocketpack::RocketMessage;
+use omnius_core_rocketpack::{RocketMessage, RocketMessageReader, RocketMessageWriter};
 
 const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
 
@@ -15,8 +15,31 @@ impl UriConverter {
         Self::encode("node", v)
     }
 
+    // flags を追加する前に書き出されたものは、古い形式として読む
     pub fn decode_node_profile(text: &str) -> anyhow::Result<NodeProfile> {
-        Self::decode("node", text)
+        Self::decode("node", text).or_else(|e| Self::decode::<NodeProfileV1>("node", text).map(|v| v.0).map_err(|_| e))
+    }
+
+    pub fn encode_asset_uri(v: &AssetUri) -> anyhow::Result<String> {
+        Self::encode("asset", v)
+    }
+
+    // 署名が付いている場合は、ここで検証まで行う
+    pub fn decode_asset_uri(text: &str) -> anyhow::Result<AssetUri> {
+        let v: AssetUri = Self::decode("asset", text.trim())?;
+        v.verify(None)?;
+        Ok(v)
+    }
+
+    pub fn encode_file_manifest(v: &FileManifest) -> anyhow::Result<String> {
+        Self::encode("manifest", v)
+    }
+
+    // 署名が付いている場合は、ここで検証まで行う
+    pub fn decode_file_manifest(text: &str) -> anyhow::Result<FileManifest> {
+        let v: FileManifest = Self::decode("manifest", text.trim())?;
+        v.verify(None)?;
+        Ok(v)
     }
 
     fn encode<T: RocketMessage>(typ: &str, v: &T) -> anyhow::Result<String> {
@@ -80,21 +103,94 @@ impl UriConverter {
     }
 }
 
+// flags を持たない形式の NodeProfile
+struct NodeProfileV1(NodeProfile);
+
+impl RocketMessage for NodeProfileV1 {
+    fn pack(writer: &mut RocketMessageWriter, value: &Self, depth: u32) -> anyhow::Result<()> {
+        NodeProfile::pack_v1(writer, &value.0, depth)
+    }
+
+    fn unpack(reader: &mut RocketMessageReader, depth: u32) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self(NodeProfile::unpack_v1(reader, depth)?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use omnius_core_omnikit::model::OmniAddr;
+    use omnius_core_omnikit::model::{OmniAddr, OmniHash, OmniHashAlgorithmType, OmniSignType, OmniSigner};
 
-    use crate::{model::NodeProfile, service::util::UriConverter};
+    use crate::{
+        model::{AssetKey, AssetUri, FileManifest, FileManifestLayer, NodeProfile},
+        service::util::UriConverter,
+    };
+
+    use super::NodeProfileV1;
 
     #[test]
     pub fn node_profile_test() {
         let v = NodeProfile {
             id: vec![1, 2, 3],
             addrs: ["a", "b", "c"].into_iter().map(OmniAddr::new).collect(),
+            reachable: true,
         };
         let s = UriConverter::encode_node_profile(&v).unwrap();
         println!("{}", s);
         let v2 = UriConverter::decode_node_profile(s.as_str()).unwrap();
         assert_eq!(v, v2);
+
+        // flags を追加する前に書き出されたもの
+        let s = UriConverter::encode("node", &NodeProfileV1(v.clone())).unwrap();
+        let v2 = UriConverter::decode_node_profile(s.as_str()).unwrap();
+        assert_eq!(v2.id, v.id);
+        assert_eq!(v2.addrs, v.addrs);
+        assert!(!v2.reachable);
+    }
+
+    #[test]
+    pub fn asset_uri_test() {
+        let asset_key = AssetKey {
+            typ: "file".to_string(),
+            hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+        };
+
+        let v = AssetUri {
+            asset_key: asset_key.clone(),
+            name: None,
+            cert: None,
+        };
+        let s = UriConverter::encode_asset_uri(&v).unwrap();
+        assert!(s.starts_with("axus:asset/"));
+        assert_eq!(UriConverter::decode_asset_uri(s.as_str()).unwrap(), v);
+
+        // 貼り付けた際の前後の空白は無視する
+        let signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author").unwrap();
+        let v = AssetUri::sign(asset_key, Some("test.txt".to_string()), &signer).unwrap();
+        let s = UriConverter::encode_asset_uri(&v).unwrap();
+        assert_eq!(UriConverter::decode_asset_uri(format!(" {}\n", s).as_str()).unwrap(), v);
+
+        assert!(UriConverter::decode_asset_uri(s.replace("axus:asset/", "axus:node/").as_str()).is_err());
+    }
+
+    #[test]
+    pub fn file_manifest_test() {
+        let signer = OmniSigner::new(OmniSignType::Ed25519_Sha3_256_Base64Url, "author").unwrap();
+        let v = FileManifest {
+            root_hash: OmniHash::compute_hash(OmniHashAlgorithmType::Sha3_256, b"test"),
+            file_name: "test.txt".to_string(),
+            block_size: 1024,
+            layers: vec![FileManifestLayer { depth: 0, block_count: 1 }],
+            property: None,
+            cert: None,
+        }
+        .sign(&signer)
+        .unwrap();
+
+        let s = UriConverter::encode_file_manifest(&v).unwrap();
+        assert!(s.starts_with("axus:manifest/"));
+        assert_eq!(UriConverter::decode_file_manifest(s.as_str()).unwrap(), v);
     }
 }